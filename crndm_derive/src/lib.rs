@@ -19,6 +19,7 @@ extern crate cbindgen;
 extern crate proc_macro_error;
 
 mod pclone;
+mod relocate;
 mod root;
 mod cbinding;
 
@@ -29,7 +30,13 @@ pub fn derive_pclone(input: TokenStream) -> TokenStream {
 }
 
 #[proc_macro_error]
-#[proc_macro_derive(Root, attributes(pools))]
+#[proc_macro_derive(Relocate, attributes(pools))]
+pub fn derive_relocate(input: TokenStream) -> TokenStream {
+    relocate::derive_relocate(input)
+}
+
+#[proc_macro_error]
+#[proc_macro_derive(Root, attributes(pools, root))]
 pub fn derive_root(input: TokenStream) -> TokenStream {
     root::derive_root(input)
 }
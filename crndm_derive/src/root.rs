@@ -35,6 +35,40 @@ pub fn derive_root(input: TokenStream) -> TokenStream {
         });
     }
 
+    // The field layout hash does not depend on the pool type
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let root_name = root_name(&input.attrs).unwrap_or_else(|| name.to_string());
+    let (layout, types) = layout_of(&root_name, &input.data);
+    // Type parameters are part of the name, unless they are pool types; the
+    // name is not stable if any of them is not
+    let params: Vec<Ident> = input.generics.type_params()
+        .map(|t| t.ident.clone())
+        .filter(|t| !pools.iter().any(|p| p.to_string() == t.to_string()))
+        .collect();
+    expanded.push(quote! {
+        #[automatically_derived]
+        #[allow(unused_qualifications)]
+        impl#impl_generics corundum::RootSchema for #name #ty_generics #where_clause {
+            fn schema_hash() -> u64 {
+                corundum::schema_hash(#layout, &[
+                    #(<#types as corundum::RootSchema>::schema_hash(),)*
+                ])
+            }
+
+            fn root_name() -> Option<String> {
+                let params: Option<std::vec::Vec<String>> = vec![
+                    #(<#params as corundum::RootSchema>::root_name(),)*
+                ].into_iter().collect();
+                let params = params?;
+                if params.is_empty() {
+                    Some(#root_name.to_string())
+                } else {
+                    Some(format!("{}<{}>", #root_name, params.join(", ")))
+                }
+            }
+        }
+    });
+
     let expanded = quote! { #(#expanded)* };
 
     // Hand the output tokens back to the compiler.
@@ -141,4 +175,114 @@ fn root_all_fields(ident: &Ident, data: &Data) -> TokenStream2 {
         }
         Data::Union(_) => panic!("Union types cannot derive RootObj"),
     }
-}
\ No newline at end of file
+}
+
+// Find the name given by `#[root(name = "...")]`
+fn root_name(attrs: &Vec<Attribute>) -> Option<String> {
+    for attr in attrs {
+        if !attr.path.is_ident("root") {
+            continue;
+        }
+        match attr.parse_meta() {
+            Ok(Meta::List(list)) => {
+                for item in list.nested {
+                    match item {
+                        NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                            ref path, lit: Lit::Str(ref s), ..
+                        })) if path.is_ident("name") => return Some(s.value()),
+                        _ => abort!(item.span(), "expected `name = \"...\"`"),
+                    }
+                }
+            }
+            _ => abort!(attr.span(), "expected `#[root(name = \"...\")]`"),
+        }
+    }
+    None
+}
+
+// Spell a type without the paths and the spacing, so that the same type is
+// described the same way wherever it is named from.
+fn type_name(ty: &Type) -> String {
+    fn args(args: &PathArguments) -> String {
+        match args {
+            PathArguments::AngleBracketed(a) => {
+                let list: Vec<String> = a.args.iter().filter_map(|arg| match arg {
+                    GenericArgument::Type(t) => Some(type_name(t)),
+                    GenericArgument::Lifetime(_) => None,
+                    other => Some(tokens(other)),
+                }).collect();
+                if list.is_empty() {
+                    String::new()
+                } else {
+                    format!("<{}>", list.join(","))
+                }
+            }
+            PathArguments::Parenthesized(p) => {
+                let inputs: Vec<String> = p.inputs.iter().map(type_name).collect();
+                match &p.output {
+                    ReturnType::Default => format!("({})", inputs.join(",")),
+                    ReturnType::Type(_, t) => format!("({})->{}", inputs.join(","), type_name(t)),
+                }
+            }
+            PathArguments::None => String::new(),
+        }
+    }
+    fn tokens<T: quote::ToTokens>(t: &T) -> String {
+        quote!(#t).to_string().split_whitespace().collect()
+    }
+
+    match ty {
+        Type::Path(p) if p.qself.is_none() => {
+            let last = p.path.segments.last().unwrap();
+            format!("{}{}", last.ident, args(&last.arguments))
+        }
+        Type::Reference(r) => format!("&{}{}",
+            if r.mutability.is_some() { "mut " } else { "" }, type_name(&r.elem)),
+        Type::Ptr(p) => format!("*{}{}",
+            if p.mutability.is_some() { "mut " } else { "const " }, type_name(&p.elem)),
+        Type::Array(a) => format!("[{};{}]", type_name(&a.elem), tokens(&a.len)),
+        Type::Slice(s) => format!("[{}]", type_name(&s.elem)),
+        Type::Tuple(t) => {
+            let elems: Vec<String> = t.elems.iter().map(type_name).collect();
+            format!("({})", elems.join(","))
+        }
+        Type::Paren(p) => type_name(&p.elem),
+        Type::Group(g) => type_name(&g.elem),
+        other => tokens(other),
+    }
+}
+
+// Describe the fields of a type as a string, and collect the field types.
+fn layout_of(name: &str, data: &Data) -> (String, Vec<Type>) {
+    fn fields_of(fields: &Fields, layout: &mut String, types: &mut Vec<Type>) {
+        for (i, f) in fields.iter().enumerate() {
+            let name = match &f.ident {
+                Some(name) => name.to_string(),
+                None => i.to_string(),
+            };
+            layout.push_str(&format!("{}:{};", name, type_name(&f.ty)));
+            types.push(f.ty.clone());
+        }
+    }
+
+    let mut layout = String::new();
+    let mut types = vec![];
+    match data {
+        Data::Struct(data) => {
+            layout.push_str(&format!("struct {}{{", name));
+            fields_of(&data.fields, &mut layout, &mut types);
+            layout.push('}');
+        }
+        Data::Enum(data) => {
+            layout.push_str(&format!("enum {}{{", name));
+            for v in &data.variants {
+                layout.push_str(&format!("{}(", v.ident));
+                fields_of(&v.fields, &mut layout, &mut types);
+                layout.push_str(");");
+            }
+            layout.push('}');
+        }
+        Data::Union(_) => panic!("Union types cannot derive RootObj"),
+    }
+    (layout, types)
+}
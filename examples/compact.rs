@@ -0,0 +1,28 @@
+use corundum::default::*;
+
+type P = Allocator;
+
+// The root type should match the one the pool image was created with
+type Root = PRefCell<PVec<PString>>;
+
+fn main() {
+    use std::env;
+    use std::vec::Vec as StdVec;
+
+    let args: StdVec<String> = env::args().collect();
+
+    if args.len() < 4 {
+        println!("usage: {} src-file dst-file dst-size", args[0]);
+        return;
+    }
+
+    let size: u64 = args[3].parse().expect("expected an integer");
+
+    let root = P::open::<Root>(&args[1], 0).unwrap();
+    println!("{}: {} item(s), {} byte(s) used", args[1], root.borrow().len(), P::used());
+    P::compact_to::<Root>(&args[2], size).unwrap();
+    drop(root);
+
+    let root = P::open::<Root>(&args[2], 0).unwrap();
+    println!("{}: {} item(s), {} byte(s) used", args[2], root.borrow().len(), P::used());
+}
@@ -0,0 +1,172 @@
+//! An adapter for using memory pools as [`std::alloc::Allocator`]
+
+use crate::alloc::{AccessGuard, MemPool};
+use crate::stm::{Journal, Log};
+use std::alloc::{AllocError, Allocator, Layout};
+use std::marker::PhantomData;
+use std::ptr::{self, NonNull};
+
+/// An adapter implementing [`Allocator`] on top of a memory pool type
+///
+/// It allows placing standard containers (e.g. `std::vec::Vec<T, A>`) in the
+/// address space of a memory pool. The containers themselves are volatile,
+/// but their buffers are allocated in the pool using [`MemPool::alloc`] and
+/// [`MemPool::dealloc`].
+///
+/// The adapter is journal-aware: if a transaction is running on the current
+/// thread, every allocation is logged so that it is reclaimed if the
+/// transaction aborts or a crash happens before it commits, and every
+/// deallocation is deferred to the commit time. Outside a transaction, the
+/// allocation and deallocation happen immediately and are only failure-atomic
+/// with respect to the allocator metadata, and they wait while the pool is
+/// quiesced (e.g. by a snapshot). In this case, the memory leaks if it is not
+/// deallocated before a crash.
+///
+/// The pool should be open as long as there is a live allocation.
+///
+/// # Examples
+///
+/// ```
+/// #![feature(allocator_api)]
+/// # use corundum::default::*;
+/// # type P = Allocator;
+/// let _p = P::open_no_root("foo.pool", O_CF).unwrap();
+///
+/// let mut v = Vec::new_in(P::allocator());
+/// v.push(1);
+/// v.push(2);
+/// assert!(P::valid(v.as_ptr()));
+/// ```
+///
+/// [`Allocator`]: std::alloc::Allocator
+/// [`MemPool::alloc`]: ./trait.MemPoolTraits.html#method.alloc
+/// [`MemPool::dealloc`]: ./trait.MemPoolTraits.html#method.dealloc
+pub struct PoolAllocator<A: MemPool> {
+    phantom: PhantomData<A>,
+}
+
+impl<A: MemPool> PoolAllocator<A> {
+    /// Creates a new adapter for pool type `A`
+    pub const fn new() -> Self {
+        Self { phantom: PhantomData }
+    }
+
+    fn journal() -> Option<&'static Journal<A>> {
+        if let Some((j, cnt)) = Journal::<A>::try_current() {
+            unsafe {
+                if *cnt != 0 {
+                    return Some(&*j);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<A: MemPool> Clone for PoolAllocator<A> {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl<A: MemPool> Copy for PoolAllocator<A> {}
+
+impl<A: MemPool> Default for PoolAllocator<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<A: MemPool> Allocator for PoolAllocator<A> {
+    /// Allocates a block of memory in the pool
+    ///
+    /// The pool does not guarantee alignments larger than the block size. If
+    /// the allocated block does not satisfy the requested alignment, it is
+    /// released and an [`AllocError`] is returned.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let size = layout.size();
+        if size == 0 {
+            let p = layout.align() as *mut u8;
+            return NonNull::new(ptr::slice_from_raw_parts_mut(p, 0)).ok_or(AllocError);
+        }
+        unsafe {
+            let p = if let Some(j) = Self::journal() {
+                let mut log = Log::drop_on_abort(u64::MAX, 1, j);
+                let (p, off, len, z) = A::pre_alloc(size);
+                if p.is_null() {
+                    return Err(AllocError);
+                }
+                A::drop_on_failure(off, len, z);
+                log.set(off, len, z);
+                A::perform(z);
+                p
+            } else {
+                let _access = AccessGuard::<A>::new();
+                let (p, _, _) = A::alloc(size);
+                if p.is_null() {
+                    return Err(AllocError);
+                }
+                p
+            };
+            if p as usize % layout.align() != 0 {
+                self.deallocate(NonNull::new_unchecked(p), layout);
+                return Err(AllocError);
+            }
+            NonNull::new(ptr::slice_from_raw_parts_mut(p, size)).ok_or(AllocError)
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let size = layout.size();
+        if size == 0 {
+            return;
+        }
+        if let Some(j) = Self::journal() {
+            Log::drop_on_commit(A::off_unchecked(ptr.as_ptr()), size, j);
+        } else {
+            let _access = AccessGuard::<A>::new();
+            A::dealloc(ptr.as_ptr(), size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::default::*;
+    use crate::alloc::heap::Heap;
+    use crate::open_flags::*;
+    type P = Allocator;
+
+    #[test]
+    fn std_vec_in_pool() {
+        let _pool = P::open_no_root("adapter.pool", O_CF).unwrap();
+        let used = P::used();
+
+        let mut v = Vec::new_in(P::allocator());
+        for i in 0..100 {
+            v.push(i);
+        }
+        assert!(P::valid(v.as_ptr()));
+        assert!(P::used() > used);
+        drop(v);
+        assert_eq!(P::used(), used);
+
+        // The allocation is reclaimed when the transaction aborts
+        P::transaction(|_| {}).unwrap();
+        let used = P::used();
+        let _ = P::transaction(|_| {
+            let mut v = Vec::new_in(P::allocator());
+            v.push(1);
+            std::mem::forget(v);
+            panic!("abort");
+        });
+        assert_eq!(P::used(), used);
+    }
+
+    #[test]
+    fn std_vec_in_heap() {
+        let mut v = Vec::new_in(Heap::allocator());
+        v.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(v.iter().sum::<i32>(), 6);
+    }
+}
@@ -77,6 +77,9 @@ pub struct BuddyAlg<A: MemPool> {
     /// The device size in bytes
     size: usize,
 
+    /// Number of allocated blocks
+    objects: u64,
+
     /// An axillary ring list for allocation and recovery
     aux: Ring<(u64, u64), 128>,
 
@@ -101,8 +104,8 @@ pub struct BuddyAlg<A: MemPool> {
     mutex: (libc::pthread_mutex_t, libc::pthread_mutexattr_t),
 
     #[cfg(any(feature = "no_pthread", windows))]
-    /// A mutex for atomic operations
-    mutex: u64,
+    /// A recursive spin lock for atomic operations as `(owner, depth)`
+    mutex: (u64, u64),
 
     // Marker
     phantom: PhantomData<A>,
@@ -123,7 +126,48 @@ pub fn get_idx(x: usize) -> usize {
     }
 }
 
+/// Clips the `(offset, length)` pairs in `records` to `[start, end)`, and
+/// returns them as sorted and coalesced `(start, end)` ranges. Records with
+/// zero length are ignored.
+pub(crate) fn free_ranges(records: &[(u64, u64)], start: u64, end: u64) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = records
+        .iter()
+        .filter(|(_, len)| *len != 0)
+        .map(|(off, len)| ((*off).max(start), (off + len).min(end)))
+        .filter(|(s, e)| s < e)
+        .collect();
+    ranges.sort_unstable();
+    let mut res: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (s, e) in ranges {
+        match res.last_mut() {
+            Some(last) if last.1 >= s => last.1 = last.1.max(e),
+            _ => res.push((s, e)),
+        }
+    }
+    res
+}
+
 impl<A: MemPool> BuddyAlg<A> {
+    /// Maps `len` bytes of the pool `file`, which may be more than its size to
+    /// reserve address space for growing it. A `read_only` pool is mapped
+    /// privately, so that no changes are written to the file.
+    pub fn map(
+        _path: &str,
+        file: &std::fs::File,
+        len: usize,
+        read_only: bool
+    ) -> crate::result::Result<memmap::MmapMut> {
+        let mut opts = memmap::MmapOptions::new();
+        opts.len(len);
+        unsafe {
+            if read_only {
+                Ok(opts.map_copy(file)?)
+            } else {
+                Ok(opts.map_mut(file)?)
+            }
+        }
+    }
+
     /// Pool Initialization with a given device size
     pub fn init(&mut self, base: u64, size: usize) {
         let mut idx = get_idx(size);
@@ -135,6 +179,7 @@ impl<A: MemPool> BuddyAlg<A> {
         self.available = self.size;
         self.buddies[idx] = base;
         self.last_idx = idx;
+        self.objects = 0;
         self.log64.clear();
         self.drop_log.clear();
         self.aux.clear();
@@ -146,7 +191,7 @@ impl<A: MemPool> BuddyAlg<A> {
         }
 
         #[cfg(any(feature = "no_pthread", windows))] {
-        self.mutex = 0; }
+        self.mutex = (0, 0); }
     }
 
     #[inline]
@@ -167,28 +212,39 @@ impl<A: MemPool> BuddyAlg<A> {
     }
 
     #[inline]
-    fn lock(&mut self) {
+    pub(crate) fn lock(&mut self) {
         unsafe { 
             // debug_assert!(self.aux.empty(), "locked before: aux is not empty");
 
             #[cfg(not(any(feature = "no_pthread", windows)))]
             libc::pthread_mutex_lock(&mut self.mutex.0); 
 
+            // The lock is recursive like the pthread mutex, because the
+            // higher-level algorithms (e.g. `SlabAlg`) call into the buddy
+            // allocator while they hold the lock
             #[cfg(any(feature = "no_pthread", windows))] {
-                let tid = std::thread::current().id().as_u64().get();
-                while std::intrinsics::atomic_cxchg_acqrel(&mut self.mutex, 0, tid).0 != tid {}
+                let tid = crate::sync::current_tid();
+                if std::intrinsics::atomic_load_acq(&self.mutex.0) != tid {
+                    while !std::intrinsics::atomic_cxchg_acqrel(&mut self.mutex.0, 0, tid).1 {}
+                }
+                self.mutex.1 += 1;
             }
         }
     }
 
     #[inline]
-    fn unlock(&mut self) {
+    pub(crate) fn unlock(&mut self) {
         unsafe { 
             #[cfg(not(any(feature = "no_pthread", windows)))]
             libc::pthread_mutex_unlock(&mut self.mutex.0); 
 
-            #[cfg(any(feature = "no_pthread", windows))]
-            std::intrinsics::atomic_store_rel(&mut self.mutex, 0);
+            #[cfg(any(feature = "no_pthread", windows))] {
+                debug_assert_ne!(self.mutex.1, 0, "unlocking an unlocked mutex");
+                self.mutex.1 -= 1;
+                if self.mutex.1 == 0 {
+                    std::intrinsics::atomic_store_rel(&mut self.mutex.0, 0);
+                }
+            }
         }
     }
 
@@ -210,6 +266,29 @@ impl<A: MemPool> BuddyAlg<A> {
         self.aux.push((off, data));
     }
 
+    #[inline]
+    /// Returns the value of the 64-bit word at offset `off` as it will be after
+    /// draining the auxiliary list of changes
+    pub(crate) fn pending(&self, off: u64) -> u64 {
+        let mut res = None;
+        self.aux.foreach(|(o, v)| if o == off { res = Some(v) });
+        self.log64.foreach(|(o, v)| if o == off { res = Some(v) });
+        res.unwrap_or_else(|| Self::buddy(off).next)
+    }
+
+    #[inline]
+    /// Flushes the auxiliary list of changes
+    pub(crate) fn sync_aux(&self) {
+        self.aux.sync_all();
+        self.log64.sync_all();
+    }
+
+    #[inline]
+    /// Indicates that the last failure-atomic section was not finished
+    pub(crate) fn is_operating(&self) -> bool {
+        self.aux_valid
+    }
+
     #[inline]
     /// Drain the auxiliary list of changes
     /// 
@@ -262,6 +341,7 @@ impl<A: MemPool> BuddyAlg<A> {
         self.aux.clear();
         self.log64.clear();
         self.drop_log.clear();
+        self.available_log = self.available;
         self.unlock();
     }
 
@@ -271,6 +351,11 @@ impl<A: MemPool> BuddyAlg<A> {
         off - A::start()
     }
 
+    #[inline]
+    fn off_of<T>(x: &T) -> u64 {
+        x as *const T as u64 - A::start()
+    }
+
     #[inline]
     unsafe fn find_free_memory(&mut self, idx: usize, split: bool) -> Option<u64> {
         if idx > self.last_idx {
@@ -333,6 +418,8 @@ impl<A: MemPool> BuddyAlg<A> {
                     debug_alloc::<A>(off, len, self.used(), self.used() + (1 << idx));
 
                     self.available_log = self.available - len;
+                    let objects = Self::off_of(&self.objects);
+                    self.aux_push(objects, self.pending(objects).wrapping_add(1));
 
                     self.aux.sync_all();
                     if perform {
@@ -350,6 +437,7 @@ impl<A: MemPool> BuddyAlg<A> {
                     off
                 }
                 None => {
+                    #[cfg(feature = "verbose")]
                     eprintln!(
                         "Cannot find memory slot of size {} (available: {})",
                         len,
@@ -377,8 +465,53 @@ impl<A: MemPool> BuddyAlg<A> {
 
         self.available_log = self.available;
         self.free_impl(off, len);
+        let objects = Self::off_of(&self.objects);
+        self.aux_push(objects, self.pending(objects).wrapping_sub(1));
+
+        self.aux.sync_all();
+        if perform {
+            self.perform();
+        }
+    }
+
+    #[inline]
+    /// Generates required changes to the metadata for adding a new memory
+    /// block at offset `off` with the size of `len` to the free lists, and
+    /// materialize them by calling [`drain_aux`](#methods.drain_aux) according
+    /// to the `perform` argument. `len` should be a power of two, and `off`
+    /// should be aligned to it. Unlike [`dealloc_impl`](#methods.dealloc_impl),
+    /// the new block is not merged with its neighbors, and the size of the
+    /// zone grows by `len`.
+    pub unsafe fn extend(&mut self, off: u64, len: usize, perform: bool) {
+        self.lock();
+        let idx = get_idx(len);
+        debug_assert_eq!(1 << idx, len, "block size should be a power of two");
+        debug_assert_eq!(off % len as u64, 0, "block should be aligned to its size");
+
+        let mut curr = self.buddies[idx];
+        let mut prev: Option<u64> = None;
+        while let Some(b) = off_to_option(curr) {
+            if b > off {
+                break;
+            }
+            prev = Some(b);
+            curr = Self::buddy(b).next;
+        }
+        if let Some(p) = prev {
+            self.aux_push(off, Self::buddy(p).next);
+            self.aux_push(p, off);
+        } else {
+            self.aux_push(off, self.buddies[idx]);
+            self.aux_push(Self::get_off(&self.buddies[idx]), off);
+        }
+        self.available_log = self.available + len;
+        self.log(Self::off_of(&self.size), (self.size + len) as u64);
+        if idx > self.last_idx {
+            self.log(Self::off_of(&self.last_idx), idx as u64);
+        }
 
         self.aux.sync_all();
+        self.log64.sync_all();
         if perform {
             self.perform();
         }
@@ -489,7 +622,7 @@ impl<A: MemPool> BuddyAlg<A> {
         }
 
         #[cfg(any(feature = "no_pthread", windows))] {
-        self.mutex = 0; }
+        self.mutex = (0, 0); }
 
 
         #[cfg(feature = "check_allocator_cyclic_links")]
@@ -611,6 +744,90 @@ impl<A: MemPool> BuddyAlg<A> {
         self.foot_print
     }
 
+    #[inline]
+    /// Returns the number of allocated blocks
+    pub fn objects(&self) -> usize {
+        self.objects as usize
+    }
+
+    /// Returns the statistics of the zone including the free-list histogram
+    pub fn stats(&mut self) -> ZoneStats {
+        self.lock();
+        let mut stats = ZoneStats::new(self.size, self.available, self.objects as usize);
+        for idx in 0..self.last_idx + 1 {
+            let mut curr = self.buddies[idx];
+            while let Some(b) = off_to_option(curr) {
+                stats.add_free_block(1 << idx);
+                curr = Self::buddy(b).next;
+            }
+        }
+        self.unlock();
+        stats
+    }
+
+    /// Appends the free blocks of the zone to `out` as `(offset, length)`
+    /// pairs, so that the free space can be handed over to a new set of zones
+    /// using [`import`](#method.import)
+    pub fn export(&mut self, out: &mut Vec<(u64, u64)>) {
+        self.lock();
+        for idx in 0..self.last_idx + 1 {
+            let mut curr = self.buddies[idx];
+            while let Some(b) = off_to_option(curr) {
+                out.push((b, 1 << idx));
+                curr = Self::buddy(b).next;
+            }
+        }
+        self.unlock();
+    }
+
+    /// Rebuilds the zone over the address range `[start, end)` from the free
+    /// ranges in `records`. The free ranges are split into blocks aligned to
+    /// their sizes, and the parts of the range which are not covered by
+    /// `records` are considered allocated. `objects` is the initial number of
+    /// allocated blocks.
+    ///
+    /// The changes are written and flushed directly. The caller is responsible
+    /// for keeping `records` in a durable place until the whole set of zones
+    /// is rebuilt, so that it can be redone after a crash.
+    pub unsafe fn import(&mut self, start: u64, end: u64, records: &[(u64, u64)], objects: usize) {
+        let mut tails = [u64::MAX; 64];
+        self.buddies = [u64::MAX; 64];
+        self.available = 0;
+        for (s, e) in free_ranges(records, start, end) {
+            let mut off = (s + 7) & !7;
+            let e = e & !7;
+            while off + 8 <= e {
+                let mut len = 1u64 << off.trailing_zeros().min(62);
+                while off + len > e {
+                    len >>= 1;
+                }
+                let idx = get_idx(len as usize);
+                let b = Self::buddy(off);
+                b.next = u64::MAX;
+                persist_obj(b, false);
+                if let Some(t) = off_to_option(tails[idx]) {
+                    let t = Self::buddy(t);
+                    t.next = off;
+                    persist_obj(t, false);
+                } else {
+                    self.buddies[idx] = off;
+                }
+                tails[idx] = off;
+                self.available += len as usize;
+                off += len;
+            }
+        }
+        self.size = (end - start) as usize;
+        self.last_idx = get_idx(self.size).min(62);
+        self.available_log = self.available;
+        self.objects = objects as u64;
+        self.aux.clear();
+        self.log64.clear();
+        self.drop_log.clear();
+        self.aux_valid = false;
+        persist_obj(self, true);
+    }
+
     pub fn check(&self, f: &str) {
         for idx in 3..self.last_idx + 1 {
             let mut curr = self.buddies[idx];
@@ -672,7 +889,11 @@ impl<A: MemPool> BuddyAlg<A> {
 /// Memory Zones
 /// 
 /// It manages memory zones to optimally dedicate a zone to each cpu for 
-/// scalability.
+/// scalability. An allocation which cannot be satisfied by the zone of the
+/// current cpu is stolen from the sibling zones. The number of zones can be
+/// changed later using [`MemPool::reconfigure_zones()`].
+///
+/// [`MemPool::reconfigure_zones()`]: ./trait.MemPoolTraits.html#method.reconfigure_zones
 pub struct Zones<T, A: MemPool> {
     count: usize,
     quota: usize,
@@ -692,6 +913,19 @@ impl<T, A: MemPool> Zones<T, A> {
         Self { count, quota, base: offset, phantom: PhantomData }
     }
 
+    /// Creates a new `Zones` object with the zone objects stored at offset
+    /// `base`, which is usually a block allocated from the pool when the
+    /// zones are reconfigured
+    pub fn with_base(count: usize, base: usize, quota: usize) -> Self {
+        Self { count, quota, base, phantom: PhantomData }
+    }
+
+    #[inline]
+    /// Returns the offset of the zone objects
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
     #[inline]
     /// Returns the size of zones
     pub fn quota(&self) -> usize {
@@ -721,11 +955,18 @@ impl<T, A: MemPool> Zones<T, A> {
         Self::read(off as u64)
     }
 
+    #[inline]
+    /// Returns the index of the zone containing the given offset. The space
+    /// added by growing the pool belongs to the last zone.
+    pub fn index_of(&self, off: u64) -> usize {
+        (off as usize / self.quota).min(self.count - 1)
+    }
+
     #[inline]
     /// Returns a mutable reference to the zone object associated with the
     /// given offset
     pub fn from_off(&self, off: u64) -> (&mut T, usize) {
-        let i = off as usize / self.quota;
+        let i = self.index_of(off);
         let off = self.base + i * mem::size_of::<T>();
         (Self::read(off as u64), i)
     }
@@ -747,7 +988,7 @@ impl<T, A: MemPool> IndexMut<usize> for Zones<T, A> {
 
 #[cfg(test)]
 mod test {
-    use crate::RootObj;
+    use crate::{Error, RootObj};
     use crate::default::*;
     use crate::open_flags::*;
     type P = Allocator;
@@ -827,6 +1068,481 @@ mod test {
 
         println!("{} -> {}", u, P::used());
     }
+
+    #[test]
+    fn grow_test() {
+        let _pool = P::open_no_root("grow.pool", O_CF).unwrap();
+        let size = P::size();
+        let avail = P::available();
+
+        P::grow(size * 2).unwrap();
+        assert!(P::size() > size);
+        assert!(P::available() > avail);
+        assert!(matches!(
+            P::grow(crate::alloc::POOL_RESERVED_SIZE as usize + 1),
+            Err(Error::ReservationExceeded { .. })
+        ));
+
+        P::set_auto_grow(1024 * 1024);
+        let size = P::size();
+        P::transaction(|j| {
+            let _v = PVec::<u8>::with_capacity(size, j);
+        }).unwrap();
+        assert!(P::size() > size);
+    }
+
+    #[test]
+    fn defrag_test() {
+        type Root = PRefCell<PVec<Pbox<u64>>>;
+        let root = P::open::<Root>("defrag.pool", O_CF).unwrap();
+        P::transaction(|j| {
+            let mut v = root.borrow_mut(j);
+            for i in 0..64 {
+                v.push(Pbox::new(i, j), j);
+            }
+        }).unwrap();
+
+        // Leave holes at lower addresses
+        P::transaction(|j| {
+            let mut v = root.borrow_mut(j);
+            for _ in 0..32 {
+                v.remove(0);
+            }
+        }).unwrap();
+
+        let mut moved = 0;
+        loop {
+            let n = unsafe { P::defrag::<Root>(8) }.unwrap();
+            if n == 0 {
+                break;
+            }
+            moved += n;
+        }
+        assert!(moved > 0);
+        assert!(P::verify());
+        for (i, b) in root.borrow().iter().enumerate() {
+            assert_eq!(**b, i as u64 + 32);
+        }
+    }
+
+    #[test]
+    fn defrag_background() {
+        type Root = PMutex<PVec<Pbox<PCell<u64>>>>;
+        let root = P::open::<Root>("defrag_bg.pool", O_CF).unwrap();
+        P::transaction(|j| {
+            let mut v = root.lock(j);
+            for i in 0..64 {
+                v.push(Pbox::new(PCell::new(i), j), j);
+            }
+        }).unwrap();
+        P::transaction(|j| {
+            let mut v = root.lock(j);
+            for _ in 0..32 {
+                v.remove(0);
+            }
+        }).unwrap();
+
+        // The updates of the transactions survive the concurrent moves
+        let defrag = std::thread::spawn(|| {
+            let mut moved = 0;
+            loop {
+                let n = unsafe { P::defrag::<Root>(1) }.unwrap();
+                if n == 0 {
+                    break;
+                }
+                moved += n;
+            }
+            moved
+        });
+        for _ in 0..100 {
+            P::transaction(|j| {
+                for c in root.lock(j).iter() {
+                    c.set(c.get() + 1, j);
+                }
+            }).unwrap();
+        }
+        assert!(defrag.join().unwrap() > 0);
+        assert!(P::verify());
+        P::transaction(|j| {
+            for (i, c) in root.lock(j).iter().enumerate() {
+                assert_eq!(c.get(), i as u64 + 132);
+            }
+        }).unwrap();
+    }
+
+    #[test]
+    fn stats_test() {
+        let _pool = P::open_no_root("stats.pool", O_CF).unwrap();
+        let stats = P::stats();
+        assert_eq!(stats.used, P::used());
+        assert_eq!(stats.available, P::available());
+        assert!(stats.largest_free <= stats.available);
+        assert_eq!(stats.free_blocks.iter().enumerate()
+            .map(|(k, n)| n << k).sum::<usize>(), stats.available);
+
+        P::transaction(|_| {}).unwrap();
+        let objects = P::stats().objects;
+        P::transaction(|j| {
+            let _a = Pbox::new(1, j);
+            let _b = Pbox::new(2, j);
+            let stats = P::stats();
+            assert!(stats.journals >= 1);
+            assert!(stats.journal_pages >= 1);
+            assert!(stats.objects >= objects + 2);
+        }).unwrap();
+        assert_eq!(P::stats().objects, objects);
+    }
+
+    #[test]
+    fn compact_test() {
+        type Root = PRefCell<PVec<Pbox<u64>>>;
+        {
+            let root = P::open::<Root>("compact.pool", O_CF).unwrap();
+            P::transaction(|j| {
+                let mut v = root.borrow_mut(j);
+                for i in 0..100 {
+                    v.push(Pbox::new(i, j), j);
+                }
+            }).unwrap();
+            P::compact_to::<Root>("compact2.pool", 8 * 1024 * 1024).unwrap();
+            assert!(matches!(
+                P::compact_to::<Root>("./compact.pool", 8 * 1024 * 1024),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+
+        let root = P::open::<Root>("compact2.pool", 0).unwrap();
+        let v = root.borrow();
+        assert_eq!(v.len(), 100);
+        for i in 0..100 {
+            assert_eq!(*v[i], i as u64);
+        }
+    }
+
+    #[test]
+    fn try_alloc_test() {
+        type Root = PRefCell<PVec<u64>>;
+        {
+            let root = P::open::<Root>("try_alloc.pool", O_CF).unwrap();
+            P::transaction(|j| {
+                let mut v = root.borrow_mut(j);
+                v.push(1, j);
+                assert!(v.try_reserve(1 << 40, j).is_err());
+                assert!(Pbox::try_new(2u64, j).is_ok());
+                v.try_push(2, j).unwrap();
+            }).unwrap();
+        }
+
+        let root = P::open::<Root>("try_alloc.pool", 0).unwrap();
+        assert_eq!(root.borrow().as_slice(), [1, 2]);
+    }
+
+    #[test]
+    fn read_transaction_test() {
+        let root = P::open::<PMutex<u64>>("read_tx.pool", O_CF).unwrap();
+        P::transaction(|j| *root.lock(j) = 5).unwrap();
+
+        let v = P::read_transaction(|r| {
+            assert!(P::transaction(|_| {}).is_err());
+            *root.read(r) + *root.read(r)
+        }).unwrap();
+        assert_eq!(v, 10);
+
+        P::transaction(|_| {
+            assert!(matches!(P::read_transaction(|_| {}), Err(Error::TxRunning)));
+        }).unwrap();
+
+        // The lock is released after the read-only transaction
+        P::transaction(|j| *root.lock(j) += 1).unwrap();
+        assert_eq!(P::read_transaction(|r| *root.read(r)).unwrap(), 6);
+    }
+
+    #[test]
+    fn read_transaction_shared() {
+        let root = P::open::<PMutex<u64>>("read_tx_shared.pool", O_CF).unwrap();
+        P::transaction(|j| *root.lock(j) = 5).unwrap();
+        let addr = &*root as *const PMutex<u64> as usize;
+
+        P::read_transaction(|r| {
+            assert_eq!(*root.read(r), 5);
+            std::thread::spawn(move || {
+                let m = unsafe { &*(addr as *const PMutex<u64>) };
+
+                // Other readers can enter, but writers have to wait
+                assert_eq!(P::read_transaction(|r| *m.read(r)).unwrap(), 5);
+                P::transaction(|j| assert!(m.try_lock(j).is_err())).unwrap();
+            }).join().unwrap();
+        }).unwrap();
+
+        P::transaction(|j| *root.lock(j) += 1).unwrap();
+        assert_eq!(P::read_transaction(|r| *root.read(r)).unwrap(), 6);
+    }
+
+    #[test]
+    fn header_test() {
+        std::fs::write("header.pool", vec![0u8; 1024 * 1024]).unwrap();
+        let err = P::open_no_root("header.pool", 0).err().unwrap();
+        assert!(matches!(err, Error::InvalidPool(_)), "{}", err);
+
+        // The pool can be opened again after a failure
+        let _pool = P::open_no_root("header.pool", O_CF).unwrap();
+    }
+
+    #[test]
+    fn reconfigure_test() {
+        type Root = PRefCell<PVec<Pbox<u64>>>;
+        let count = {
+            let root = P::open::<Root>("reconf.pool", O_CF).unwrap();
+            P::transaction(|j| {
+                let mut v = root.borrow_mut(j);
+                for i in 0..100 {
+                    v.push(Pbox::new(i, j), j);
+                }
+            }).unwrap();
+            let count = P::zone_count() + 3;
+            P::transaction(|_| {
+                assert!(matches!(P::reconfigure_zones(count), Err(Error::TxRunning)));
+            }).unwrap();
+            P::reconfigure_zones(count).unwrap();
+            assert_eq!(P::zone_count(), count);
+            assert!(P::verify());
+            let objects = P::stats().objects;
+
+            // The objects can be allocated and freed in any of the new zones
+            P::transaction(|j| {
+                let mut v = root.borrow_mut(j);
+                for i in 100..200 {
+                    v.push(Pbox::new(i, j), j);
+                }
+                for _ in 0..50 {
+                    v.remove(0);
+                }
+            }).unwrap();
+            assert!(P::verify());
+            assert_eq!(P::stats().objects, objects + 50);
+            count
+        };
+
+        let root = P::open::<Root>("reconf.pool", 0).unwrap();
+        assert_eq!(P::zone_count(), count);
+        P::reconfigure_zones(1).unwrap();
+        assert_eq!(P::zone_count(), 1);
+        let v = root.borrow();
+        assert_eq!(v.len(), 150);
+        for (i, b) in v.iter().enumerate() {
+            assert_eq!(**b, i as u64 + 50);
+        }
+    }
+
+    #[test]
+    fn pool_instances() {
+        type Root = PRefCell<PVec<u64>>;
+        let shards: Vec<_> = (0..3).map(|i| {
+            P::open_instance::<Root>(&format!("instance{}.pool", i), O_CF).unwrap()
+        }).collect();
+        for (i, s) in shards.iter().enumerate() {
+            s.run(|root| P::transaction(|j| {
+                let mut v = root.borrow_mut(j);
+                for k in 0..=i as u64 {
+                    v.push(k, j);
+                }
+            })).unwrap();
+        }
+
+        // A failed transaction in one instance does not affect the others
+        assert!(shards[0].run(|root| P::transaction(|j| {
+            root.borrow_mut(j).push(100, j);
+            panic!("intentional");
+        })).is_err());
+        shards[1].run(|root| P::transaction(|j| root.borrow_mut(j).push(100, j))).unwrap();
+
+        let lens: Vec<_> = shards.iter().map(|s| s.run(|root| root.borrow().len())).collect();
+        assert_eq!(lens, vec![1, 3, 3]);
+        drop(shards);
+
+        let s = P::open_instance::<Root>("instance2.pool", 0).unwrap();
+        assert_eq!(s.run(|root| root.borrow().len()), 3);
+    }
+
+    crate::pool!(threaded);
+
+    #[test]
+    fn instance_threads() {
+        use crate::alloc::MemPoolTraits;
+        use std::sync::Arc;
+        type Q = threaded::Allocator;
+
+        let s = Arc::new(Q::open_instance::<threaded::PMutex<u64>>("threaded.pool", O_CF).unwrap());
+        let t = {
+            let s = s.clone();
+            std::thread::spawn(move || s.run(|root| Q::transaction(|j| *root.lock(j) = 7).unwrap()))
+        };
+        t.join().unwrap();
+        assert_eq!(s.run(|root| Q::transaction(|j| *root.lock(j)).unwrap()), 7);
+
+        // A thread which is not bound cannot reach the instance
+        assert!(std::thread::spawn(|| Q::start()).join().is_err());
+    }
+
+    #[test]
+    fn file_lock() {
+        let _p = P::open_instance::<u64>("locked.pool", O_CF).unwrap();
+        assert!(matches!(
+            P::open_instance::<u64>("locked.pool", 0),
+            Err(Error::PoolLocked(_))
+        ));
+        // The file is not formatted while it is in use
+        assert!(matches!(
+            P::open_instance::<u64>("locked.pool", O_CF),
+            Err(Error::PoolLocked(_))
+        ));
+    }
+
+    #[test]
+    fn read_only_open() {
+        type Root = PRefCell<PVec<u64>>;
+        {
+            let p = P::open_instance::<Root>("rdonly.pool", O_CF).unwrap();
+            p.run(|root| P::transaction(|j| root.borrow_mut(j).push(7, j))).unwrap();
+        }
+        let image = std::fs::read("rdonly.pool").unwrap();
+        {
+            let p = P::open_instance::<Root>("rdonly.pool", O_RDONLY).unwrap();
+            assert_eq!(p.run(|root| root.borrow()[0]), 7);
+            assert!(matches!(
+                p.run(|root| P::transaction(|j| root.borrow_mut(j).push(8, j))),
+                Err(Error::ReadOnly)
+            ));
+
+            // Read-only pools share the file, but a writer cannot open it
+            let _q = P::open_instance::<Root>("rdonly.pool", O_RDONLY).unwrap();
+            assert!(matches!(
+                P::open_instance::<Root>("rdonly.pool", 0),
+                Err(Error::PoolLocked(_))
+            ));
+        }
+        assert!(std::fs::read("rdonly.pool").unwrap() == image);
+    }
+
+    #[test]
+    fn snapshot_test() {
+        type Root = PRefCell<PVec<u64>>;
+        let p = P::open_instance::<Root>("snap-src.pool", O_CF).unwrap();
+        p.run(|root| P::transaction(|j| {
+            let mut v = root.borrow_mut(j);
+            for i in 0..100 {
+                v.push(i, j);
+            }
+        })).unwrap();
+        p.run(|_| P::snapshot("snap-dst.pool")).unwrap();
+        p.run(|root| P::transaction(|j| root.borrow_mut(j).push(100, j))).unwrap();
+        assert!(matches!(
+            p.run(|_| P::snapshot("snap-src.pool")),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            p.run(|_| P::transaction(|_| P::snapshot("snap-dst.pool"))),
+            Ok(Err(Error::TxRunning))
+        ));
+
+        let s = P::open_instance::<Root>("snap-dst.pool", 0).unwrap();
+        s.run(|root| {
+            let v = root.borrow();
+            assert_eq!(v.len(), 100);
+            assert_eq!(v[99], 99);
+        });
+        assert_eq!(p.run(|root| root.borrow().len()), 101);
+    }
+
+    #[test]
+    fn snapshot_while_busy() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let root = P::open::<PAtomicU64>("snap-busy.pool", O_CF).unwrap();
+        let addr = &*root as *const PAtomicU64 as usize;
+        let done = Arc::new(AtomicBool::new(false));
+        let t = {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let a = unsafe { &*(addr as *const PAtomicU64) };
+                let mut n = 0;
+                while !done.load(Ordering::Acquire) {
+                    a.fetch_add(1, Ordering::AcqRel);
+                    P::transaction(|j| { a.fetch_add_tx(1, j); }).unwrap();
+                    n += 2;
+                }
+                n
+            })
+        };
+
+        // The snapshots are not starved by the other thread
+        for _ in 0..10 {
+            P::snapshot("snap-busy-dst.pool").unwrap();
+        }
+        done.store(true, Ordering::Release);
+        let n = t.join().unwrap();
+        assert_eq!(root.load(Ordering::Acquire), n);
+    }
+
+    #[test]
+    #[cfg(feature = "replicate")]
+    fn replica_failover() {
+        use std::io::{Seek, SeekFrom, Write};
+        type Root = PRefCell<PVec<u64>>;
+        {
+            let root = P::open_with_replica::<Root>("primary.pool", "replica.pool", O_CF).unwrap();
+            P::transaction(|j| {
+                let mut v = root.borrow_mut(j);
+                for i in 0..100 {
+                    v.push(i, j);
+                }
+            }).unwrap();
+        }
+
+        // Damage the header of the primary image
+        let mut file = std::fs::OpenOptions::new().write(true).open("primary.pool").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&[0u8; 64]).unwrap();
+        file.sync_all().unwrap();
+        drop(file);
+
+        let _ = std::fs::remove_file("primary.pool.damaged");
+        let root = P::open_with_replica::<Root>("primary.pool", "replica.pool", 0).unwrap();
+        let v = root.borrow();
+        assert_eq!(v.len(), 100);
+        assert_eq!(v[99], 99);
+        assert!(std::path::Path::new("primary.pool.damaged").exists());
+    }
+
+    #[test]
+    #[cfg(feature = "replicate")]
+    fn replica_divergence() {
+        use std::io::{Seek, SeekFrom, Write};
+        type Root = PCell<u64>;
+        {
+            let root = P::open_with_replica::<Root>("diverged.pool", "diverged.replica", O_CF).unwrap();
+            P::transaction(|j| root.set(10, j)).unwrap();
+        }
+        {
+            // A cleanly closed replica matches the pool
+            let root = P::open_with_replica::<Root>("diverged.pool", "diverged.replica", 0).unwrap();
+            assert_eq!(root.get(), 10);
+        }
+
+        // Damage the body of the replica
+        let mut file = std::fs::OpenOptions::new().write(true).open("diverged.replica").unwrap();
+        let len = file.metadata().unwrap().len();
+        file.seek(SeekFrom::Start(len - 1)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        file.sync_all().unwrap();
+        drop(file);
+
+        match P::open_with_replica::<Root>("diverged.pool", "diverged.replica", 0) {
+            Err(Error::InvalidPool(_)) => {}
+            _ => panic!("the divergence is not reported"),
+        }
+    }
 }
 
 #[cfg(feature = "verbose")]
@@ -988,7 +1704,23 @@ macro_rules! __cfg_delete_history {
 /// }).unwrap();
 /// ```
 /// 
+/// A pool type can also open several pool files of the same schema at
+/// runtime via `Allocator::open_instance()`, which returns a `PoolInstance`
+/// handle. A thread operates on an instance inside `PoolInstance::run()`.
+/// With the "replicate" feature, `Allocator::open_with_replica()` mirrors
+/// every flush of the pool to a second file, which is used to restore the
+/// pool if its header is damaged.
+/// 
+/// The allocation algorithm is [`BuddyAlg`] by default. A different
+/// algorithm type can be given as the third argument, e.g.
+/// `pool!(my_pool, Allocator, SlabAlg)` for the segregated-fit allocator
+/// [`SlabAlg`], or `pool!(my_pool, Allocator, PmemAlg)` which is the same as
+/// using [`pmem_pool!()`].
+/// 
 /// [`Allocator`]: ./alloc/default/struct.Allocator.html
+/// [`BuddyAlg`]: ./struct.BuddyAlg.html
+/// [`SlabAlg`]: ./struct.SlabAlg.html
+/// [`pmem_pool!()`]: ./macro.pmem_pool.html
 /// [`corundum::boxed::Pbox`]: ./boxed/struct.Pbox.html
 /// [`corundum::prc::Prc`]: ./prc/struct.Prc.html
 /// [`corundum::sync::Parc`]: ./sync/struct.Parc.html
@@ -1001,6 +1733,9 @@ macro_rules! __cfg_delete_history {
 /// [`corundum::str::String`]: ./str/struct.String.html
 macro_rules! pool {
     ($mod:ident, $name:ident) => {
+        $crate::pool!($mod, $name, BuddyAlg);
+    };
+    ($mod:ident, $name:ident, $alg:ident) => {
         /// The default allocator module
         pub mod $mod {
             use memmap::*;
@@ -1010,13 +1745,15 @@ macro_rules! pool {
             use std::hash::{Hash, Hasher};
             use std::mem;
             use std::ops::Range;
-            use std::path::{Path, PathBuf};
-            use std::sync::atomic::{AtomicBool, Ordering};
-            use std::sync::{Arc, Mutex};
+            use std::path::Path;
+            use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+            use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
             use std::thread::ThreadId;
             use $crate::ll::*;
+            use $crate::stm::{Log, Notifier};
             use $crate::result::Result;
             use $crate::utils::read;
+            use $crate::Error;
             use $crate::*;
             pub use $crate::{
                 PSafe, 
@@ -1026,8 +1763,12 @@ macro_rules! pool {
                 AssertTxInSafe, 
                 VSafe, 
                 transaction, 
+                try_transaction,
+                transaction_with_retry,
+                read_transaction,
                 open_flags, 
                 PClone, 
+                Relocate,
                 Root,
                 RootObj,
                 ToPString,
@@ -1036,21 +1777,26 @@ macro_rules! pool {
                 MemPool
             };
     
-            static mut BUDDY_START: u64 = 0;
-            static mut BUDDY_VALID_START: u64 = 0;
-            static mut BUDDY_END: u64 = 0;
-    
             #[repr(C)]
             struct BuddyAllocInner {
+                header: PoolHeader,
                 magic_number: u64,
                 flags: u64,
                 gen: u32,
                 tx_gen: u32,
                 root_obj: u64,
                 root_type_id: u64,
+                root_schema: RootFingerprint,
                 journals: u64,
                 size: usize,
-                zone: Zones<BuddyAlg<$name>, $name>
+                zone: Zones<$alg<$name>, $name>,
+
+                // Zone reconfiguration
+                table_len: usize,
+                reconf_state: u64,
+                reconf: u64,
+                reconf_len: usize,
+                reconf_count: usize,
             }
     
             struct VData {
@@ -1058,15 +1804,38 @@ macro_rules! pool {
                 journals: HashMap<ThreadId, (u64, i32)>,
                 check_double_free: HashSet<u64>,
                 mmap: MmapMut,
+
+                /// Holds the advisory lock on the pool file
+                file: std::fs::File,
+
+                /// The path, the locked file, and the mapping of the replica
+                replica: Option<(String, std::fs::File, MmapMut)>,
+
+                /// The thread which quiesces the pool, if any
+                exclusive: Option<ThreadId>,
+
+                /// The number of non-transactional accesses of each thread
+                accesses: HashMap<ThreadId, usize>,
+            }
+    
+            struct GrowInfo {
+                file: std::fs::File,
+                reserved: usize,
+                step: usize,
+                replica: Option<std::fs::File>,
             }
     
             impl VData {
-                fn new(mmap: MmapMut, filename: &str) -> Self {
+                fn new(mmap: MmapMut, filename: &str, file: std::fs::File) -> Self {
                     Self {
                         filename: filename.to_string(),
                         journals: HashMap::new(),
                         check_double_free: HashSet::new(),
                         mmap,
+                        file,
+                        replica: None,
+                        exclusive: None,
+                        accesses: HashMap::new(),
                     }
                 }
             }
@@ -1081,25 +1850,35 @@ macro_rules! pool {
                     self.tx_gen = 0;
                     self.root_obj = u64::MAX;
                     self.root_type_id = 0;
+                    self.root_schema = RootFingerprint::default();
                     self.journals = u64::MAX;
                     self.size = size;
+                    self.table_len = 0;
+                    self.reconf_state = RECONF_IDLE;
+                    self.reconf = u64::MAX;
+                    self.reconf_len = 0;
+                    self.reconf_count = 0;
     
-                    type T = BuddyAlg<$name>;
+                    type T = $alg<$name>;
                     let cpus = if let Some(val) = std::env::var_os("CPUS") {
                         val.into_string().unwrap().parse::<usize>().unwrap()
                     } else {
                         num_cpus::get()
                     };
                     assert_ne!(cpus, 0);
-                    let quota = size / cpus;
-                    self.zone = Zones::new(cpus, mem::size_of::<Self>(), quota);
-                    for i in 0..cpus {
-                        self.zone[i].init((quota * i) as u64, quota);
+                    let count = cpus.min(size / ZONE_ALIGN).max(1);
+                    let quota = zone_quota(size, count);
+                    self.zone = Zones::new(count, mem::size_of::<Self>(), quota);
+                    for i in 0..count {
+                        let start = quota * i;
+                        let end = if i + 1 == count { size } else { start + quota };
+                        self.zone[i].init(start as u64, end - start);
                     }
+                    self.header = PoolHeader::new(count);
                     self.magic_number = u64::MAX;
                     unsafe {
                         self.zone[0].alloc_impl(
-                            mem::size_of::<Self>() + mem::size_of::<T>() * cpus,
+                            mem::size_of::<Self>() + mem::size_of::<T>() * count,
                             true,
                         );
                     }
@@ -1115,6 +1894,69 @@ macro_rules! pool {
                 fn has_root(&self) -> bool {
                     self.flags & FLAG_HAS_ROOT == FLAG_HAS_ROOT
                 }
+
+                /// Rebuilds the zones from the free ranges staged in the
+                /// reconfiguration block, and switches to the new zone table.
+                /// It only writes to the free space and to the block itself,
+                /// so it can be redone after a crash.
+                unsafe fn finish_reconfigure(&mut self) {
+                    let count = self.reconf_count;
+                    let stage = self.reconf + zone_table_len(count) as u64;
+                    let n = *$name::get_unchecked::<u64>(stage) as usize;
+                    let objects = *$name::get_unchecked::<u64>(stage + 8) as usize;
+                    let records = $name::deref_slice_unchecked::<(u64, u64)>(stage + 16, n);
+                    let quota = zone_quota(self.size, count);
+                    let zone = Zones::with_base(count, self.reconf as usize, quota);
+                    for i in 0..count {
+                        let start = quota * i;
+                        let end = if i + 1 == count { self.size } else { start + quota };
+                        zone.at(i).import(
+                            start as u64,
+                            end as u64,
+                            records,
+                            if i == 0 { objects } else { 0 }
+                        );
+                    }
+                    self.zone = zone;
+                    self.table_len = self.reconf_len;
+                    self.header.zone_count = count as u64;
+                    persist_obj(&self.zone, false);
+                    persist_obj(&self.table_len, false);
+                    persist_obj(&self.header, true);
+                    self.reconf_state = RECONF_IDLE;
+                    persist_obj(&self.reconf_state, true);
+                }
+
+                /// Releases the reconfiguration block if the free ranges were
+                /// not staged yet
+                unsafe fn cancel_reconfigure(&mut self) {
+                    // The block may leak if it crashes here, but it is never
+                    // freed twice
+                    self.reconf_state = RECONF_IDLE;
+                    persist_obj(&self.reconf_state, true);
+                    let (zone, _) = self.zone.from_off(self.reconf);
+                    zone.dealloc_impl(self.reconf, self.reconf_len, true);
+                }
+            }
+
+            /// The size of the zones is a multiple of this value, so that the
+            /// runs of the slab allocator do not cross zone boundaries
+            const ZONE_ALIGN: usize = 1 << 16;
+
+            /// Returns the size of every zone but the last one, which also
+            /// takes the remainder of the pool
+            #[inline]
+            fn zone_quota(size: usize, count: usize) -> usize {
+                if count == 1 {
+                    size
+                } else {
+                    (size / count) & !(ZONE_ALIGN - 1)
+                }
+            }
+
+            #[inline]
+            fn zone_table_len(count: usize) -> usize {
+                (mem::size_of::<$alg<$name>>() * count + 7) & !7
             }
     
             /// A memory allocator with buddy allocation mechanism
@@ -1135,87 +1977,983 @@ macro_rules! pool {
                 }
             }
     
-            static mut BUDDY_INNER: Option<*mut BuddyAllocInner> = None;
-            static mut OPEN: AtomicBool = AtomicBool::new(false);
-            static mut MAX_GEN: u32 = 0;
-            static mut VDATA: LazyCell<Arc<Mutex<Option<VData>>>> = 
-                LazyCell::new(|| Arc::new(Mutex::new(None)));
-    
-            impl $name {
-                fn running_transaction() -> bool {
-                    let vdata = match unsafe { VDATA.lock() } {
-                        Ok(g) => g,
-                        Err(p) => p.into_inner()
-                    };
-                    if let Some(vdata) = &*vdata {
-                        !vdata.journals.is_empty()
-                    } else {
-                        false
+            /// The volatile state of a pool image mapped by this pool type
+            struct PoolState {
+                start: u64,
+                valid_start: u64,
+
+                /// The end of the valid address range. It is published with
+                /// an atomic store, as `grow` moves it while the other threads
+                /// may be checking addresses against it.
+                end: AtomicU64,
+                inner: Option<*mut BuddyAllocInner>,
+                open: AtomicBool,
+                max_gen: u32,
+                read_only: bool,
+
+                /// Shows that the pool is open in the shared mode
+                shared: bool,
+                vdata: Mutex<Option<VData>>,
+                grow: Mutex<Option<GrowInfo>>,
+
+                /// The address space to reserve on the next writable open
+                reserve: usize,
+
+                /// Signals the changes of the running transactions and
+                /// accesses to the quiescing thread, and the end of the
+                /// quiescence to the waiting threads
+                gate: Condvar,
+            }
+
+            impl PoolState {
+                fn new() -> Self {
+                    Self {
+                        start: 0,
+                        valid_start: 0,
+                        end: AtomicU64::new(0),
+                        inner: None,
+                        open: AtomicBool::new(false),
+                        max_gen: 0,
+                        read_only: false,
+                        shared: false,
+                        vdata: Mutex::new(None),
+                        grow: Mutex::new(None),
+                        reserve: POOL_RESERVED_SIZE as usize,
+                        gate: Condvar::new(),
                     }
                 }
+            }
+
+            /// The state of the default instance, which is used by the threads
+            /// that are not bound to any instance
+            static DEFAULT_STATE: OnceLock<usize> = OnceLock::new();
+
+            thread_local! {
+                /// The instance which the current thread is bound to, if any.
+                /// It is const-initialized so that reading it is a plain
+                /// thread-local load without a lazy initialization check.
+                static BOUND_STATE: std::cell::Cell<*mut PoolState> =
+                    const { std::cell::Cell::new(std::ptr::null_mut()) };
+            }
+
+            /// Returns the state of the pool instance which the current thread
+            /// operates on
+            #[inline]
+            unsafe fn inst() -> &'static mut PoolState {
+                let bound = BOUND_STATE.with(|b| b.get());
+                if bound.is_null() {
+                    let state = DEFAULT_STATE.get_or_init(||
+                        Box::into_raw(Box::new(PoolState::new())) as usize);
+                    &mut *(*state as *mut PoolState)
+                } else {
+                    &mut *bound
+                }
+            }
+
+            /// Binds the current thread to a pool instance, and restores the
+            /// previous binding when it is dropped
+            struct Binding(*mut PoolState);
+
+            impl Binding {
+                fn new(state: *mut PoolState) -> Self {
+                    Binding(BOUND_STATE.with(|b| b.replace(state)))
+                }
+            }
+
+            impl Drop for Binding {
+                fn drop(&mut self) {
+                    let prev = self.0;
+                    BOUND_STATE.with(|b| b.set(prev));
+                }
+            }
+
+            /// Panics if the current thread is not bound to any instance while
+            /// some instances are open. It is kept out of `start()`, which is
+            /// on the path of every dereference, as it is only needed when the
+            /// thread sees no mapped pool.
+            #[cold]
+            #[inline(never)]
+            fn check_bound() {
+                if INSTANCES.load(Ordering::Acquire) != 0
+                    && BOUND_STATE.with(|b| b.get().is_null())
+                {
+                    panic!("The current thread is not bound to a pool instance of `{}`; \
+                        use `PoolInstance::run` in every thread which accesses the instance",
+                        stringify!($mod));
+                }
+            }
+
+            /// Waits on the gate of the pool instance
+            fn wait_gate<'a>(
+                state: &PoolState,
+                guard: MutexGuard<'a, Option<VData>>
+            ) -> MutexGuard<'a, Option<VData>> {
+                match state.gate.wait(guard) {
+                    Ok(g) => g,
+                    Err(p) => p.into_inner()
+                }
+            }
+
+            /// Keeps the other threads out of the pool until it is dropped.
+            /// See `quiesce()`.
+            struct Quiesced(*mut PoolState);
+
+            impl Drop for Quiesced {
+                fn drop(&mut self) {
+                    let state = unsafe { &*self.0 };
+                    let mut vdata = match state.vdata.lock() {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    if let Some(vdata) = &mut *vdata {
+                        vdata.exclusive = None;
+                    }
+                    state.gate.notify_all();
+                }
+            }
+
+            /// A pool file opened as a separate instance of the pool type
+            ///
+            /// By default, a pool type maps a single pool file at a time.
+            /// [`open_instance`] maps another file of the same pool type
+            /// independently, so that any number of pool files with the same
+            /// schema can be open at the same time. The persistent pointers do
+            /// not carry the identity of their pool; instead, every thread
+            /// operates on one instance at a time. [`run`] binds the current
+            /// thread to this instance while its closure runs, and all
+            /// operations of the pool type (e.g. transactions, allocations,
+            /// and dereferencing the persistent pointers) apply to this
+            /// instance. Outside of [`run`], the thread operates on the pool
+            /// opened by [`open`].
+            ///
+            /// The objects of an instance must not be accessed while the
+            /// thread is bound to another instance. Since the value returned
+            /// from [`run`] is [`TxOutSafe`], persistent pointers cannot leave
+            /// it; volatile references (e.g. `VWeak`) should not be used across
+            /// instances. The pool file is closed when the instance is
+            /// dropped.
+            ///
+            /// The binding is per thread, and it is not inherited by the
+            /// threads spawned inside [`run`]. To use an instance in several
+            /// threads, share it (e.g. in an `Arc`) and call [`run`] in each of
+            /// them. A thread which is not bound to an instance panics if it
+            /// dereferences a persistent pointer while instances are open but
+            /// the pool of [`open`] is not.
+            ///
+            /// # Examples
+            ///
+            /// ```
+            /// corundum::pool!(shard);
+            /// use shard::*;
+            ///
+            /// type P = Allocator;
+            ///
+            /// let shards: Vec<_> = (0..4).map(|i| {
+            ///     P::open_instance::<PCell<i32>>(&format!("shard{}.pool", i), O_CF).unwrap()
+            /// }).collect();
+            ///
+            /// for (i, s) in shards.iter().enumerate() {
+            ///     s.run(|root| P::transaction(|j| root.set(i as i32, j))).unwrap();
+            /// }
+            /// for (i, s) in shards.iter().enumerate() {
+            ///     assert_eq!(s.run(|root| root.get()), i as i32);
+            /// }
+            /// ```
+            ///
+            /// [`open_instance`]: ./struct.Allocator.html#method.open_instance
+            /// [`open`]: ./struct.Allocator.html#method.open
+            /// [`run`]: #method.run
+            /// [`TxOutSafe`]: ../trait.TxOutSafe.html
+            pub struct PoolInstance<U: 'static + PSafe> {
+                state: *mut PoolState,
+                root: Option<RootCell<'static, U, $name>>,
+            }
+
+            unsafe impl<U: 'static + PSafe + Send> Send for PoolInstance<U> {}
+            unsafe impl<U: 'static + PSafe + Send + Sync> Sync for PoolInstance<U> {}
+
+            /// The number of open instances
+            static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+            impl<U: 'static + PSafe> PoolInstance<U> {
+                /// Binds the current thread to this instance and runs `f`
+                /// with a reference to its root object
+                ///
+                /// The previous binding of the thread is restored when `f`
+                /// returns or panics. It can be called in a transaction of
+                /// another instance, but the transactions of the two instances
+                /// are independent.
+                pub fn run<T, F>(&self, f: F) -> T
+                where
+                    F: FnOnce(&RootCell<'static, U, $name>) -> T,
+                    T: TxOutSafe,
+                {
+                    let _binding = Binding::new(self.state);
+                    f(self.root.as_ref().unwrap())
+                }
+            }
+
+            impl<U: 'static + PSafe> Drop for PoolInstance<U> {
+                fn drop(&mut self) {
+                    {
+                        let _binding = Binding::new(self.state);
+                        self.root = None;
+                    }
+                    unsafe { drop(Box::from_raw(self.state)); }
+                    INSTANCES.fetch_sub(1, Ordering::Release);
+                }
+            }
+
+            impl<U: 'static + PSafe> std::fmt::Debug for PoolInstance<U> {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    f.debug_struct("PoolInstance")
+                        .field("state", &self.state)
+                        .finish()
+                }
+            }
+
+            impl $name {
+                /// Opens a pool file as a new instance of this pool type
+                ///
+                /// It works similar to [`open`], except that the pool is mapped
+                /// independently of the other instances and the file opened by
+                /// [`open`]. The root object is accessible via
+                /// [`PoolInstance::run`].
+                ///
+                /// [`open`]: #method.open
+                /// [`PoolInstance::run`]: ./struct.PoolInstance.html#method.run
+                pub fn open_instance<U: 'static + PSafe + RootObj<Self>>(
+                    path: &str,
+                    flags: u32
+                ) -> Result<PoolInstance<U>> {
+                    let state = Box::into_raw(Box::new(PoolState::new()));
+                    let root = {
+                        let _binding = Binding::new(state);
+                        Self::open::<U>(path, flags)
+                    };
+                    match root {
+                        Ok(root) => {
+                            INSTANCES.fetch_add(1, Ordering::Acquire);
+                            Ok(PoolInstance { state, root: Some(root) })
+                        }
+                        Err(e) => {
+                            unsafe { drop(Box::from_raw(state)); }
+                            Err(e)
+                        }
+                    }
+                }
+            }
     
-                /// Opens a memory pool file and returns an instance of
-                /// [`Allocator`](#) if success. The pool remains open as long
-                /// as the instance lives.
-                #[track_caller]
-                pub fn open_impl(filename: &str, no_check: bool) -> Result<PoolGuard<Self>> {
-                    let metadata = std::fs::metadata(filename);
-                    if let Err(e) = &metadata {
-                        Err(format!("{}", e))
+            impl $name {
+                fn running_transaction() -> bool {
+                    let vdata = match unsafe { inst().vdata.lock() } {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    if let Some(vdata) = &*vdata {
+                        !vdata.journals.is_empty()
+                    } else {
+                        false
+                    }
+                }
+
+                /// Takes an exclusive lock on the pool file if it exists, so
+                /// that the open flags do not truncate or format a file which
+                /// is in use by another pool
+                fn probe_lock(path: &str) -> Result<Option<std::fs::File>> {
+                    if Path::new(path).exists() {
+                        let file = OpenOptions::new().read(true).open(path)?;
+                        lock_file(&file, path, true)?;
+                        Ok(Some(file))
+                    } else {
+                        Ok(None)
+                    }
+                }
+
+                /// Opens the pool file for writing and locks it exclusively
+                ///
+                /// The file is created empty if `flags` may create it. The
+                /// same file, and so the same lock, is used for applying the
+                /// open flags and for mapping the pool, so that no other pool
+                /// can take the file in between.
+                fn lock_for_write(path: &str, flags: u32) -> Result<std::fs::File> {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(flags & (open_flags::O_C | open_flags::O_CNE) != 0)
+                        .open(path)?;
+                    lock_file(&file, path, true)?;
+                    Ok(file)
+                }
+
+                /// Recovers the pool after it is mapped
+                unsafe fn recover_on_open(guard: PoolGuard<Self>) -> Result<PoolGuard<Self>> {
+                    // The logs of a chaperoned session cannot be recovered
+                    // without the chaperon file
+                    static_inner!(inst().inner, inner, {
+                        let mut curr = inner.journals;
+                        while let Ok(j) = Self::deref::<Journal>(curr) {
+                            if let Some(f) = j.missing_chaperon() {
+                                return Err(Error::ChaperonMissing(f));
+                            }
+                            curr = j.next_off();
+                        }
+                    });
+                    Self::recover();
+                    Ok(guard)
+                }
+
+                /// Waits while another thread quiesces the pool, unless the
+                /// current thread is already inside the pool, i.e. it runs a
+                /// transaction or accesses the pool outside a transaction
+                fn enter_gate<'a>(
+                    mut guard: MutexGuard<'a, Option<VData>>
+                ) -> MutexGuard<'a, Option<VData>> {
+                    let tid = std::thread::current().id();
+                    loop {
+                        match &*guard {
+                            Some(vdata) if vdata.exclusive.map_or(false, |t| t != tid)
+                                && !vdata.journals.contains_key(&tid)
+                                && !vdata.accesses.contains_key(&tid) => {
+                                guard = wait_gate(unsafe { inst() }, guard);
+                            }
+                            _ => return guard,
+                        }
+                    }
+                }
+
+                /// Keeps the other threads from starting a transaction or
+                /// accessing the pool outside a transaction, and waits until
+                /// the running ones finish
+                ///
+                /// The pool is quiesced until the returned guard is dropped.
+                /// The current thread may run transactions meanwhile. It
+                /// returns [`TxRunning`] if the current thread is already
+                /// inside the pool, and [`Unsupported`] if the pool is shared
+                /// with other processes.
+                ///
+                /// [`TxRunning`]: ../../enum.Error.html#variant.TxRunning
+                /// [`Unsupported`]: ../../enum.Error.html#variant.Unsupported
+                unsafe fn quiesce() -> Result<Quiesced> {
+                    let tid = std::thread::current().id();
+                    let state = inst();
+                    if state.shared {
+                        // The other processes cannot be kept out
+                        return Err(Error::Unsupported(
+                            "A shared pool cannot be quiesced".to_string()
+                        ));
+                    }
+                    let mut guard = match state.vdata.lock() {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    loop {
+                        let vdata = match &mut *guard {
+                            Some(vdata) => vdata,
+                            None => return Err(Error::PoolNotOpen),
+                        };
+                        if vdata.exclusive == Some(tid)
+                            || vdata.journals.contains_key(&tid)
+                            || vdata.accesses.contains_key(&tid) {
+                            return Err(Error::TxRunning);
+                        }
+                        if vdata.exclusive.is_none() {
+                            vdata.exclusive = Some(tid);
+                            break;
+                        }
+                        // Another thread quiesces the pool
+                        guard = wait_gate(state, guard);
+                    }
+                    let quiesced = Quiesced(state as *mut PoolState);
+                    loop {
+                        match &*guard {
+                            Some(vdata) if !vdata.journals.is_empty()
+                                || !vdata.accesses.is_empty() => {
+                                guard = wait_gate(state, guard);
+                            }
+                            Some(_) => return Ok(quiesced),
+                            None => {
+                                drop(guard);
+                                return Err(Error::PoolNotOpen);
+                            }
+                        }
+                    }
+                }
+
+                fn magic_number() -> u64 {
+                    let id = std::any::type_name::<BuddyAllocInner>();
+                    let mut s = DefaultHasher::new();
+                    id.hash(&mut s);
+                    s.finish()
+                }
+
+                /// Checks if the root object of the pool is of type `U`
+                ///
+                /// The fingerprint of the root type is checked, if it is
+                /// recorded; otherwise, the type id of older pools is.
+                fn check_root_type<U>(inner: &BuddyAllocInner) -> Result<()> {
+                    if !inner.root_schema.is_empty() {
+                        inner.root_schema.check(&RootFingerprint::of::<U>())?;
+                        if inner.root_schema.has_stable_name() {
+                            return Ok(());
+                        }
+                    }
+                    // Compiler-given names are not reliable; fail closed
+                    if inner.root_type_id != Self::root_type_id::<U>() {
+                        Err(Error::RootTypeMismatch(
+                            "Incompatible root type".to_string()
+                        ))
                     } else {
-                        let metadata = metadata.unwrap();
-                        assert!(metadata.is_file());
-                        if metadata.len() < 8 {
-                            Err("Invalid pool file".to_string())
+                        Ok(())
+                    }
+                }
+
+                /// Checks if the file at `path` has a valid pool header for
+                /// this pool type
+                fn check_image(path: &str) -> Result<()> {
+                    let file = OpenOptions::new().read(true).open(path)?;
+                    if file.metadata()?.len() < mem::size_of::<BuddyAllocInner>() as u64 {
+                        return Err(Error::InvalidPool("Invalid pool file".to_string()));
+                    }
+                    let mmap = unsafe {
+                        memmap::MmapOptions::new()
+                            .len(mem::size_of::<BuddyAllocInner>())
+                            .map(&file)?
+                    };
+                    let inner = unsafe { &*(mmap.as_ptr() as *const BuddyAllocInner) };
+                    inner.header.validate()?;
+                    if inner.magic_number != Self::magic_number() {
+                        return Err(Error::InvalidPool(format!(
+                            "Invalid magic number for the pool image file (the pool was not created by `{}`)",
+                            stringify!($mod)
+                        )));
+                    }
+                    Ok(())
+                }
+
+                /// Maps the replica file at `path` for the pool image of
+                /// `filename`, which is mapped at `base`, and mirrors the
+                /// flushes of the pool to it
+                ///
+                /// A new replica, or a replica with a damaged header, is made
+                /// from the pool. If the pool crashed while the replica was
+                /// attached, the pages of the replica which differ from the
+                /// pool are repaired, because every flush reaches the pool
+                /// before the replica. Otherwise, the two images should be
+                /// identical; if they are not, it returns [`InvalidPool`]
+                /// rather than overwriting either of them.
+                ///
+                /// [`InvalidPool`]: ../../enum.Error.html#variant.InvalidPool
+                unsafe fn attach_replica(
+                    path: &str,
+                    filename: &str,
+                    inner: &mut BuddyAllocInner,
+                    mmap: &mut MmapMut,
+                    len: usize,
+                    reserved: usize,
+                    base: u64
+                ) -> Result<(std::fs::File, MmapMut)> {
+                    if Path::new(path) == Path::new(filename) {
+                        return Err(Error::InvalidArgument(
+                            "The replica cannot be the pool file itself".to_string()
+                        ));
+                    }
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .open(path)?;
+                    lock_file(&file, path, true)?;
+                    let rlen = file.metadata()?.len() as usize;
+                    let mut rmap = memmap::MmapOptions::new()
+                        .len(reserved)
+                        .map_mut(&file)?;
+
+                    let valid = rlen >= mem::size_of::<BuddyAllocInner>() && {
+                        let rinner = read::<BuddyAllocInner>(rmap.as_mut_ptr());
+                        rinner.header.validate().is_ok()
+                            && rinner.magic_number == inner.magic_number
+                    };
+                    let mut diverged = 0;
+                    if valid {
+                        let common = len.min(rlen);
+                        diverged = mmap[..common].chunks(4096)
+                            .zip(rmap[..common].chunks(4096))
+                            .filter(|(p, r)| p != r)
+                            .count();
+                        diverged += (len.max(rlen) - common + 4095) / 4096;
+                        if diverged != 0 && inner.flags & FLAG_REPLICATED == 0 {
+                            return Err(Error::InvalidPool(format!(
+                                "The replica `{}` differs from the pool in {} page(s); \
+                                restore the damaged image or remove the replica",
+                                path, diverged
+                            )));
+                        }
+                        if diverged != 0 {
+                            eprintln!("Repairing {} page(s) of the replica `{}` which \
+                                were not mirrored before the pool crashed", diverged, path);
+                        }
+                    } else if rlen != 0 {
+                        eprintln!("The replica `{}` is damaged; making it again from the pool", path);
+                    }
+
+                    if !valid || diverged != 0 {
+                        if rlen != len {
+                            file.set_len(len as u64)?;
+                        }
+                        for (p, r) in mmap[..len].chunks(4096).zip(rmap[..len].chunks_mut(4096)) {
+                            if p != r {
+                                r.copy_from_slice(p);
+                            }
+                        }
+                        rmap.flush_range(0, len)?;
+                    }
+
+                    add_replica(base as usize, len, rmap.as_ptr() as usize);
+                    inner.flags |= FLAG_REPLICATED;
+                    persist_obj(inner, true);
+                    Ok((file, rmap))
+                }
+
+                /// Opens a pool and keeps a replica of it in another file
+                ///
+                /// It works similar to [`open`], and in addition, every range
+                /// of the pool which is flushed (e.g. by a transaction) is
+                /// also written to the same offset of the replica file at
+                /// `replica` and flushed there. Since the replica receives the
+                /// flushes in the same order, it is always a consistent image
+                /// of the pool which can be recovered like the pool itself
+                /// after a crash.
+                ///
+                /// On open, if the header of an existing pool file is damaged
+                /// or missing and the replica is valid, the damaged file is
+                /// moved to `<path>.damaged` and the pool file is restored
+                /// from the replica. A replica which does not exist or has a
+                /// damaged header is made from the pool. If the pool crashed
+                /// while it was replicated, the pages which did not reach the
+                /// replica are copied to it. Otherwise, if the two images
+                /// differ, it returns [`InvalidPool`] without changing them.
+                ///
+                /// It requires the "replicate" feature, and a replicated pool
+                /// cannot be open in read-only mode.
+                ///
+                /// # Examples
+                ///
+                /// ```no_run
+                /// # use corundum::default::*;
+                /// # type P = Allocator;
+                /// let root = P::open_with_replica::<PCell<i32>>("foo.pool", "foo.replica", O_CF).unwrap();
+                /// P::transaction(|j| root.set(10, j)).unwrap();
+                /// ```
+                ///
+                /// [`open`]: #method.open
+                /// [`InvalidPool`]: ../../enum.Error.html#variant.InvalidPool
+                #[track_caller]
+                pub fn open_with_replica<'a, U: 'a + PSafe + RootObj<Self>>(
+                    path: &str,
+                    replica: &str,
+                    flags: u32,
+                ) -> Result<RootCell<'a, U, Self>> {
+                    if !REPLICATION {
+                        return Err(Error::Unsupported(
+                            "Replication requires the `replicate` feature".to_string()
+                        ));
+                    }
+                    if flags == open_flags::O_READINFO || flags & open_flags::O_RDONLY != 0 {
+                        return Err(Error::InvalidArgument(
+                            "A replicated pool cannot be open in read-only mode".to_string()
+                        ));
+                    }
+                    let creates = flags & (open_flags::O_C | open_flags::O_F) != 0
+                        || (flags & open_flags::O_CNE != 0 && !Path::new(path).exists());
+                    if !creates && Self::check_image(path).is_err() && Self::check_image(replica).is_ok() {
+                        let _probe = Self::probe_lock(path)?;
+                        let src = OpenOptions::new().read(true).open(replica)?;
+                        lock_file(&src, replica, true)?;
+                        let image = unsafe { memmap::MmapOptions::new().map(&src)? };
+                        if Path::new(path).exists() {
+                            let mut backup = format!("{}.damaged", path);
+                            let mut i = 1;
+                            while Path::new(&backup).exists() {
+                                backup = format!("{}.damaged.{}", path, i);
+                                i += 1;
+                            }
+                            eprintln!("The pool image `{}` is damaged; moving it to `{}` and restoring it from `{}`",
+                                path, backup, replica);
+                            std::fs::rename(path, &backup)?;
                         } else {
-                            let path = PathBuf::from(filename);
-                            let file = OpenOptions::new()
-                                .read(true)
-                                .write(true)
-                                .create(true)
-                                .open(&path)
+                            eprintln!("The pool image `{}` is missing; restoring it from `{}`", path, replica);
+                        }
+                        copy_image(Some(&src), &image, path)?;
+                    }
+                    Self::open_root::<U>(path, flags, Some(replica))
+                }
+
+                /// Opens the pool and its root object, and mirrors the pool to
+                /// `replica` if it is given
+                #[allow(unused_unsafe)]
+                #[track_caller]
+                fn open_root<'a, U: 'a + PSafe + RootObj<Self>>(
+                    path: &str,
+                    flags: u32,
+                    replica: Option<&str>
+                ) -> Result<RootCell<'a, U, Self>> {
+                    let slf = Self::open_no_root_impl(path, flags, replica)?;
+                    static_inner!(inst().inner, inner, {
+                        let id = Self::root_type_id::<U>();
+                        let schema = RootFingerprint::of::<U>();
+                        if !inner.has_root() {
+                            if Self::is_read_only() {
+                                Err(Error::NoRootObject)
+                            } else if mem::size_of::<U>() == 0 {
+                                Err(Error::InvalidArgument("root type cannot be a ZST".to_string()))
+                            } else {
+                                let root_off = Self::transaction(move |j| {
+                                    let ptr = Self::new(U::init(j), j);
+                                    Self::off_unchecked(ptr)
+                                })
                                 .unwrap();
+                                let ptr = Self::get_unchecked(root_off);
+                                inner.flags |= FLAG_HAS_ROOT;
+                                inner.root_obj = root_off;
+                                inner.root_type_id = id;
+                                inner.root_schema = schema;
+                                persist_obj(inner, true);
+                                Ok(RootCell::new(ptr, Arc::new(slf)))
+                            }
+                        } else {
+                            Self::check_root_type::<U>(inner)?;
+                            Ok(RootCell::new(
+                                Self::deref::<U>(inner.root_obj)?,
+                                Arc::new(slf),
+                            ))
+                        }
+                    })
+                }
     
-                            let mut mmap =
-                                unsafe { memmap::MmapOptions::new().map_mut(&file).unwrap() };
-    
-                            let raw_offset = mmap.get_mut(0).unwrap();
-    
-                            let id = std::any::type_name::<BuddyAllocInner>();
-                            let mut s = DefaultHasher::new();
-                            id.hash(&mut s);
-                            let id = s.finish();
-    
-                            let inner = unsafe {
-                                read::<BuddyAllocInner>(raw_offset)
+                /// Opens the pool without the root object, and mirrors the
+                /// pool to `replica` if it is given
+                #[allow(unused_unsafe)]
+                #[track_caller]
+                fn open_no_root_impl(path: &str, flags: u32, replica: Option<&str>) -> Result<PoolGuard<Self>> {
+                    unsafe {
+                        while inst().open.compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed).is_err() {}
+                        if !Self::running_transaction() {
+                            let res = if flags == open_flags::O_READINFO {
+                                Self::open_impl(path, true, false, None)
+                            } else if flags & open_flags::O_RDONLY != 0 {
+                                if flags == open_flags::O_RDONLY {
+                                    // The recovery only changes the private
+                                    // mapping
+                                    Self::open_impl(path, false, true, None)
+                                        .and_then(Self::recover_on_open)
+                                } else {
+                                    Err(Error::InvalidArgument(
+                                        "Cannot combine O_RDONLY with other flags".to_string()
+                                    ))
+                                }
+                            } else if flags & open_flags::O_SHARED != 0 {
+                                if replica.is_some() {
+                                    Err(Error::InvalidArgument(
+                                        "Cannot replicate a shared pool".to_string()
+                                    ))
+                                } else {
+                                    Self::open_shared(path, flags & !open_flags::O_SHARED)
+                                }
+                            } else {
+                                Self::lock_for_write(path, flags)
+                                    .and_then(|file| {
+                                        Self::apply_flags(path, flags, &file)?;
+                                        Self::map_file(path, file, false, false, replica, false)
+                                    })
+                                    .and_then(Self::recover_on_open)
                             };
-                            if !no_check {
-                                assert_eq!(
-                                    inner.magic_number, id,
-                                    "Invalid magic number for the pool image file"
-                                );
+                            if res.is_err() {
+                                inst().open.store(false, Ordering::Release);
+                            }
+                            res
+                        } else {
+                            inst().open.store(false, Ordering::Release);
+                            Err(Error::TxRunning)
+                        }
+                    }
+                }
+
+                /// Opens the pool in the shared mode (see [`O_SHARED`])
+                ///
+                /// The first process applies the open `flags` and recovers
+                /// the pool. The others wait until it is recovered, and then
+                /// map it without changing its generation, so that the
+                /// volatile cells, including the locks, stay valid.
+                ///
+                /// [`O_SHARED`]: ../open_flags/constant.O_SHARED.html
+                unsafe fn open_shared(path: &str, flags: u32) -> Result<PoolGuard<Self>> {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(flags & (open_flags::O_C | open_flags::O_CNE) != 0)
+                        .open(path)?;
+
+                    // Keeps out the processes which open it exclusively
+                    lock_file(&file, path, false)?;
+                    let first = join_shared(&file)?;
+                    if !first && flags & open_flags::O_C != 0 {
+                        // It would truncate the pool under the other processes
+                        return Err(Error::PoolLocked(path.to_string()));
+                    }
+                    $crate::sync::share_locks(true);
+                    inst().shared = true;
+                    let res = if first {
+                        file.try_clone().map_err(Error::from).and_then(|lock| {
+                            Self::apply_flags(path, flags, &file)?;
+                            let guard = Self::map_file(path, file, false, false, None, false)
+                                .and_then(|g| Self::recover_on_open(g))?;
+                            shared_ready(&lock)?;
+                            Ok(guard)
+                        })
+                    } else {
+                        Self::map_file(path, file, false, false, None, true)
+                    };
+                    if res.is_err() {
+                        inst().shared = false;
+                        $crate::sync::share_locks(false);
+                    }
+                    res
+                }
+
+                fn auto_grow_step() -> usize {
+                    let grow = match unsafe { inst().grow.lock() } {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    if let Some(info) = &*grow {
+                        info.step
+                    } else {
+                        0
+                    }
+                }
+
+                fn root_type_id<U>() -> u64 {
+                    // Replace it with std::any::TypeId::of::<U>() when it
+                    // is available in the future for non-'static types
+                    let id = format!("{} ({})", std::any::type_name::<U>(),
+                        mem::size_of::<U>());
+                    let mut s = DefaultHasher::new();
+                    id.hash(&mut s);
+                    s.finish()
+                }
+
+                /// Formats `path` as a new pool image of `size` bytes and
+                /// copies the blocks collected in `r` into it
+                ///
+                /// The source image is addressed by `src`. The caller should
+                /// bind the current thread to a temporary pool state, which
+                /// is pointed to the new image, so that the allocator of the
+                /// new image works on it while the other threads keep using
+                /// the source image.
+                unsafe fn compact_impl(
+                    src: u64,
+                    path: &str,
+                    size: u64,
+                    r: &Relocator<Self>,
+                    root_off: u64,
+                    root_type_id: u64,
+                    root_schema: RootFingerprint,
+                    gen: u32,
+                ) -> Result<()> {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(path)?;
+                    file.set_len(size)?;
+
+                    let mut mmap = memmap::MmapOptions::new().map_mut(&file)?;
+                    let base = mmap.get_mut(0).unwrap() as *mut u8;
+                    inst().start = base as u64;
+                    inst().end.store(u64::MAX, Ordering::Release);
+                    let dst = read::<BuddyAllocInner>(base);
+                    dst.init(size as usize);
+                    inst().valid_start = inst().start
+                        + mem::size_of::<BuddyAllocInner>() as u64
+                        + mem::size_of::<$alg<Self>>() as u64;
+                    inst().end.store(inst().start + dst.size as u64 + 1, Ordering::Release);
+
+                    // Copy the live blocks to the new image
+                    let mut offs = HashMap::<u64, u64>::new();
+                    for (off, len) in r.blocks() {
+                        let mut new = u64::MAX;
+                        for z in 0..dst.zone.count() {
+                            new = dst.zone[z].alloc_impl(len, true);
+                            if new != u64::MAX {
+                                break;
                             }
+                        }
+                        if new == u64::MAX {
+                            return Err(Error::OutOfMemory { requested: len });
+                        }
+                        std::ptr::copy_nonoverlapping(
+                            (src + off) as *const u8,
+                            Self::get_mut_unchecked::<u8>(new) as *mut u8,
+                            len
+                        );
+                        offs.insert(off, new);
+                    }
+
+                    // Rewrite the persistent pointers in their new locations
+                    for link in r.links() {
+                        let block = r.block_of(*link)
+                            .ok_or_else(|| Error::InvalidPool(
+                                format!("Pointer @{} is not in a live block", link)))?;
+                        let trg = *((src + link) as *const u64);
+                        let new_trg = *offs.get(&trg)
+                            .ok_or_else(|| Error::InvalidPool(
+                                format!("Pointer @{} refers to an unknown block", link)))?;
+                        *Self::get_mut_unchecked::<u64>(offs[&block] + (link - block)) = new_trg;
+                    }
+
+                    // The generation is carried over so that the volatile
+                    // cells in the copied objects are reset on the next open
+                    dst.gen = gen;
+                    dst.root_obj = offs[&root_off];
+                    dst.root_type_id = root_type_id;
+                    dst.root_schema = root_schema;
+                    dst.flags |= FLAG_HAS_ROOT;
+                    mmap.flush()?;
+                    Ok(file.sync_all()?)
+                }
     
-                            let base = raw_offset as *mut _ as u64;
-                            unsafe {
-                                inner.gen = MAX_GEN.max(inner.gen + 1);
+                /// Opens a memory pool file and returns an instance of
+                /// [`Allocator`](#) if success. The pool remains open as long
+                /// as the instance lives.
+                ///
+                /// The file is locked exclusively, or shared if `no_check` is
+                /// set for only reading the pool information or if the pool is
+                /// `read_only`. If another pool holds a conflicting lock, it
+                /// returns [`PoolLocked`](../../enum.Error.html#variant.PoolLocked).
+                /// A `read_only` pool is mapped privately, so that no changes
+                /// are written to the file. If a `replica` path is given, the
+                /// flushes of the pool are mirrored to it (see
+                /// [`open_with_replica`](#method.open_with_replica)).
+                #[track_caller]
+                pub fn open_impl(
+                    filename: &str,
+                    no_check: bool,
+                    read_only: bool,
+                    replica: Option<&str>
+                ) -> Result<PoolGuard<Self>> {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .write(!read_only)
+                        .open(filename)?;
+
+                    // Only one pool may write to the file at a time
+                    lock_file(&file, filename, !no_check && !read_only)?;
+                    Self::map_file(filename, file, no_check, read_only, replica, false)
+                }
+
+                /// Maps the pool `file` at `filename`, which the caller has
+                /// locked, and sets up the state of the pool as in
+                /// [`open_impl`](#method.open_impl)
+                ///
+                /// If the pool is `joined`, i.e. another process has it open
+                /// in the shared mode, its generation is kept.
+                #[track_caller]
+                fn map_file(
+                    filename: &str,
+                    file: std::fs::File,
+                    no_check: bool,
+                    read_only: bool,
+                    replica: Option<&str>,
+                    joined: bool
+                ) -> Result<PoolGuard<Self>> {
+                    let metadata = file.metadata()?;
+                    assert!(metadata.is_file());
+                    if metadata.len() < mem::size_of::<BuddyAllocInner>() as u64 {
+                        Err(Error::InvalidPool("Invalid pool file".to_string()))
+                    } else {
+                        // Reserve enough virtual address space for growing
+                        // the pool without moving it, unless it is read-only
+                        let reserved = if read_only || no_check || unsafe { inst().shared } {
+                            metadata.len() as usize
+                        } else {
+                            unsafe { inst().reserve.max(metadata.len() as usize) }
+                        };
+                        let mut mmap = $alg::<Self>::map(filename, &file, reserved, read_only)?;
+
+                        let raw_offset = mmap.get_mut(0).unwrap();
+
+                        let id = Self::magic_number();
+
+                        let inner = unsafe {
+                            read::<BuddyAllocInner>(raw_offset)
+                        };
+                        if !no_check {
+                            inner.header.validate()?;
+                            if inner.magic_number != id {
+                                return Err(Error::InvalidPool(format!(
+                                    "Invalid magic number for the pool image file (the pool was not created by `{}`)",
+                                    stringify!($mod)
+                                )));
+                            }
+                            if inner.header.zone_count != inner.zone.count() as u64
+                                && inner.reconf_state == RECONF_IDLE
+                            {
+                                return Err(Error::InvalidPool(
+                                    "Corrupted pool header: zone count mismatch".to_string()
+                                ));
+                            }
+                        }
+
+                        let base = raw_offset as *mut _ as u64;
+                        let replica = if let Some(path) = replica {
+                            let (file, rmap) = unsafe {
+                                Self::attach_replica(path, filename, inner, &mut mmap,
+                                    metadata.len() as usize, reserved, base)?
+                            };
+                            Some((path.to_string(), file, rmap))
+                        } else {
+                            None
+                        };
+                        let replica_file = match &replica {
+                            Some((_, file, _)) => Some(file.try_clone()?),
+                            None => None
+                        };
+                        let grow_file = file.try_clone()?;
+                        unsafe {
+                            if !joined {
+                                inner.gen = inst().max_gen.max(inner.gen + 1);
                                 inner.tx_gen = 0;
-                                MAX_GEN = inner.gen;
-                                BUDDY_START = base;
-                                BUDDY_VALID_START = base
-                                    + mem::size_of::<BuddyAllocInner>() as u64
-                                    + mem::size_of::<BuddyAlg<Self>>() as u64;
-                                BUDDY_END = BUDDY_START + inner.size as u64 + 1;
-                                BUDDY_INNER = Some(inner);
-                                let mut vdata = match VDATA.lock() {
-                                    Ok(g) => g,
-                                    Err(p) => p.into_inner()
-                                };
-                                *vdata = Some(VData::new(mmap, filename));
                             }
-    
-                            Ok(PoolGuard::<Self>::new())
+                            inst().max_gen = inst().max_gen.max(inner.gen);
+                            inst().start = base;
+                            inst().valid_start = base
+                                + mem::size_of::<BuddyAllocInner>() as u64
+                                + mem::size_of::<$alg<Self>>() as u64;
+                            inst().end.store(inst().start + inner.size as u64 + 1, Ordering::Release);
+                            inst().inner = Some(inner);
+                            inst().read_only = read_only;
+                            let mut vdata = match inst().vdata.lock() {
+                                Ok(g) => g,
+                                Err(p) => p.into_inner()
+                            };
+                            let mut data = VData::new(mmap, filename, file);
+                            data.replica = replica;
+                            *vdata = Some(data);
+                            let mut grow = match inst().grow.lock() {
+                                Ok(g) => g,
+                                Err(p) => p.into_inner()
+                            };
+                            *grow = Some(GrowInfo {
+                                file: grow_file,
+                                reserved,
+                                step: 0,
+                                replica: replica_file
+                            });
                         }
+
+                        Ok(PoolGuard::<Self>::new())
                     }
                 }
             }
@@ -1233,57 +2971,55 @@ macro_rules! pool {
                             .read(true)
                             .write(true)
                             .create(true)
-                            .open(filename);
-                        if let Err(e) = &file {
-                            Err(format!("{}", e))
-                        } else {
-                            let file = file.unwrap();
-                            let mut len = file.metadata().unwrap().len() as usize;
-                            if len < 8 {
-                                len = 10 * 1024 * 1024;
-                                file.set_len(len as u64).unwrap();
-                            }
-    
-                            let mut mmap = memmap::MmapOptions::new().map_mut(&file).unwrap();
-                            let begin = mmap.get_mut(0).unwrap();
-                            std::ptr::write_bytes(begin, 0xff, 8);
-                            BUDDY_START = begin as *const _ as u64;
-                            BUDDY_END = u64::MAX;
-    
-                            let inner = read::<BuddyAllocInner>(begin);
-                            inner.init(len);
-                            mmap.flush().unwrap();
-                            Ok(())
+                            .open(filename)?;
+                        let mut len = file.metadata()?.len() as usize;
+                        if len < 8 {
+                            len = 10 * 1024 * 1024;
+                            file.set_len(len as u64)?;
                         }
+
+                        let mut mmap = memmap::MmapOptions::new().map_mut(&file)?;
+                        let begin = mmap.get_mut(0).unwrap();
+                        std::ptr::write_bytes(begin, 0xff, 8);
+                        inst().start = begin as *const _ as u64;
+                        inst().end.store(u64::MAX, Ordering::Release);
+
+                        let inner = read::<BuddyAllocInner>(begin);
+                        inner.init(len);
+                        mmap.flush()?;
+                        Ok(())
                     } else {
-                        Err("Image file does not exist".to_string())
+                        Err(Error::IoError(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("Image file `{}` does not exist", filename)
+                        )))
                     }
                 }
     
                 #[inline]
                 #[track_caller]
                 fn gen() -> u32 {
-                    static_inner!(BUDDY_INNER, inner, { inner.gen })
+                    static_inner!(inst().inner, inner, { inner.gen })
                 }
     
                 #[inline]
                 #[track_caller]
                 fn tx_gen() -> u32 {
-                    static_inner!(BUDDY_INNER, inner, {
-                        inner.tx_gen += 1;
-                        inner.tx_gen
+                    // The processes which share the pool count together
+                    static_inner!(inst().inner, inner, {
+                        std::intrinsics::atomic_xadd_acqrel(&mut inner.tx_gen, 1) + 1
                     })
                 }
     
                 #[track_caller]
                 fn size() -> usize {
-                    static_inner!(BUDDY_INNER, inner, { inner.size })
+                    static_inner!(inst().inner, inner, { inner.size })
                 }
     
                 #[inline]
                 #[track_caller]
                 fn available() -> usize {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         let mut sum = 0;
                         for i in 0..inner.zone.count() {
                             sum += inner.zone[i].available();
@@ -1294,7 +3030,7 @@ macro_rules! pool {
     
                 #[track_caller]
                 fn used() -> usize {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         let mut sum = 0;
                         for i in 0..inner.zone.count() {
                             sum += inner.zone[i].used();
@@ -1303,19 +3039,399 @@ macro_rules! pool {
                     })
                 }
     
+                #[track_caller]
+                fn stats() -> PoolStats {
+                    // Holding the lock keeps the list of journals unchanged
+                    let _vdata = match unsafe { inst().vdata.lock() } {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    static_inner!(inst().inner, inner, {
+                        let mut stats = PoolStats::new(inner.size);
+                        for i in 0..inner.zone.count() {
+                            stats.add_zone(inner.zone[i].stats());
+                        }
+                        let mut off = inner.journals;
+                        while let Ok(journal) = Self::deref::<Journal>(off) {
+                            stats.journals += 1;
+                            stats.journal_pages += journal.page_count();
+                            off = journal.next_off();
+                        }
+                        stats
+                    })
+                }
+    
+                #[allow(unused_unsafe)]
+                #[track_caller]
+                fn grow(new_size: usize) -> Result<()> {
+                    if Self::is_read_only() {
+                        return Err(Error::ReadOnly);
+                    }
+                    if unsafe { inst().shared } {
+                        return Err(Error::Unsupported(
+                            "A shared pool cannot grow".to_string()
+                        ));
+                    }
+                    let grow = match unsafe { inst().grow.lock() } {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    let info = if let Some(info) = &*grow {
+                        info
+                    } else {
+                        return Err(Error::PoolNotOpen);
+                    };
+                    static_inner!(inst().inner, inner, {
+                        if new_size <= inner.size {
+                            return Ok(());
+                        }
+                        if new_size > info.reserved {
+                            return Err(Error::ReservationExceeded {
+                                requested: new_size,
+                                reserved: info.reserved
+                            });
+                        }
+                        let len = info.file.metadata()?.len();
+                        if len < new_size as u64 {
+                            info.file.set_len(new_size as u64)?;
+                        }
+                        if let Some(replica) = &info.replica {
+                            if replica.metadata()?.len() < new_size as u64 {
+                                replica.set_len(new_size as u64)?;
+                            }
+                            resize_replica(inst().start as usize, new_size);
+                        }
+                        inst().end.store(inst().start + new_size as u64 + 1, Ordering::Release);
+
+                        // The new space is added to the last zone in aligned
+                        // blocks. The pool size is updated in the same
+                        // failure-atomic section as each block, so that a crash
+                        // leaves the pool with a consistent size, and growing
+                        // can be continued by calling `grow` again.
+                        let z = inner.zone.count() - 1;
+                        let mut off = (inner.size as u64 + 7) & !7;
+                        let end = new_size as u64 & !7;
+                        while off + 8 <= end {
+                            let mut len = 1u64 << off.trailing_zeros().min(62);
+                            while off + len > end {
+                                len >>= 1;
+                            }
+                            inner.zone[z].extend(off, len as usize, false);
+                            Self::log64(Self::off_unchecked(&inner.size), off + len, z);
+                            Self::perform(z);
+                            off += len;
+                        }
+                        Ok(())
+                    })
+                }
+
+                fn set_reserved_size(size: usize) {
+                    unsafe { inst().reserve = size; }
+                }
+
+                fn set_auto_grow(step: usize) {
+                    let mut grow = match unsafe { inst().grow.lock() } {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    if let Some(info) = &mut *grow {
+                        info.step = step;
+                    }
+                }
+
+                #[track_caller]
+                fn zone_count() -> usize {
+                    static_inner!(inst().inner, inner, { inner.zone.count() })
+                }
+
+                #[allow(unused_unsafe)]
+                #[track_caller]
+                fn reconfigure_zones(count: usize) -> Result<()> {
+                    if Self::is_read_only() {
+                        return Err(Error::ReadOnly);
+                    }
+                    // Keeps other threads from starting a transaction or
+                    // accessing the pool outside a transaction, and waits for
+                    // the running ones, while the zones are being rebuilt
+                    let _quiesced = unsafe { Self::quiesce()? };
+                    static_inner!(inst().inner, inner, {
+                        if count == 0 {
+                            return Err(Error::InvalidArgument(
+                                "The number of zones should be positive".to_string()
+                            ));
+                        }
+                        if zone_quota(inner.size, count) == 0 {
+                            return Err(Error::InvalidArgument(
+                                format!("The pool is too small for {} zones", count)
+                            ));
+                        }
+                        let cnt = inner.zone.count();
+                        let mut records = vec![];
+                        for i in 0..cnt {
+                            inner.zone[i].export(&mut records);
+                        }
+
+                        // The new zone table and the staged free ranges are
+                        // kept in a single block. Taking the block splits a
+                        // free block, so there is room for a few more ranges.
+                        let table = zone_table_len(count);
+                        let cap = records.len() + 128;
+                        let len = (table + 16 + 16 * cap).next_power_of_two().max(4096);
+                        let mut block = u64::MAX;
+                        for z in 0..cnt {
+                            block = inner.zone[z].alloc_impl(len, false);
+                            if block != u64::MAX {
+                                let zone = &mut inner.zone[z];
+                                zone.log(Self::off_unchecked(&inner.reconf), block);
+                                zone.log(Self::off_unchecked(&inner.reconf_len), len as u64);
+                                zone.log(Self::off_unchecked(&inner.reconf_count), count as u64);
+                                zone.log(Self::off_unchecked(&inner.reconf_state), RECONF_ALLOCATED);
+                                zone.perform();
+                                break;
+                            }
+                        }
+                        if block == u64::MAX {
+                            return Err(Error::OutOfMemory { requested: len });
+                        }
+
+                        let mut records = vec![];
+                        let mut objects = 0;
+                        for i in 0..cnt {
+                            inner.zone[i].export(&mut records);
+                            objects = inner.zone[i].objects().wrapping_add(objects);
+                        }
+                        if inner.table_len != 0 {
+                            // The old table is not needed anymore
+                            records.push((inner.zone.base() as u64, inner.table_len as u64));
+                            objects = objects.wrapping_sub(1);
+                        }
+                        if records.len() > cap {
+                            inner.cancel_reconfigure();
+                            return Err(Error::Other(
+                                "The free space changed while reconfiguring the zones".to_string()
+                            ));
+                        }
+
+                        let stage = block + table as u64;
+                        *Self::get_mut_unchecked::<u64>(stage) = records.len() as u64;
+                        *Self::get_mut_unchecked::<u64>(stage + 8) = objects as u64;
+                        Self::deref_slice_unchecked_mut::<(u64, u64)>(stage + 16, records.len())
+                            .copy_from_slice(&records);
+                        persist(Self::get_unchecked::<u8>(stage), 16 + 16 * records.len(), true);
+                        inner.reconf_state = RECONF_STAGED;
+                        persist_obj(&inner.reconf_state, true);
+
+                        inner.finish_reconfigure();
+                        Ok(())
+                    })
+                }
+
+                #[allow(unused_unsafe)]
+                #[track_caller]
+                unsafe fn defrag<U: PSafe + Relocate<Self>>(max_moves: usize) -> Result<usize> {
+                    if Self::is_read_only() {
+                        return Err(Error::ReadOnly);
+                    }
+                    let root_off = static_inner!(inst().inner, inner, {
+                        if !inner.has_root() {
+                            return Err(Error::NoRootObject);
+                        }
+                        Self::check_root_type::<U>(inner)?;
+                        inner.root_obj
+                    });
+
+                    // No other thread may update or read an object while it
+                    // is being moved
+                    let _quiesced = Self::quiesce()?;
+                    Self::transaction(move |j| unsafe {
+                        // Collect all live blocks reachable from the root
+                        let mut r = Relocator::<Self>::new();
+                        r.add_block(root_off, mem::size_of::<U>());
+                        Self::get_unchecked::<U>(root_off).visit(&mut r);
+
+                        // Move the blocks with the highest addresses first
+                        let blocks: Vec<(u64, usize)> = r.blocks().collect();
+                        let mut moves = HashMap::<u64, u64>::new();
+                        for (off, len) in blocks.into_iter().rev() {
+                            if moves.len() == max_moves {
+                                break;
+                            }
+                            if off == root_off || r.is_pinned(off, len) {
+                                continue;
+                            }
+                            let mut log = Log::drop_on_abort(u64::MAX, 1, j);
+                            let (p, new, new_len, z) = Self::pre_alloc(len);
+                            if p.is_null() {
+                                continue;
+                            }
+                            if new > off {
+                                // There is no free block at a lower address
+                                Self::discard(z);
+                                continue;
+                            }
+                            Self::drop_on_failure(new, new_len, z);
+                            log.set(new, new_len, z);
+                            Self::perform(z);
+
+                            std::ptr::copy_nonoverlapping(
+                                Self::get_unchecked::<u8>(off) as *const u8, p, len
+                            );
+                            persist(p, len, false);
+                            Log::drop_on_commit(off, len, j);
+                            moves.insert(off, new);
+                        }
+
+                        // Rewrite the pointers to the moved blocks
+                        for link in r.links() {
+                            let val = *Self::get_unchecked::<u64>(*link);
+                            let new = match r.block_of(val).and_then(|b| {
+                                moves.get(&b).map(|n| n + (val - b))
+                            }) {
+                                Some(new) => new,
+                                None => continue,
+                            };
+                            let moved = r.block_of(*link).and_then(|b| {
+                                moves.get(&b).map(|n| n + (link - b))
+                            });
+                            let field = if let Some(moved) = moved {
+                                // The pointer itself is in a new block
+                                Self::get_mut_unchecked::<u64>(moved)
+                            } else {
+                                let field = Self::get_mut_unchecked::<u64>(*link);
+                                Log::create(field, j, Notifier::None);
+                                field
+                            };
+                            *field = new;
+                            persist(field, 8, false);
+                        }
+                        moves.len()
+                    })
+                }
+
+                #[allow(unused_unsafe)]
+                #[track_caller]
+                fn compact_to<U: PSafe + Relocate<Self>>(path: &str, size: u64) -> Result<()> {
+                    // No other thread may update the pool while its objects
+                    // are being copied
+                    let _quiesced = unsafe { Self::quiesce()? };
+                    let filename = {
+                        let vdata = match unsafe { inst().vdata.lock() } {
+                            Ok(g) => g,
+                            Err(p) => p.into_inner()
+                        };
+                        match &*vdata {
+                            Some(vdata) => vdata.filename.clone(),
+                            None => return Err(Error::PoolNotOpen),
+                        }
+                    };
+
+                    // The paths may refer to the same file in different ways,
+                    // e.g. through a relative path or a symlink
+                    if let Ok(dst) = std::fs::canonicalize(path) {
+                        if dst == std::fs::canonicalize(&filename)? {
+                            return Err(Error::InvalidArgument(
+                                "Cannot compact a pool into itself".to_string()
+                            ));
+                        }
+                    }
+
+                    // The destination may be a pool which is open in this or
+                    // another process. Its lock is held until it is replaced.
+                    let _dst = if Path::new(path).exists() {
+                        Some(Self::lock_for_write(path, 0)?)
+                    } else {
+                        None
+                    };
+                    static_inner!(inst().inner, inner, {
+                        if !inner.has_root() {
+                            return Err(Error::NoRootObject);
+                        }
+                        Self::check_root_type::<U>(inner)?;
+
+                        // Collect all live blocks reachable from the root
+                        let mut r = Relocator::<Self>::new();
+                        r.add_block(inner.root_obj, mem::size_of::<U>());
+                        Self::get_unchecked::<U>(inner.root_obj).visit(&mut r);
+
+                        // The other threads may keep reading the pool, so the
+                        // new image is built with a separate pool state which
+                        // only the current thread is bound to
+                        let src = inst().start;
+                        let mut state = Box::new(PoolState::new());
+                        let _binding = Binding::new(&mut *state as *mut PoolState);
+
+                        // The image is built in a temporary file which then
+                        // replaces `path`, so that a failure, e.g. running out
+                        // of space, does not leave a partial pool behind
+                        let tmp = format!("{}.tmp{}", path, std::process::id());
+                        let res = Self::compact_impl(
+                            src, &tmp, size, &r, inner.root_obj, inner.root_type_id, inner.root_schema, inner.gen
+                        ).and_then(|_| Ok(std::fs::rename(&tmp, path)?));
+                        if res.is_err() {
+                            let _ = std::fs::remove_file(&tmp);
+                        }
+                        res
+                    })
+                }
+    
+                #[allow(unused_unsafe)]
+                #[track_caller]
+                fn snapshot(path: &str) -> Result<()> {
+                    unsafe {
+                        // Keeps other threads from starting a transaction or
+                        // updating the pool outside a transaction while the
+                        // image is being copied
+                        let _quiesced = Self::quiesce()?;
+                        let guard = match inst().vdata.lock() {
+                            Ok(g) => g,
+                            Err(p) => p.into_inner()
+                        };
+                        let vdata = if let Some(vdata) = &*guard {
+                            vdata
+                        } else {
+                            return Err(Error::PoolNotOpen);
+                        };
+                        if Path::new(path) == Path::new(&vdata.filename) {
+                            return Err(Error::InvalidArgument(
+                                "Cannot take a snapshot of a pool into itself".to_string()
+                            ));
+                        }
+                        let len = vdata.file.metadata()?.len() as usize;
+                        // The private mapping of a read-only pool may differ
+                        // from the file after recovery
+                        let file = if Self::is_read_only() { None } else { Some(&vdata.file) };
+                        copy_image(file, &vdata.mmap[..len], path)
+                    }
+                }
+
                 #[inline]
                 fn rng() -> Range<u64> {
-                    unsafe { BUDDY_VALID_START..BUDDY_END }
+                    unsafe { inst().valid_start..inst().end.load(Ordering::Acquire) }
                 }
     
                 #[inline]
                 fn start() -> u64 {
-                    unsafe { BUDDY_START }
+                    let start = unsafe { inst().start };
+                    if start == 0 {
+                        check_bound();
+                    }
+                    start
                 }
     
+                #[inline]
+                unsafe fn bind_instance() -> usize {
+                    let state = inst() as *mut PoolState;
+                    BOUND_STATE.with(|b| b.replace(state)) as usize
+                }
+
+                #[inline]
+                unsafe fn unbind_instance(prev: usize) {
+                    BOUND_STATE.with(|b| b.set(prev as *mut PoolState));
+                }
+
                 #[inline]
                 fn end() -> u64 {
-                    unsafe { BUDDY_END }
+                    unsafe { inst().end.load(Ordering::Acquire) }
                 }
     
                 #[allow(unused_unsafe)]
@@ -1323,20 +3439,38 @@ macro_rules! pool {
                 unsafe fn pre_alloc(size: usize) -> (*mut u8, u64, usize, usize) {
                     let _perf = $crate::__cfg_stat_perf!($crate::stat::Measure::<Self>::Alloc(std::time::Instant::now()));
     
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
+                        // Starting from the home zone, steal from the
+                        // sibling zones which have enough space
                         let cpu = cpu();
                         let cnt = inner.zone.count();
                         for i in 0..cnt {
                             let z = (cpu+i)%cnt;
+                            if i != 0 && inner.zone[z].available() < size {
+                                continue;
+                            }
                             let a = inner.zone[z].alloc_impl(size, false);
                             if a != u64::MAX {
                                 return (Self::get_mut_unchecked(a), a, size, z);
                             }
                         }
-                        eprintln!(
-                            "No space left (requested = {}, available= {})",
-                            size, Self::available()
-                        );
+                        let step = Self::auto_grow_step();
+                        if step != 0 {
+                            let step = step.max(2 * size.next_power_of_two());
+                            if Self::grow(inner.size + step).is_ok() {
+                                let z = cnt - 1;
+                                let a = inner.zone[z].alloc_impl(size, false);
+                                if a != u64::MAX {
+                                    return (Self::get_mut_unchecked(a), a, size, z);
+                                }
+                            }
+                        }
+                        $crate::__cfg_verbose!({
+                            eprintln!(
+                                "No space left (requested = {}, available= {})",
+                                size, Self::available()
+                            );
+                        });
                         (std::ptr::null_mut(), u64::MAX, 0, 0)
                     })
                 }
@@ -1346,7 +3480,7 @@ macro_rules! pool {
                 unsafe fn pre_dealloc(ptr: *mut u8, size: usize) -> usize {
                     let _perf = $crate::__cfg_stat_perf!($crate::stat::Measure::<Self>::Dealloc(std::time::Instant::now()));
     
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         let off = Self::off(ptr).expect("invalid pointer");
                         let (zone,zidx) = inner.zone.from_off(off);
                         $crate::__cfg_check_access_violation!({
@@ -1366,7 +3500,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn log64(off: u64, val: u64, z: usize) {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         inner.zone[z].log(off, val);
                     })
                 }
@@ -1375,7 +3509,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn drop_on_failure(off: u64, len: usize, z: usize) {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         inner.zone[z].drop_on_failure(off, len);
                     })
                 }
@@ -1383,8 +3517,8 @@ macro_rules! pool {
                 #[inline]
                 #[track_caller]
                 fn zone(off: u64) -> usize {
-                    static_inner!(BUDDY_INNER, inner, {
-                        off as usize / inner.zone.quota()
+                    static_inner!(inst().inner, inner, {
+                        inner.zone.index_of(off)
                     })
                 }
     
@@ -1392,7 +3526,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn prepare(z: usize) {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         inner.zone[z].prepare();
                     })
                 }
@@ -1401,7 +3535,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn perform(z: usize) {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         inner.zone[z].perform();
                     })
                 }
@@ -1410,7 +3544,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn discard(z: usize) {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         inner.zone[z].discard();
                     })
                 }
@@ -1419,7 +3553,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 fn allocated(off: u64, _len: usize) -> bool {
-                    static_inner!(BUDDY_INNER, _inner, {
+                    static_inner!(inst().inner, _inner, {
                         if off >= Self::end() {
                             false
                         } else if Self::contains(off + Self::start()) {
@@ -1436,7 +3570,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 fn verify() -> bool {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         for i in 0..inner.zone.count() {
                             if !inner.zone[i].verify() {
                                 return false;
@@ -1450,7 +3584,7 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn journals_head() -> &'static u64 {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         &inner.journals
                     })
                 }
@@ -1458,11 +3592,11 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn drop_journal(journal: &mut Journal) {
-                    let _vdata = match VDATA.lock() {
+                    let _vdata = match inst().vdata.lock() {
                         Ok(g) => g,
                         Err(p) => p.into_inner()
                     };
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         let off = Self::off(journal).unwrap();
                     
                         $crate::__cfg_pin_journals!({
@@ -1486,19 +3620,55 @@ macro_rules! pool {
                 #[allow(unused_unsafe)]
                 #[track_caller]
                 unsafe fn journals<T, F: Fn(&mut HashMap<ThreadId, (u64, i32)>)->T>(f: F)->T{
-                    let mut vdata = match VDATA.lock() {
+                    let vdata = match inst().vdata.lock() {
                         Ok(g) => g,
                         Err(p) => p.into_inner()
                     };
+                    let mut vdata = Self::enter_gate(vdata);
                     if let Some(vdata) = &mut *vdata {
-                        f(&mut vdata.journals)
+                        let res = f(&mut vdata.journals);
+                        if vdata.exclusive.is_some() {
+                            // The quiescing thread waits for the transactions
+                            inst().gate.notify_all();
+                        }
+                        res
                     } else {
                         panic!("No memory pool is open or the root object is moved to a transaction. Try cloning the root object instead of moving it to a transaction.");
                     }
                 }
 
+                unsafe fn begin_access() {
+                    let vdata = match inst().vdata.lock() {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    let mut vdata = Self::enter_gate(vdata);
+                    if let Some(vdata) = &mut *vdata {
+                        *vdata.accesses.entry(std::thread::current().id()).or_insert(0) += 1;
+                    }
+                }
+
+                unsafe fn end_access() {
+                    let mut vdata = match inst().vdata.lock() {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    };
+                    if let Some(vdata) = &mut *vdata {
+                        let tid = std::thread::current().id();
+                        if let Some(cnt) = vdata.accesses.get_mut(&tid) {
+                            *cnt -= 1;
+                            if *cnt == 0 {
+                                vdata.accesses.remove(&tid);
+                            }
+                        }
+                        if vdata.exclusive.is_some() {
+                            inst().gate.notify_all();
+                        }
+                    }
+                }
+
                 unsafe fn dealloc_history() -> *mut HashSet<u64> {
-                    let mut vdata = match VDATA.lock() {
+                    let mut vdata = match inst().vdata.lock() {
                         Ok(g) => g,
                         Err(p) => p.into_inner()
                     };
@@ -1511,7 +3681,7 @@ macro_rules! pool {
     
                 #[allow(unused_unsafe,unused_braces)]
                 unsafe fn recover() {
-                    static_inner!(BUDDY_INNER, inner, {
+                    static_inner!(inst().inner, inner, {
                         let info_level = std::env::var("RECOVERY_INFO")
                             .unwrap_or("0".to_string())
                             .parse::<u32>()
@@ -1530,9 +3700,18 @@ macro_rules! pool {
                             }
                         }
     
+                        if inner.reconf_state == RECONF_STAGED {
+                            eprintln!("Continuing the reconfiguration of the zones");
+                            inner.finish_reconfigure();
+                        }
+
                         for i in 0..inner.zone.count() {
                             inner.zone[i].recover();
                         }
+
+                        if inner.reconf_state == RECONF_ALLOCATED {
+                            inner.cancel_reconfigure();
+                        }
     
                         $crate::__cfg_check_allocator_cyclic_links!({
                             debug_assert!(Self::verify());
@@ -1583,100 +3762,80 @@ macro_rules! pool {
                     })
                 }
     
-                #[allow(unused_unsafe)]
                 #[track_caller]
                 fn open<'a, U: 'a + PSafe + RootObj<Self>>(
                     path: &str,
                     flags: u32,
                 ) -> Result<RootCell<'a, U, Self>> {
-                    let slf = Self::open_no_root(path, flags)?;
-                    static_inner!(BUDDY_INNER, inner, {
-                        // Replace it with std::any::TypeId::of::<U>() when it
-                        // is available in the future for non-'static types
-                        let id = format!("{} ({})", std::any::type_name::<U>(),
-                            mem::size_of::<U>());
-                        let mut s = DefaultHasher::new();
-                        id.hash(&mut s);
-                        let id = s.finish();
-                        if !inner.has_root() {
-                            if mem::size_of::<U>() == 0 {
-                                Err("root type cannot be a ZST".to_string())
-                            } else {
-                                let root_off = Self::transaction(move |j| {
-                                    let ptr = Self::new(U::init(j), j);
-                                    Self::off_unchecked(ptr)
-                                })
-                                .unwrap();
-                                let ptr = Self::get_unchecked(root_off);
-                                inner.flags |= FLAG_HAS_ROOT;
-                                inner.root_obj = root_off;
-                                inner.root_type_id = id;
-                                persist_obj(inner, true);
-                                Ok(RootCell::new(ptr, Arc::new(slf)))
-                            }
-                        } else {
-                            if inner.root_type_id == id {
-                                Ok(RootCell::new(
-                                    Self::deref::<U>(inner.root_obj)?,
-                                    Arc::new(slf),
-                                ))
-                            } else {
-                                Err("Incompatible root type".to_string())
-                            }
-                        }
-                    })
+                    Self::open_root::<U>(path, flags, None)
                 }
     
                 #[inline]
                 fn is_open() -> bool {
-                    unsafe { BUDDY_INNER.is_some() }
+                    unsafe { inst().inner.is_some() }
+                }
+
+                #[inline]
+                fn is_read_only() -> bool {
+                    unsafe { inst().read_only }
                 }
     
-                #[allow(unused_unsafe)]
                 #[track_caller]
                 fn open_no_root(path: &str, flags: u32) -> Result<PoolGuard<Self>> {
-                    unsafe {
-                        while OPEN.compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed).is_err() {}
-                        if !Self::running_transaction() {
-                            if flags == open_flags::O_READINFO {
-                                Self::open_impl(path, true)
-                            } else if let Ok(_) = Self::apply_flags(path, flags) {
-                                let res = Self::open_impl(path, false);
-                                if res.is_ok() {
-                                    Self::recover();
-                                }
-                                res
-                            } else {
-                                OPEN.store(false, Ordering::Release);
-                                Err("Could not open file".to_string())
-                            }
-                        } else {
-                            OPEN.store(false, Ordering::Release);
-                            Err("An uncommitted transaction exists in the pool"
-                                .to_string())
-                        }
-                    }
+                    Self::open_no_root_impl(path, flags, None)
                 }
     
                 #[allow(unused_unsafe)]
                 unsafe fn close() -> Result<()> {
-                    if OPEN.load(Ordering::Acquire) {
-                        let mut vdata = match VDATA.lock() {
+                    if inst().open.load(Ordering::Acquire) {
+                        let mut vdata = match inst().vdata.lock() {
                             Ok(g) => g,
                             Err(p) => p.into_inner()
                         };
+                        if let Some(vdata) = &mut *vdata {
+                            if let Some((_, _, rmap)) = &mut vdata.replica {
+                                // A cleanly closed pool and its replica are
+                                // identical. The stores which were never
+                                // flushed reach the pool file only when it is
+                                // unmapped, so they are copied here.
+                                if let Some(inner) = inst().inner {
+                                    (*inner).flags &= !FLAG_REPLICATED;
+                                }
+                                if let Ok(m) = vdata.file.metadata() {
+                                    let len = m.len() as usize;
+                                    for (p, r) in vdata.mmap[..len].chunks(4096)
+                                        .zip(rmap[..len].chunks_mut(4096)) {
+                                        if p != r {
+                                            r.copy_from_slice(p);
+                                        }
+                                    }
+                                    let _ = rmap.flush_range(0, len);
+                                }
+                            }
+                        }
+                        remove_replica(inst().start as usize);
                         *vdata = None;
-                        BUDDY_INNER = None;
-                        OPEN.store(false, Ordering::Release);
+                        let mut grow = match inst().grow.lock() {
+                            Ok(g) => g,
+                            Err(p) => p.into_inner()
+                        };
+                        *grow = None;
+                        inst().inner = None;
+                        inst().read_only = false;
+                        if inst().shared {
+                            inst().shared = false;
+                            $crate::sync::share_locks(false);
+                        }
+                        inst().open.store(false, Ordering::Release);
                         Ok(())
                     } else {
-                        Err("Pool was already closed".to_string())
+                        Err(Error::PoolNotOpen)
                     }
                 }
     
                 fn stat_footprint() -> usize {
                     $crate::__cfg_stat_footprint!({
-                        static_inner!(BUDDY_INNER, inner, { inner.zone.stat_footprint() })
+                        static_inner!(inst().inner, inner, { inner.zone.stat_footprint() })
                     }, {
                         unimplemented!()
                     })
@@ -1688,7 +3847,7 @@ macro_rules! pool {
                     println!("       Used: {} bytes", Self::used());
                     println!("  Available: {} bytes", Self::available());
     
-                    static_inner!(BUDDY_INNER, inner, { 
+                    static_inner!(inst().inner, inner, { 
                         for i in 0..inner.zone.count() {
                             println!("{:=^80}", format!(" Persistent Memory Zone #{} ", i));
                             println!("       Total      {}", inner.zone[i].size());
@@ -1715,6 +3874,30 @@ macro_rules! pool {
             /// Compact form of [`PMutex`](../../sync/struct.PMutex.html)
             /// `<T,`[`Allocator`](./struct.Allocator.html)`>`.
             pub type PMutex<T> = $crate::sync::PMutex<T, $name>;
+
+            /// Compact form of [`PRwLock`](../../sync/struct.PRwLock.html)
+            /// `<T,`[`Allocator`](./struct.Allocator.html)`>`.
+            pub type PRwLock<T> = $crate::sync::PRwLock<T, $name>;
+
+            /// Compact form of [`PCondvar`](../../sync/struct.PCondvar.html)
+            /// `<`[`Allocator`](./struct.Allocator.html)`>`.
+            pub type PCondvar = $crate::sync::PCondvar<$name>;
+
+            /// Compact form of [`PAtomicU64`](../../sync/struct.PAtomicU64.html)
+            /// `<`[`Allocator`](./struct.Allocator.html)`>`.
+            pub type PAtomicU64 = $crate::sync::PAtomicU64<$name>;
+
+            /// Compact form of [`PAtomicUsize`](../../sync/struct.PAtomicUsize.html)
+            /// `<`[`Allocator`](./struct.Allocator.html)`>`.
+            pub type PAtomicUsize = $crate::sync::PAtomicUsize<$name>;
+
+            /// Compact form of [`PAtomicBool`](../../sync/struct.PAtomicBool.html)
+            /// `<`[`Allocator`](./struct.Allocator.html)`>`.
+            pub type PAtomicBool = $crate::sync::PAtomicBool<$name>;
+
+            /// Compact form of [`PWord`](../../sync/struct.PWord.html)
+            /// `<`[`Allocator`](./struct.Allocator.html)`>`.
+            pub type PWord = $crate::sync::PWord<$name>;
     
             /// Compact form of [`PCell`](../../cell/struct.PCell.html)
             /// `<T,`[`Allocator`](./struct.Allocator.html)`>`.
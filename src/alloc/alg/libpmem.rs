@@ -1,2 +1,1121 @@
+//! Allocation algorithm using libpmem-style primitives
+//!
+//! This module provides native Rust implementations of the essential libpmem
+//! functions ([`pmem_map_file`], [`pmem_persist`], [`pmem_memcpy_persist`],
+//! etc.) and [`PmemAlg`], an allocation algorithm built on top of them. The
+//! pools of this algorithm are mapped using [`pmem_map_file`]. Unlike
+//! [`BuddyAlg`] which rounds every request up to a power of two, `PmemAlg`
+//! keeps an address-ordered list of free chunks and serves requests with the
+//! best fitting chunk, merging adjacent chunks on deallocation. It wastes
+//! less space for odd-sized objects. A pool type with this algorithm can be
+//! defined using [`pmem_pool!()`].
+//!
+//! [`BuddyAlg`]: ./struct.BuddyAlg.html
+//! [`pmem_pool!()`]: ../macro.pmem_pool.html
 
-// To be implemented
\ No newline at end of file
+use crate::alloc::*;
+use crate::result::Result;
+use crate::Error;
+use crate::utils::*;
+use super::buddy::free_ranges;
+use std::fs::OpenOptions;
+use std::marker::PhantomData;
+use std::{mem, ptr};
+
+/// `pmem_map_file` flag: Creates the file if it does not exist
+pub const PMEM_FILE_CREATE: u32 = 1 << 0;
+
+/// `pmem_map_file` flag: Fails if the file already exists (with
+/// [`PMEM_FILE_CREATE`])
+pub const PMEM_FILE_EXCL: u32 = 1 << 1;
+
+/// `pmem_map_file` flag: Does not allocate the blocks of a new file
+pub const PMEM_FILE_SPARSE: u32 = 1 << 2;
+
+/// `pmem_map_file` flag: Maps the file privately (copy-on-write) for reading,
+/// so that no changes are written to it
+pub const PMEM_FILE_PRIVATE: u32 = 1 << 3;
+
+/// A memory mapped file obtained from [`pmem_map_file`]
+///
+/// The file is unmapped when it goes out of scope.
+pub struct PmemFile {
+    mmap: memmap::MmapMut,
+    len: usize,
+    is_pmem: bool,
+}
+
+impl PmemFile {
+    /// Returns the start address of the mapping
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.mmap.as_ptr() as *mut u8
+    }
+
+    /// Returns the length of the mapping
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the mapping is on persistent memory, so that flushing
+    /// the CPU caches is enough to make the data durable
+    pub fn is_pmem(&self) -> bool {
+        self.is_pmem
+    }
+
+    /// Makes the whole mapping durable using `msync`
+    pub fn msync(&self) -> Result<()> {
+        Ok(self.mmap.flush()?)
+    }
+
+    /// Consumes the file and returns the mapping
+    pub(crate) fn into_mmap(self) -> memmap::MmapMut {
+        self.mmap
+    }
+}
+
+/// Maps a file into memory
+///
+/// If [`PMEM_FILE_CREATE`] is given and `len` is not zero, the file is
+/// created (or truncated) with the size of `len` bytes, and its blocks are
+/// allocated unless [`PMEM_FILE_SPARSE`] is also given. Otherwise, the whole
+/// existing file is mapped; if `len` is larger than the file, the rest of the
+/// mapping reserves address space for the file to grow in place. With
+/// [`PMEM_FILE_PRIVATE`], the file is opened for reading and the mapping is
+/// copy-on-write.
+///
+/// Similar to libpmem, whether the mapping is on persistent memory can be
+/// forced using `PMEM_IS_PMEM_FORCE` environment variable. Otherwise, the
+/// file is considered to be on persistent memory if it is on a file system
+/// mounted with `dax` option.
+pub fn pmem_map_file(path: &str, len: usize, flags: u32) -> Result<PmemFile> {
+    let create = flags & PMEM_FILE_CREATE != 0 && len != 0;
+    let private = flags & PMEM_FILE_PRIVATE != 0;
+    if create && private {
+        return Err(Error::InvalidArgument(
+            "Cannot create a file for a private mapping".to_string()
+        ));
+    }
+    let mut opts = OpenOptions::new();
+    opts.read(true).write(!private);
+    if create {
+        if flags & PMEM_FILE_EXCL != 0 {
+            opts.create_new(true);
+        } else {
+            opts.create(true);
+        }
+    }
+    let file = opts.open(path)?;
+    let len = if create {
+        file.set_len(len as u64)?;
+        if flags & PMEM_FILE_SPARSE == 0 {
+            allocate_blocks(&file, len)?;
+        }
+        len
+    } else {
+        let file_len = file.metadata()?.len() as usize;
+        if file_len == 0 { 0 } else { file_len.max(len) }
+    };
+    if len == 0 {
+        return Err(Error::InvalidArgument(
+            format!("Cannot map an empty file `{}`", path)
+        ));
+    }
+    let mmap = unsafe {
+        if private {
+            memmap::MmapOptions::new()
+                .len(len)
+                .map_copy(&file)?
+        } else {
+            memmap::MmapOptions::new()
+                .len(len)
+                .map_mut(&file)?
+        }
+    };
+    Ok(PmemFile {
+        mmap,
+        len,
+        is_pmem: is_dax(path),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn allocate_blocks(file: &std::fs::File, len: usize) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let res = unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) };
+    if res != 0 {
+        Err(Error::IoError(std::io::Error::from_raw_os_error(res)))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allocate_blocks(_file: &std::fs::File, _len: usize) -> Result<()> {
+    Ok(())
+}
+
+fn is_dax(path: &str) -> bool {
+    if let Ok(v) = std::env::var("PMEM_IS_PMEM_FORCE") {
+        return v == "1";
+    }
+    let path = match std::fs::canonicalize(path) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    let mounts = match std::fs::read_to_string("/proc/mounts") {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    let mut best: Option<(usize, bool)> = None;
+    for line in mounts.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || !path.starts_with(fields[1]) {
+            continue;
+        }
+        if best.map_or(true, |(len, _)| fields[1].len() > len) {
+            let dax = fields[3].split(',').any(|o| o == "dax" || o == "dax=always");
+            best = Some((fields[1].len(), dax));
+        }
+    }
+    best.map_or(false, |(_, dax)| dax)
+}
+
+
+/// Flushes the cache lines of the given range without waiting for them
+#[inline]
+pub fn pmem_flush(addr: *const u8, len: usize) {
+    crate::ll::clflush(addr, len, false);
+}
+
+/// Waits for the previous flushes and non-temporal stores to complete
+#[inline]
+pub fn pmem_drain() {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        std::arch::x86_64::_mm_sfence();
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Makes the given range durable
+#[inline]
+pub fn pmem_persist(addr: *const u8, len: usize) {
+    pmem_flush(addr, len);
+    pmem_drain();
+}
+
+/// Copies `len` bytes from `src` to `dst` using non-temporal stores without
+/// waiting for them to complete
+///
+/// # Safety
+///
+/// Both ranges should be valid and should not overlap.
+pub unsafe fn pmem_memcpy_nodrain(dst: *mut u8, src: *const u8, len: usize) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::_mm_stream_si64;
+
+        let (mut d, mut s, mut n) = (dst, src, len);
+
+        // The unaligned head is copied with regular stores
+        let head = ((8 - (d as usize & 7)) & 7).min(n);
+        if head != 0 {
+            ptr::copy_nonoverlapping(s, d, head);
+            pmem_flush(d, head);
+            d = d.add(head);
+            s = s.add(head);
+            n -= head;
+        }
+        while n >= 8 {
+            _mm_stream_si64(d as *mut i64, ptr::read_unaligned(s as *const i64));
+            d = d.add(8);
+            s = s.add(8);
+            n -= 8;
+        }
+        if n != 0 {
+            ptr::copy_nonoverlapping(s, d, n);
+            pmem_flush(d, n);
+        }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        ptr::copy_nonoverlapping(src, dst, len);
+        pmem_flush(dst, len);
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst` using non-temporal stores and makes
+/// them durable
+///
+/// # Safety
+///
+/// Both ranges should be valid and should not overlap.
+pub unsafe fn pmem_memcpy_persist(dst: *mut u8, src: *const u8, len: usize) {
+    pmem_memcpy_nodrain(dst, src, len);
+    pmem_drain();
+}
+
+/// Fills `len` bytes at `dst` with `c` using non-temporal stores without
+/// waiting for them to complete
+///
+/// # Safety
+///
+/// The range should be valid.
+pub unsafe fn pmem_memset_nodrain(dst: *mut u8, c: u8, len: usize) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use std::arch::x86_64::_mm_stream_si64;
+
+        let (mut d, mut n) = (dst, len);
+        let pattern = i64::from_ne_bytes([c; 8]);
+
+        let head = ((8 - (d as usize & 7)) & 7).min(n);
+        if head != 0 {
+            ptr::write_bytes(d, c, head);
+            pmem_flush(d, head);
+            d = d.add(head);
+            n -= head;
+        }
+        while n >= 8 {
+            _mm_stream_si64(d as *mut i64, pattern);
+            d = d.add(8);
+            n -= 8;
+        }
+        if n != 0 {
+            ptr::write_bytes(d, c, n);
+            pmem_flush(d, n);
+        }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        ptr::write_bytes(dst, c, len);
+        pmem_flush(dst, len);
+    }
+}
+
+/// Fills `len` bytes at `dst` with `c` using non-temporal stores and makes
+/// them durable
+///
+/// # Safety
+///
+/// The range should be valid.
+pub unsafe fn pmem_memset_persist(dst: *mut u8, c: u8, len: usize) {
+    pmem_memset_nodrain(dst, c, len);
+    pmem_drain();
+}
+
+#[repr(C)]
+/// Free chunk header
+///
+/// The first 16 bytes of each free chunk keeps the offset of the next free
+/// chunk and the length of the chunk.
+struct Chunk {
+    /// Next chunk offset (`u64::MAX` is NULL)
+    next: u64,
+
+    /// Length of the chunk in bytes
+    len: u64,
+}
+
+/// The allocation granularity which is also the smallest chunk size
+const GRANULE: usize = mem::size_of::<Chunk>();
+
+#[inline]
+fn round_up(len: usize) -> usize {
+    (len.max(1) + GRANULE - 1) & !(GRANULE - 1)
+}
+
+/// Returns the alignment of a block of `len` bytes, which is the largest
+/// power of two that divides `len`. The alignment of a type never exceeds
+/// it, which is what the buddy allocator guarantees for it.
+#[inline]
+fn align_of_len(len: usize) -> u64 {
+    let len = len.max(1);
+    (len & len.wrapping_neg()).max(GRANULE) as u64
+}
+
+#[repr(C)]
+/// Best-fit Allocation Algorithm with libpmem-style persistence
+///
+/// It keeps an address-ordered list of free chunks. Allocation takes the
+/// smallest chunk that fits the request and splits the remaining part as a
+/// new free chunk. Deallocation merges the chunk with its adjacent free
+/// chunks. All sizes are rounded up to 16 bytes, and each block is aligned
+/// to the largest power of two that divides the requested size; the padding
+/// before an aligned block remains free.
+///
+/// The changes to the free list are failure-atomic and follow the same
+/// protocol as [`BuddyAlg`]: they are first written to an auxiliary redo log,
+/// and then drained with non-temporal stores when [`perform`] is called.
+///
+/// [`BuddyAlg`]: ./struct.BuddyAlg.html
+/// [`perform`]: #method.perform
+pub struct PmemAlg<A: MemPool> {
+    /// Offset of the first free chunk
+    head: u64,
+
+    /// Total available space in bytes
+    available: usize,
+
+    /// The zone size in bytes
+    size: usize,
+
+    /// Number of allocated blocks
+    objects: u64,
+
+    /// An axillary ring list for allocation and recovery
+    aux: Ring<(u64, u64), 128>,
+
+    /// Low-level 64-bit logs for allocation and recovery
+    log64: Ring<(u64, u64), 8>,
+
+    /// Low-level `DropOnFailure` logs for recovery
+    drop_log: Ring<(u64, usize), 8>,
+
+    /// Indicates that it is draining `aux`
+    aux_valid: bool,
+
+    /// Log of available space
+    available_log: usize,
+
+    #[cfg(feature = "stat_footprint")]
+    /// The stat_footprint of memory usage in bytes
+    foot_print: usize,
+
+    #[cfg(not(any(feature = "no_pthread", windows)))]
+    /// A mutex for atomic operations
+    mutex: (libc::pthread_mutex_t, libc::pthread_mutexattr_t),
+
+    #[cfg(any(feature = "no_pthread", windows))]
+    /// A mutex for atomic operations
+    mutex: (u64, u64),
+
+    // Marker
+    phantom: PhantomData<A>,
+}
+
+impl<A: MemPool> PmemAlg<A> {
+    /// Maps `len` bytes of the pool file at `path` using [`pmem_map_file`]
+    ///
+    /// The caller keeps `_file` open and locked while the pool is mapped. A
+    /// `read_only` pool is mapped with [`PMEM_FILE_PRIVATE`].
+    pub fn map(
+        path: &str,
+        _file: &std::fs::File,
+        len: usize,
+        read_only: bool
+    ) -> Result<memmap::MmapMut> {
+        let flags = if read_only { PMEM_FILE_PRIVATE } else { 0 };
+        Ok(pmem_map_file(path, len, flags)?.into_mmap())
+    }
+
+    /// Pool Initialization with a given device size
+    pub fn init(&mut self, base: u64, size: usize) {
+        let start = round_up(base as usize) as u64;
+        let end = (base + size as u64) & !(GRANULE as u64 - 1);
+        let len = end.saturating_sub(start) as usize;
+        self.size = len;
+        self.available = len;
+        self.available_log = len;
+        self.objects = 0;
+        self.aux_valid = false;
+        self.log64.clear();
+        self.drop_log.clear();
+        self.aux.clear();
+
+        if len == 0 {
+            self.head = u64::MAX;
+        } else {
+            self.head = start;
+            let c = Self::chunk(start);
+            c.next = u64::MAX;
+            c.len = len as u64;
+            pmem_persist(c as *const Chunk as *const u8, GRANULE);
+        }
+
+        #[cfg(not(any(feature = "no_pthread", windows)))] unsafe {
+        crate::sync::init_lock(&mut self.mutex.0, &mut self.mutex.1);
+        }
+
+        #[cfg(any(feature = "no_pthread", windows))] {
+        self.mutex = (0, 0); }
+    }
+
+    #[inline]
+    fn in_range(off: u64) -> bool {
+        (off < u64::MAX - A::start()) && (off + A::start() < A::end())
+    }
+
+    #[inline]
+    #[track_caller]
+    fn chunk<'a>(off: u64) -> &'a mut Chunk {
+        debug_assert!(Self::in_range(off), "off(0x{:x}) out of range", off);
+        unsafe { read_addr(A::start() + off) }
+    }
+
+    #[inline]
+    fn word<'a>(off: u64) -> &'a mut u64 {
+        unsafe { read_addr(A::start() + off) }
+    }
+
+    #[inline]
+    fn off_of<T>(x: &T) -> u64 {
+        x as *const T as u64 - A::start()
+    }
+
+    #[inline]
+    fn lock(&mut self) {
+        unsafe {
+            #[cfg(not(any(feature = "no_pthread", windows)))]
+            libc::pthread_mutex_lock(&mut self.mutex.0);
+
+            // The lock is recursive like the pthread mutex
+            #[cfg(any(feature = "no_pthread", windows))] {
+                let tid = crate::sync::current_tid();
+                if std::intrinsics::atomic_load_acq(&self.mutex.0) != tid {
+                    while !std::intrinsics::atomic_cxchg_acqrel(&mut self.mutex.0, 0, tid).1 {}
+                }
+                self.mutex.1 += 1;
+            }
+        }
+    }
+
+    #[inline]
+    fn unlock(&mut self) {
+        unsafe {
+            #[cfg(not(any(feature = "no_pthread", windows)))]
+            libc::pthread_mutex_unlock(&mut self.mutex.0);
+
+            #[cfg(any(feature = "no_pthread", windows))] {
+                debug_assert_ne!(self.mutex.1, 0, "unlocking an unlocked mutex");
+                self.mutex.1 -= 1;
+                if self.mutex.1 == 0 {
+                    std::intrinsics::atomic_store_rel(&mut self.mutex.0, 0);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    /// Adds a new low-level 64-bit log entry
+    pub unsafe fn log(&mut self, off: u64, data: u64) {
+        self.log64.push((off, data));
+    }
+
+    #[inline]
+    /// Adds a new low-level `DropOnFailure` log entry
+    pub unsafe fn drop_on_failure(&mut self, off: u64, len: usize) {
+        self.drop_log.push((off, len));
+    }
+
+    #[inline]
+    /// Adds a new entry to the auxiliary list of changes
+    pub unsafe fn aux_push(&mut self, off: u64, data: u64) {
+        self.aux.push((off, data));
+    }
+
+    /// Drain the auxiliary list of changes
+    ///
+    /// The changes are written using non-temporal stores, and they are
+    /// durable when this function returns.
+    pub fn drain_aux(&mut self) {
+        pmem_drain();
+
+        self.aux_valid = true;
+        self.aux.foreach(|(off, data)| unsafe {
+            let w = Self::word(off) as *mut u64 as *mut u8;
+            pmem_memcpy_nodrain(w, &data as *const u64 as *const u8, 8);
+        });
+        self.aux.clear();
+        self.log64.foreach(|(off, data)| unsafe {
+            let w = Self::word(off) as *mut u64 as *mut u8;
+            pmem_memcpy_nodrain(w, &data as *const u64 as *const u8, 8);
+        });
+        self.log64.clear();
+        self.available = self.available_log;
+        pmem_drain();
+    }
+
+    #[inline(always)]
+    /// Begins a failure-atomic section
+    pub unsafe fn prepare(&mut self) {
+        self.lock();
+        self.log64.clear();
+        self.aux_valid = true;
+    }
+
+    #[inline]
+    /// Materializes the changes in the auxiliary list and clears the drop log
+    /// records
+    pub unsafe fn perform(&mut self) {
+        self.drain_aux();
+        self.drop_log.clear();
+        self.aux_valid = false;
+        self.unlock();
+    }
+
+    #[inline]
+    /// Discards the changes in the auxiliary buffer
+    pub fn discard(&mut self) {
+        self.aux.clear();
+        self.log64.clear();
+        self.drop_log.clear();
+        self.available_log = self.available;
+        self.unlock();
+    }
+
+    /// Generates required changes to the metadata for allocating a new memory
+    /// block with the size `len`, and materialize them by calling
+    /// [`drain_aux`](#methods.drain_aux) according to the `perform` argument.
+    /// If successful, it returns the offset of the available free block.
+    /// Otherwise, `u64::MAX` is returned.
+    pub unsafe fn alloc_impl(&mut self, len: usize, perform: bool) -> u64 {
+        self.lock();
+        let align = align_of_len(len);
+        let len = round_up(len);
+
+        if len > self.available {
+            self.discard();
+            return u64::MAX;
+        }
+
+        // Find the smallest chunk that fits an aligned block
+        let mut best: Option<(u64, u64, usize)> = None;
+        let mut link = Self::off_of(&self.head);
+        let mut curr = self.head;
+        while curr != u64::MAX {
+            let c = Self::chunk(curr);
+            let clen = c.len as usize;
+            let pad = (((curr + align - 1) & !(align - 1)) - curr) as usize;
+            if clen >= pad + len && best.map_or(true, |(_, _, b)| clen < b) {
+                best = Some((link, curr, clen));
+                if clen == len {
+                    break;
+                }
+            }
+            link = curr;
+            curr = c.next;
+            debug_assert_ne!(curr, link, "Cyclic link in alloc_impl");
+        }
+
+        match best {
+            Some((link, chunk, clen)) => {
+                let off = (chunk + align - 1) & !(align - 1);
+                let pad = (off - chunk) as usize;
+
+                #[cfg(feature = "verbose")]
+                debug_alloc::<A>(off, len, self.used(), self.used() + len);
+
+                // All sizes and offsets are multiples of GRANULE, so the
+                // padding and the remaining part are large enough to be chunks
+                let next = Self::chunk(chunk).next;
+                let rest = clen - pad - len;
+                let after = if rest > 0 {
+                    let r = off + len as u64;
+                    self.aux_push(r, next);
+                    self.aux_push(r + 8, rest as u64);
+                    r
+                } else {
+                    next
+                };
+                if pad > 0 {
+                    self.aux_push(chunk, after);
+                    self.aux_push(chunk + 8, pad as u64);
+                } else {
+                    self.aux_push(link, after);
+                }
+                self.available_log = self.available - len;
+                self.aux_push(Self::off_of(&self.objects), self.objects.wrapping_add(1));
+
+                self.aux.sync_all();
+                if perform {
+                    self.perform();
+                }
+
+                #[cfg(feature = "stat_footprint")]
+                {
+                    let usage = self.size - self.available_log;
+                    if usage > self.foot_print {
+                        self.foot_print = usage;
+                    }
+                }
+
+                off
+            }
+            None => {
+                #[cfg(feature = "verbose")]
+                eprintln!(
+                    "Cannot find memory slot of size {} (available: {})",
+                    len,
+                    self.available()
+                );
+                self.discard();
+                u64::MAX
+            }
+        }
+    }
+
+    /// Generates required changes to the metadata for reclaiming the memory
+    /// block at offset `off` with the size of `len`, and materialize them by
+    /// calling [`drain_aux`](#methods.drain_aux) according to the `perform`
+    /// argument.
+    pub unsafe fn dealloc_impl(&mut self, off: u64, len: usize, perform: bool) {
+        self.lock();
+        let len = round_up(len);
+
+        #[cfg(feature = "verbose")]
+        debug_dealloc::<A>(off, len, self.used(), self.used() - len);
+
+        self.available_log = self.available;
+        self.free_impl(off, len as u64);
+        self.aux_push(Self::off_of(&self.objects), self.objects.wrapping_sub(1));
+
+        self.aux.sync_all();
+        if perform {
+            self.perform();
+        }
+    }
+
+    unsafe fn free_impl(&mut self, off: u64, len: u64) {
+        let mut prev: Option<u64> = None;
+        let mut curr = self.head;
+        while curr != u64::MAX && curr < off {
+            prev = Some(curr);
+            curr = Self::chunk(curr).next;
+        }
+        debug_assert_ne!(curr, off, "Double free @{}", off);
+
+        // Merge with the next chunk if they are adjacent
+        let mut end = off + len;
+        let mut next = curr;
+        if curr != u64::MAX && curr == end {
+            let c = Self::chunk(curr);
+            end += c.len;
+            next = c.next;
+        }
+
+        // Merge with the previous chunk if they are adjacent
+        if let Some(p) = prev {
+            let c = Self::chunk(p);
+            if p + c.len == off {
+                self.aux_push(p, next);
+                self.aux_push(p + 8, end - p);
+                self.available_log += len as usize;
+                return;
+            }
+        }
+
+        let link = match prev {
+            Some(p) => p,
+            None => Self::off_of(&self.head),
+        };
+        self.aux_push(off, next);
+        self.aux_push(off + 8, end - off);
+        self.aux_push(link, off);
+        self.available_log += len as usize;
+    }
+
+    /// Adds the memory block at offset `off` with the size of `len` to the
+    /// free list, and materialize the changes by calling
+    /// [`drain_aux`](#methods.drain_aux) according to the `perform` argument.
+    /// The size of the zone grows by the usable part of the block.
+    pub unsafe fn extend(&mut self, off: u64, len: usize, perform: bool) {
+        self.lock();
+        let start = round_up(off as usize) as u64;
+        let end = (off + len as u64) & !(GRANULE as u64 - 1);
+        if end <= start {
+            self.unlock();
+            return;
+        }
+        let len = end - start;
+
+        let mut last: Option<u64> = None;
+        let mut curr = self.head;
+        while curr != u64::MAX {
+            last = Some(curr);
+            curr = Self::chunk(curr).next;
+        }
+        debug_assert!(last.map_or(true, |l| l < start), "extending with a used block");
+
+        match last {
+            Some(l) if l + Self::chunk(l).len == start => {
+                self.aux_push(l + 8, Self::chunk(l).len + len);
+            }
+            _ => {
+                let link = last.unwrap_or(Self::off_of(&self.head));
+                self.aux_push(start, u64::MAX);
+                self.aux_push(start + 8, len);
+                self.aux_push(link, start);
+            }
+        }
+        self.available_log = self.available + len as usize;
+        self.log(Self::off_of(&self.size), self.size as u64 + len);
+
+        self.aux.sync_all();
+        self.log64.sync_all();
+        if perform {
+            self.perform();
+        }
+    }
+
+    /// Determines if the given address range is allocated
+    pub fn is_allocated(&mut self, off: u64, len: usize) -> bool {
+        self.lock();
+
+        if !self.aux.is_empty() {
+            self.unlock();
+            return true;
+        }
+
+        let end = off + len as u64;
+        let mut curr = self.head;
+        while curr != u64::MAX {
+            let c = Self::chunk(curr);
+            if off < curr + c.len && curr < end {
+                self.unlock();
+                return false;
+            }
+            if curr >= end {
+                break;
+            }
+            curr = c.next;
+        }
+        self.unlock();
+        true
+    }
+
+    /// Starts the recovery procedure. If the crash happened while draining the
+    /// auxiliary buffer, it continues draining it and making the remaining
+    /// changes. Then, it reclaims the allocations in the drop log.
+    pub fn recover(&mut self) {
+        #[cfg(not(any(feature = "no_pthread", windows)))] unsafe {
+        crate::sync::init_lock(&mut self.mutex.0, &mut self.mutex.1);
+        }
+
+        #[cfg(any(feature = "no_pthread", windows))] {
+        self.mutex = (0, 0); }
+
+        if self.aux_valid {
+            #[cfg(debug_assertions)]
+            eprintln!("Crashed while the allocator was operating");
+
+            // continue draining
+            self.drain_aux();
+
+            // drop unnecessary allocations
+            if !self.drop_log.is_empty() {
+                eprintln!("Dropping unnecessary allocations");
+                unsafe {
+                    let self_mut = self as *mut Self;
+                    self.drop_log.drain_atomic(|(off, len)| {
+                        (*self_mut).dealloc_impl(off, len, false);
+                    }, || {
+                        (*self_mut).drain_aux();
+                        (*self_mut).discard();
+                    });
+                }
+                self.drop_log.clear();
+            }
+
+            #[cfg(debug_assertions)]
+            self.check(module_path!());
+        } else {
+            self.aux.clear();
+            self.log64.clear();
+            self.drop_log.clear();
+        }
+        self.aux_valid = false;
+    }
+
+    pub fn recovery_info(&self, info_level: u32) -> String {
+        let mut res = format!("Crashed while operating: {}\n",
+            if self.aux_valid { "Yes" } else { "No" });
+        if info_level > 1 {
+            res += &format!("Redo Operation Logs (aux): {}\n", self.aux.len());
+            res += &format!("Redo Logs (log64):         {}\n", self.log64.len());
+            res += &format!("Drop Logs:                 {}\n", self.drop_log.len());
+        }
+        if info_level > 2 {
+            self.aux.foreach(|(off, data)| {
+                res += &format!("  aux @({:x}) {:x} -> {:x}\n", off, Self::word(off), data);
+            });
+            self.log64.foreach(|(off, data)| {
+                res += &format!("  log @({:x}) {:x} -> {:x}\n", off, Self::word(off), data);
+            });
+            self.drop_log.foreach(|(off, len)| {
+                res += &format!("  drop ({:x}; {})\n", off, len);
+            });
+        }
+        res
+    }
+
+    #[inline]
+    /// Returns the pool size
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    #[inline]
+    /// Returns the total available space in the pool
+    pub fn available(&self) -> usize {
+        self.available
+    }
+
+    #[inline]
+    /// Returns the total number of bytes used from the pool
+    pub fn used(&self) -> usize {
+        self.size - self.available
+    }
+
+    #[cfg(feature = "stat_footprint")]
+    /// Returns the total number of bytes written to the pool. It may exceed the
+    /// pool size as it does not subtract the reclaimed space after being used.
+    pub fn stat_footprint(&self) -> usize {
+        self.foot_print
+    }
+
+    #[inline]
+    /// Returns the number of allocated blocks
+    pub fn objects(&self) -> usize {
+        self.objects as usize
+    }
+
+    /// Returns the statistics of the zone including the free-list histogram
+    pub fn stats(&mut self) -> ZoneStats {
+        self.lock();
+        let mut stats = ZoneStats::new(self.size, self.available, self.objects as usize);
+        let mut curr = self.head;
+        while curr != u64::MAX {
+            let c = Self::chunk(curr);
+            stats.add_free_block(c.len as usize);
+            curr = c.next;
+        }
+        self.unlock();
+        stats
+    }
+
+    /// Appends the free chunks of the zone to `out` as `(offset, length)`
+    /// pairs. See [`BuddyAlg::export`](./struct.BuddyAlg.html#method.export).
+    pub fn export(&mut self, out: &mut Vec<(u64, u64)>) {
+        self.lock();
+        let mut curr = self.head;
+        while curr != u64::MAX {
+            let c = Self::chunk(curr);
+            out.push((curr, c.len));
+            curr = c.next;
+        }
+        self.unlock();
+    }
+
+    /// Rebuilds the zone over the address range `[start, end)` from the free
+    /// ranges in `records`. The changes are written and flushed directly. See
+    /// [`BuddyAlg::import`](./struct.BuddyAlg.html#method.import).
+    pub unsafe fn import(&mut self, start: u64, end: u64, records: &[(u64, u64)], objects: usize) {
+        let mut last: Option<u64> = None;
+        self.head = u64::MAX;
+        self.available = 0;
+        for (s, e) in free_ranges(records, start, end) {
+            let s = round_up(s as usize) as u64;
+            let e = e & !(GRANULE as u64 - 1);
+            if e <= s {
+                continue;
+            }
+            let c = Self::chunk(s);
+            c.next = u64::MAX;
+            c.len = e - s;
+            pmem_persist(c as *const Chunk as *const u8, GRANULE);
+            if let Some(l) = last {
+                let w = Self::word(l);
+                *w = s;
+                pmem_persist(w as *const u64 as *const u8, 8);
+            } else {
+                self.head = s;
+            }
+            last = Some(s);
+            self.available += c.len as usize;
+        }
+        self.size = (end - start) as usize;
+        self.available_log = self.available;
+        self.objects = objects as u64;
+        self.aux.clear();
+        self.log64.clear();
+        self.drop_log.clear();
+        self.aux_valid = false;
+        pmem_persist(self as *const Self as *const u8, mem::size_of::<Self>());
+    }
+
+    pub fn check(&self, f: &str) {
+        let mut curr = self.head;
+        while curr != u64::MAX {
+            let next = Self::chunk(curr).next;
+            assert!(next == u64::MAX || next > curr, "Unordered free list in checking {}", f);
+            curr = next;
+        }
+    }
+
+    pub fn verify(&mut self) -> bool {
+        if std::env::var("VERIFY").is_err() { return true; }
+        self.lock();
+        let mut curr = self.head;
+        let mut sum = 0;
+        while curr != u64::MAX {
+            if !Self::in_range(curr) {
+                self.unlock();
+                eprintln!("Verification Failed: Invalid chunk address 0x{:x}", curr);
+                return false;
+            }
+            let c = Self::chunk(curr);
+            sum += c.len as usize;
+            if c.next != u64::MAX && c.next < curr + c.len {
+                self.unlock();
+                eprintln!("Verification Failed: Overlapping chunks at 0x{:x}", curr);
+                return false;
+            }
+            curr = c.next;
+        }
+        self.unlock();
+        if sum != self.available {
+            eprintln!("Verification Failed: Free space mismatch ({} != {})", sum, self.available);
+            return false;
+        }
+        true
+    }
+
+    /// Prints the free list
+    pub fn print(&self) {
+        println!();
+        let mut curr = self.head;
+        while curr != u64::MAX {
+            let c = Self::chunk(curr);
+            if A::contains(curr + A::start()) {
+                print!("({}:{})", curr, curr + c.len - 1);
+            } else {
+                print!("(ERR)");
+                break;
+            }
+            curr = c.next;
+        }
+        println!();
+    }
+}
+
+#[macro_export]
+/// Defines a new pool type with the [`PmemAlg`] allocation algorithm
+///
+/// It generates the same module as [`pool!()`] does, with the difference
+/// that the free space is managed by the best-fit algorithm in [`PmemAlg`]
+/// rather than the buddy allocator, and the pool file is mapped using
+/// [`pmem_map_file`].
+///
+/// # Examples
+///
+/// ```
+/// # fn main() {
+/// corundum::pmem_pool!(my_alloc);
+/// use my_alloc::*;
+///
+/// type P = Allocator;
+///
+/// let _pool = P::open_no_root("pmem.pool", O_CF).unwrap();
+///
+/// P::transaction(|j| {
+///     let temp = Pbox::new([0u8; 100], j);
+/// }).unwrap();
+/// # }
+/// ```
+///
+/// [`PmemAlg`]: ./struct.PmemAlg.html
+/// [`pool!()`]: ./macro.pool.html
+/// [`pmem_map_file`]: ./alloc/fn.pmem_map_file.html
+macro_rules! pmem_pool {
+    ($mod:ident, $name:ident) => {
+        $crate::pool!($mod, $name, PmemAlg);
+    };
+    ($mod:ident) => {
+        $crate::pool!($mod, Allocator, PmemAlg);
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::open_flags::*;
+
+    crate::pmem_pool!(pmem);
+    type P = pmem::Allocator;
+
+    #[test]
+    fn memcpy_persist() {
+        let src: Vec<u8> = (0..100).collect();
+        let mut dst = vec![0u8; 103];
+        unsafe {
+            pmem_memcpy_persist(dst.as_mut_ptr().add(3), src.as_ptr(), 100);
+        }
+        assert_eq!(&dst[3..], &src[..]);
+        unsafe {
+            pmem_memset_persist(dst.as_mut_ptr().add(1), 7, 90);
+        }
+        assert!(dst[1..91].iter().all(|b| *b == 7));
+        assert_eq!(dst[0], 0);
+        assert_eq!(dst[91], 88);
+    }
+
+    #[test]
+    fn map_file() {
+        let path = "pmem_map.pool";
+        let _ = std::fs::remove_file(path);
+        let file = pmem_map_file(path, 8192, PMEM_FILE_CREATE | PMEM_FILE_EXCL).unwrap();
+        assert_eq!(file.len(), 8192);
+        unsafe { pmem_memset_persist(file.as_ptr(), 7, 8192); }
+        drop(file);
+        assert!(pmem_map_file(path, 8192, PMEM_FILE_CREATE | PMEM_FILE_EXCL).is_err());
+
+        // An existing file is mapped privately, and address space is reserved
+        let file = pmem_map_file(path, 1 << 20, PMEM_FILE_PRIVATE).unwrap();
+        assert_eq!(file.len(), 1 << 20);
+        unsafe {
+            assert_eq!(*file.as_ptr().add(8191), 7);
+            *file.as_ptr() = 0;
+        }
+        drop(file);
+        let file = pmem_map_file(path, 0, 0).unwrap();
+        assert_eq!(file.len(), 8192);
+        unsafe { assert_eq!(*file.as_ptr(), 7); }
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn best_fit_alloc() {
+        let _pool = P::open_no_root("pmem.pool", O_CF).unwrap();
+        let used = P::used();
+        unsafe {
+            let (a, _, _) = P::alloc(24);
+            let (b, b_off, _) = P::alloc(100);
+            let (c, _, _) = P::alloc(24);
+            assert_eq!(P::used(), used + 32 + 112 + 32);
+
+            // The freed chunk of `b` is reused for a smaller request
+            P::dealloc(b, 100);
+            let (d, d_off, _) = P::alloc(40);
+            assert_eq!(d_off, b_off);
+
+            P::dealloc(a, 24);
+            P::dealloc(c, 24);
+            P::dealloc(d, 40);
+        }
+        assert_eq!(P::used(), used);
+
+        // Blocks are aligned to the largest power of two dividing their size
+        unsafe {
+            let (a, _, _) = P::alloc(16);
+            let (b, b_off, _) = P::alloc(256);
+            assert_eq!(b_off % 256, 0);
+            assert_eq!(b as usize % 256, 0);
+            P::dealloc(a, 16);
+            P::dealloc(b, 256);
+        }
+        assert_eq!(P::used(), used);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod buddy;
 pub mod libpmem;
+pub mod slab;
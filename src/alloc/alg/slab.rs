@@ -0,0 +1,649 @@
+//! Segregated-fit allocation algorithm
+//!
+//! [`SlabAlg`] serves small objects from fixed-size runs, each of which is
+//! dedicated to a single size class. Large objects and the runs themselves
+//! are allocated by an embedded [`BuddyAlg`]. A pool type with this algorithm
+//! can be defined by passing `SlabAlg` as the third argument of [`pool!()`].
+//!
+//! [`BuddyAlg`]: ./struct.BuddyAlg.html
+//! [`pool!()`]: ../macro.pool.html
+
+use crate::alloc::*;
+use crate::ll::*;
+use crate::utils::*;
+use super::buddy::free_ranges;
+use std::marker::PhantomData;
+
+/// The size of a run in bytes
+const RUN_SIZE: usize = 8192;
+
+/// The number of words in the bitmap of a run
+const BITMAP_WORDS: usize = 8;
+
+// Offsets of the run header fields
+const RUN_NEXT: u64 = 0;
+const RUN_CLASS: u64 = 8;
+const RUN_FREE: u64 = 16;
+const RUN_BITMAP: u64 = 32;
+
+/// The size of the run header in bytes
+const RUN_HEADER: usize = 32 + 8 * BITMAP_WORDS;
+
+/// The number of size classes
+const NUM_CLASSES: usize = 14;
+
+/// Slot sizes of the size classes
+const CLASSES: [usize; NUM_CLASSES] = [
+    16, 24, 32, 40, 48, 64, 80, 96, 128, 192, 256, 384, 512, 1024
+];
+
+/// Returns the largest power of two that divides `x`
+#[inline]
+const fn pow2_factor(x: usize) -> usize {
+    x & x.wrapping_neg()
+}
+
+/// Returns the size class of `len`, if a slot of the class is large enough
+/// and aligned enough for it. The alignment of a type never exceeds the
+/// largest power of two that divides its size, which is what the buddy
+/// allocator guarantees for it.
+#[inline]
+fn class_of(len: usize) -> Option<usize> {
+    CLASSES
+        .iter()
+        .position(|c| *c >= len)
+        .filter(|class| pow2_factor(len) <= pow2_factor(CLASSES[*class]))
+}
+
+/// Returns the offset of the first slot in a run of the class. The header is
+/// padded so that every slot is aligned to the largest power of two that
+/// divides the slot size.
+#[inline]
+const fn first_slot(class: usize) -> usize {
+    let align = pow2_factor(CLASSES[class]);
+    (RUN_HEADER + align - 1) & !(align - 1)
+}
+
+#[inline]
+const fn slots(class: usize) -> usize {
+    (RUN_SIZE - first_slot(class)) / CLASSES[class]
+}
+
+#[repr(C)]
+/// Segregated-fit Allocation Algorithm
+///
+/// Requests up to 1024 bytes are rounded up to the nearest size class rather
+/// than to a power of two. Each class has a list of partially used runs. A
+/// run is an 8 KiB block obtained from the buddy allocator, with a header
+/// followed by equally sized slots:
+///
+/// ```text
+///   [next|class|free|-|bitmap (512 bits)|padding][slot 0][slot 1]...[slot n-1]
+/// ```
+///
+/// The slots are aligned to the largest power of two that divides the slot
+/// size, e.g. 64 bytes for the 192-byte class. Requests that need a stronger
+/// alignment than their class provides are forwarded to the buddy allocator.
+///
+/// The bitmap keeps the allocation state of the slots. A run leaves the list
+/// of its class when it becomes full, and is returned to the buddy allocator
+/// when it becomes empty, unless it is the only partially used run of its
+/// class. Larger requests are forwarded to the buddy allocator.
+///
+/// All changes to the runs and the lists go to the auxiliary redo log of the
+/// embedded [`BuddyAlg`], so that allocations and deallocations follow the same
+/// `prepare`/`perform`/`discard` protocol and are failure-atomic together
+/// with the buddy metadata.
+///
+/// [`BuddyAlg`]: ./struct.BuddyAlg.html
+pub struct SlabAlg<A: MemPool> {
+    /// The buddy allocator for runs and large blocks
+    buddy: BuddyAlg<A>,
+
+    /// Lists of partially used runs
+    partial: [u64; NUM_CLASSES],
+
+    /// Total size of the free slots in bytes
+    free_bytes: u64,
+
+    /// Number of runs
+    runs: u64,
+
+    /// Number of objects allocated from the runs
+    objects: u64,
+
+    /// Low-level `DropOnFailure` logs for recovery
+    drop_log: Ring<(u64, usize), 8>,
+
+    // Marker
+    phantom: PhantomData<A>,
+}
+
+impl<A: MemPool> SlabAlg<A> {
+    /// Maps the pool file the same way as [`BuddyAlg::map`]
+    ///
+    /// [`BuddyAlg::map`]: ./struct.BuddyAlg.html#method.map
+    pub fn map(
+        path: &str,
+        file: &std::fs::File,
+        len: usize,
+        read_only: bool
+    ) -> crate::result::Result<memmap::MmapMut> {
+        BuddyAlg::<A>::map(path, file, len, read_only)
+    }
+
+    /// Pool Initialization with a given device size
+    pub fn init(&mut self, base: u64, size: usize) {
+        // Runs are aligned to their size, so the start of a run can be found
+        // from the offset of any of its slots
+        let start = (base + RUN_SIZE as u64 - 1) & !(RUN_SIZE as u64 - 1);
+        self.buddy.init(start, size - (start - base) as usize);
+        self.partial = [u64::MAX; NUM_CLASSES];
+        self.free_bytes = 0;
+        self.runs = 0;
+        self.objects = 0;
+        self.drop_log.clear();
+    }
+
+    #[inline]
+    fn off_of<T>(x: &T) -> u64 {
+        x as *const T as u64 - A::start()
+    }
+
+    #[inline]
+    fn run_of(off: u64) -> u64 {
+        off & !(RUN_SIZE as u64 - 1)
+    }
+
+    #[inline]
+    /// Reads a metadata word including the changes that are not drained yet
+    fn word(&self, off: u64) -> u64 {
+        self.buddy.pending(off)
+    }
+
+    #[inline]
+    unsafe fn set(&mut self, off: u64, data: u64) {
+        self.buddy.aux_push(off, data);
+    }
+
+    #[inline]
+    /// Adds a new low-level 64-bit log entry
+    pub unsafe fn log(&mut self, off: u64, data: u64) {
+        self.buddy.log(off, data);
+    }
+
+    #[inline]
+    /// Adds a new low-level `DropOnFailure` log entry
+    pub unsafe fn drop_on_failure(&mut self, off: u64, len: usize) {
+        self.drop_log.push((off, len));
+    }
+
+    #[inline(always)]
+    /// Begins a failure-atomic section
+    pub unsafe fn prepare(&mut self) {
+        self.buddy.prepare();
+    }
+
+    #[inline]
+    /// Materializes the changes in the auxiliary list and clears the drop log
+    /// records
+    pub unsafe fn perform(&mut self) {
+        self.buddy.drain_aux();
+        self.drop_log.clear();
+        self.buddy.perform();
+    }
+
+    #[inline]
+    /// Discards the changes in the auxiliary buffer
+    pub fn discard(&mut self) {
+        self.drop_log.clear();
+        self.buddy.discard();
+    }
+
+    unsafe fn init_run(&mut self, run: u64, class: usize) {
+        let n = slots(class);
+        self.set(run + RUN_NEXT, u64::MAX);
+        self.set(run + RUN_CLASS, class as u64);
+        self.set(run + RUN_FREE, n as u64);
+        for w in 0..BITMAP_WORDS {
+            // The bits of nonexistent slots are always set
+            let first = w * 64;
+            let bits = if first >= n {
+                u64::MAX
+            } else if n - first >= 64 {
+                0
+            } else {
+                u64::MAX << (n - first)
+            };
+            self.set(run + RUN_BITMAP + 8 * w as u64, bits);
+        }
+    }
+
+    unsafe fn unlink_run(&mut self, head: u64, run: u64) {
+        let mut link = head;
+        let mut curr = self.word(head);
+        while curr != u64::MAX {
+            let next = self.word(curr + RUN_NEXT);
+            if curr == run {
+                self.set(link, next);
+                return;
+            }
+            link = curr + RUN_NEXT;
+            curr = next;
+        }
+        debug_assert!(false, "run @{} is not in the list", run);
+    }
+
+    /// Generates required changes to the metadata for allocating a new memory
+    /// block with the size `len`, and materialize them by calling
+    /// [`perform`](#methods.perform) according to the `perform` argument.
+    /// If successful, it returns the offset of the available free block.
+    /// Otherwise, `u64::MAX` is returned.
+    pub unsafe fn alloc_impl(&mut self, len: usize, perform: bool) -> u64 {
+        let class = match class_of(len) {
+            Some(class) => class,
+            None => return self.buddy.alloc_impl(len, perform),
+        };
+        let size = CLASSES[class];
+        self.buddy.lock();
+
+        let head = Self::off_of(&self.partial[class]);
+        let free_bytes = Self::off_of(&self.free_bytes);
+        let mut run = self.word(head);
+        if run == u64::MAX {
+            // The buddy allocator holds the lock until the changes are
+            // performed, or releases it when it fails
+            run = self.buddy.alloc_impl(RUN_SIZE, false);
+            self.buddy.unlock();
+            if run == u64::MAX {
+                return u64::MAX;
+            }
+            self.init_run(run, class);
+            self.set(head, run);
+            self.set(free_bytes, self.word(free_bytes) + (slots(class) * size) as u64);
+            let runs = Self::off_of(&self.runs);
+            self.set(runs, self.word(runs).wrapping_add(1));
+        }
+
+        let free = self.word(run + RUN_FREE);
+        debug_assert_ne!(free, 0, "a full run @{} is in the list", run);
+        let mut slot = usize::MAX;
+        for w in 0..BITMAP_WORDS {
+            let off = run + RUN_BITMAP + 8 * w as u64;
+            let bits = self.word(off);
+            if bits != u64::MAX {
+                let b = (!bits).trailing_zeros() as usize;
+                self.set(off, bits | (1 << b));
+                slot = w * 64 + b;
+                break;
+            }
+        }
+        debug_assert!(slot < slots(class), "no free slot in run @{}", run);
+
+        self.set(run + RUN_FREE, free - 1);
+        if free == 1 {
+            // The run is full
+            self.set(head, self.word(run + RUN_NEXT));
+        }
+        self.set(free_bytes, self.word(free_bytes) - size as u64);
+        let objects = Self::off_of(&self.objects);
+        self.set(objects, self.word(objects).wrapping_add(1));
+
+        let off = run + (first_slot(class) + slot * size) as u64;
+
+        #[cfg(feature = "verbose")]
+        debug_alloc::<A>(off, size, self.used(), self.used() + size);
+
+        self.buddy.sync_aux();
+        if perform {
+            self.perform();
+        }
+        off
+    }
+
+    /// Generates required changes to the metadata for reclaiming the memory
+    /// block at offset `off` with the size of `len`, and materialize them by
+    /// calling [`perform`](#methods.perform) according to the `perform`
+    /// argument.
+    pub unsafe fn dealloc_impl(&mut self, off: u64, len: usize, perform: bool) {
+        let class = match class_of(len) {
+            Some(class) => class,
+            None => return self.buddy.dealloc_impl(off, len, perform),
+        };
+        let size = CLASSES[class];
+        self.buddy.lock();
+
+        let run = Self::run_of(off);
+        debug_assert_eq!(self.word(run + RUN_CLASS), class as u64,
+            "size class mismatch for @{} ({})", off, len);
+
+        let slot = ((off - run) as usize - first_slot(class)) / size;
+        let word = run + RUN_BITMAP + 8 * (slot / 64) as u64;
+        let bit = 1u64 << (slot % 64);
+        let bits = self.word(word);
+        debug_assert_ne!(bits & bit, 0, "Double free @{}", off);
+
+        #[cfg(feature = "verbose")]
+        debug_dealloc::<A>(off, size, self.used(), self.used() - size);
+
+        let head = Self::off_of(&self.partial[class]);
+        let free_bytes = Self::off_of(&self.free_bytes);
+        let free = self.word(run + RUN_FREE) + 1;
+        let objects = Self::off_of(&self.objects);
+        self.set(objects, self.word(objects).wrapping_sub(1));
+        if free as usize == slots(class)
+            && (self.word(head) != run || self.word(run + RUN_NEXT) != u64::MAX)
+        {
+            // The run is empty, and it is not the only partially used run of
+            // its class
+            self.unlink_run(head, run);
+            self.set(free_bytes, self.word(free_bytes) - (free - 1) * size as u64);
+            let runs = Self::off_of(&self.runs);
+            self.set(runs, self.word(runs).wrapping_sub(1));
+            self.buddy.dealloc_impl(run, RUN_SIZE, false);
+            self.buddy.unlock();
+        } else {
+            self.set(word, bits & !bit);
+            self.set(run + RUN_FREE, free);
+            if free == 1 {
+                // The run was full
+                self.set(run + RUN_NEXT, self.word(head));
+                self.set(head, run);
+            }
+            self.set(free_bytes, self.word(free_bytes) + size as u64);
+        }
+
+        self.buddy.sync_aux();
+        if perform {
+            self.perform();
+        }
+    }
+
+    #[inline]
+    /// Adds a new memory block to the buddy allocator. See
+    /// [`BuddyAlg::extend`](./struct.BuddyAlg.html#method.extend).
+    pub unsafe fn extend(&mut self, off: u64, len: usize, perform: bool) {
+        self.buddy.extend(off, len, perform);
+    }
+
+    /// Determines if the given address range is allocated
+    pub fn is_allocated(&mut self, off: u64, len: usize) -> bool {
+        let class = match class_of(len) {
+            Some(class) => class,
+            None => return self.buddy.is_allocated(off, len),
+        };
+        let run = Self::run_of(off);
+        if off < run + first_slot(class) as u64 || !self.buddy.is_allocated(run, RUN_SIZE) {
+            return false;
+        }
+        self.buddy.lock();
+        let slot = ((off - run) as usize - first_slot(class)) / CLASSES[class];
+        let res = self.word(run + RUN_CLASS) == class as u64
+            && self.word(run + RUN_BITMAP + 8 * (slot / 64) as u64) & (1 << (slot % 64)) != 0;
+        self.buddy.unlock();
+        res
+    }
+
+    /// Starts the recovery procedure. The buddy allocator continues draining
+    /// the auxiliary buffer if the crash happened while draining it. Then, the
+    /// allocations in the drop log are reclaimed.
+    pub fn recover(&mut self) {
+        let crashed = self.buddy.is_operating();
+        self.buddy.recover();
+
+        if crashed && !self.drop_log.is_empty() {
+            eprintln!("Dropping unnecessary allocations");
+            unsafe {
+                let self_mut = self as *mut Self;
+                self.drop_log.drain_atomic(|(off, len)| {
+                    (*self_mut).dealloc_impl(off, len, false);
+                }, || {
+                    (*self_mut).buddy.drain_aux();
+                    (*self_mut).buddy.discard();
+                });
+            }
+        }
+        self.drop_log.clear();
+
+        #[cfg(debug_assertions)]
+        self.check(module_path!());
+    }
+
+    pub fn recovery_info(&self, info_level: u32) -> String {
+        let mut res = self.buddy.recovery_info(info_level);
+        if info_level > 1 {
+            res += &format!("Slab Drop Logs:            {}\n", self.drop_log.len());
+        }
+        if info_level > 2 {
+            self.drop_log.foreach(|(off, len)| {
+                res += &format!("  drop ({:x}; {})\n", off, len);
+            });
+        }
+        res
+    }
+
+    #[inline]
+    /// Returns the pool size
+    pub fn size(&self) -> usize {
+        self.buddy.size()
+    }
+
+    #[inline]
+    /// Returns the total available space in the pool
+    pub fn available(&self) -> usize {
+        self.buddy.available() + self.free_bytes as usize
+    }
+
+    #[inline]
+    /// Returns the total number of bytes used from the pool
+    pub fn used(&self) -> usize {
+        self.size() - self.available()
+    }
+
+    #[cfg(feature = "stat_footprint")]
+    /// Returns the total number of bytes written to the pool. It may exceed the
+    /// pool size as it does not subtract the reclaimed space after being used.
+    pub fn stat_footprint(&self) -> usize {
+        self.buddy.stat_footprint()
+    }
+
+    #[inline]
+    /// Returns the number of allocated blocks excluding the runs
+    ///
+    /// After the zones are reconfigured, it is relative to the import (see
+    /// [`import`](#method.import)).
+    pub fn objects(&self) -> usize {
+        self.buddy.objects()
+            .wrapping_sub(self.runs as usize)
+            .wrapping_add(self.objects as usize)
+    }
+
+    /// Returns the statistics of the zone including the free-list histogram
+    /// of the buddy allocator. The free slots are counted as available space,
+    /// but not as free blocks.
+    pub fn stats(&mut self) -> ZoneStats {
+        self.buddy.lock();
+        let mut stats = self.buddy.stats();
+        stats.available = self.available();
+        stats.used = self.used();
+        stats.objects = self.objects();
+        self.buddy.unlock();
+        stats
+    }
+
+    /// Appends the free blocks of the buddy allocator to `out` as `(offset,
+    /// length)` pairs, followed by the partially used runs as `(offset, 0)`
+    /// pairs. See [`BuddyAlg::export`](./struct.BuddyAlg.html#method.export).
+    pub fn export(&mut self, out: &mut Vec<(u64, u64)>) {
+        self.buddy.export(out);
+        self.buddy.lock();
+        for class in 0..NUM_CLASSES {
+            let mut curr = self.partial[class];
+            while curr != u64::MAX {
+                out.push((curr, 0));
+                curr = self.word(curr + RUN_NEXT);
+            }
+        }
+        self.buddy.unlock();
+    }
+
+    /// Rebuilds the zone over the address range `[start, end)` from the free
+    /// ranges and the partially used runs in `records`. The runs are linked to
+    /// the lists of their classes as they are. The changes are written and
+    /// flushed directly. See
+    /// [`BuddyAlg::import`](./struct.BuddyAlg.html#method.import).
+    pub unsafe fn import(&mut self, start: u64, end: u64, records: &[(u64, u64)], objects: usize) {
+        let mut runs: Vec<u64> = records
+            .iter()
+            .filter(|(off, len)| *len == 0 && *off >= start && *off < end)
+            .map(|(off, _)| *off)
+            .collect();
+        runs.sort_unstable();
+        self.partial = [u64::MAX; NUM_CLASSES];
+        self.free_bytes = 0;
+        for run in runs.into_iter().rev() {
+            let class = self.word(run + RUN_CLASS) as usize;
+            let next = read_addr::<u64>(A::start() + run + RUN_NEXT);
+            *next = self.partial[class];
+            persist_obj(next, false);
+            self.partial[class] = run;
+            self.free_bytes += self.word(run + RUN_FREE) * CLASSES[class] as u64;
+        }
+
+        // The blocks of the old zones cannot be told apart, so all objects
+        // are accounted to the slab allocator of the first zone. A block is
+        // still counted off in the zone which owns its address, so the
+        // counters of a zone are relative from now on, and they wrap around
+        // if it frees more blocks than it allocates. Their sum over all zones
+        // stays exact.
+        self.runs = 0;
+        self.objects = objects as u64;
+        self.drop_log.clear();
+        self.buddy.import(start, end, records, 0);
+        persist_obj(self, true);
+    }
+
+    pub fn check(&self, f: &str) {
+        self.buddy.check(f);
+        for class in 0..NUM_CLASSES {
+            let mut curr = self.partial[class];
+            while curr != u64::MAX {
+                let next = self.word(curr + RUN_NEXT);
+                assert_eq!(self.word(curr + RUN_CLASS), class as u64,
+                    "Size class mismatch in checking {}", f);
+                assert_ne!(next, curr, "Cyclic link in checking {}", f);
+                curr = next;
+            }
+        }
+    }
+
+    pub fn verify(&mut self) -> bool {
+        if !self.buddy.verify() { return false; }
+        if std::env::var("VERIFY").is_err() { return true; }
+        self.buddy.lock();
+        let mut free_bytes = 0;
+        for class in 0..NUM_CLASSES {
+            let mut curr = self.partial[class];
+            let mut runs = vec![];
+            while curr != u64::MAX {
+                if runs.contains(&curr) || self.word(curr + RUN_CLASS) != class as u64 {
+                    self.buddy.unlock();
+                    eprintln!("Verification Failed: Invalid run 0x{:x} (class={})", curr, class);
+                    return false;
+                }
+                runs.push(curr);
+                free_bytes += self.word(curr + RUN_FREE) * CLASSES[class] as u64;
+                curr = self.word(curr + RUN_NEXT);
+            }
+        }
+        self.buddy.unlock();
+        if free_bytes != self.free_bytes {
+            eprintln!("Verification Failed: Free slots mismatch ({} != {})",
+                free_bytes, self.free_bytes);
+            return false;
+        }
+        true
+    }
+
+    /// Prints the free lists and the partially used runs
+    pub fn print(&self) {
+        self.buddy.print();
+        for class in 0..NUM_CLASSES {
+            print!("{:>12} [slab] ", CLASSES[class]);
+            let mut curr = self.partial[class];
+            while curr != u64::MAX {
+                if A::contains(curr + A::start()) {
+                    print!("({}:{} free)", curr, self.word(curr + RUN_FREE));
+                } else {
+                    print!("(ERR)");
+                    break;
+                }
+                curr = self.word(curr + RUN_NEXT);
+            }
+            println!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::open_flags::*;
+
+    crate::pool!(slab, Allocator, SlabAlg);
+    type P = slab::Allocator;
+
+    #[test]
+    fn slab_alloc() {
+        let _pool = P::open_no_root("slab.pool", O_CF).unwrap();
+        let used = P::used();
+        unsafe {
+            // Objects of the same class are packed in the same run
+            let (a, a_off, _) = P::alloc(24);
+            let (b, b_off, _) = P::alloc(24);
+            assert_eq!(b_off, a_off + 24);
+
+            let (c, _, _) = P::alloc(40);
+            let (d, _, _) = P::alloc(5000);
+
+            P::dealloc(a, 24);
+            let (e, e_off, _) = P::alloc(24);
+            assert_eq!(e_off, a_off);
+
+            P::dealloc(b, 24);
+            P::dealloc(c, 40);
+            P::dealloc(d, 5000);
+            P::dealloc(e, 24);
+        }
+        assert!(P::verify());
+
+        // Runs that become empty are returned to the buddy allocator
+        let fill = || P::transaction(|j| {
+            let mut v = vec![];
+            for i in 0..1000 {
+                v.push(slab::Pbox::new((i as u64, i as u64, i as u64), j));
+            }
+        }).unwrap();
+        fill();
+        let used = P::used();
+        fill();
+        assert!(P::verify());
+        assert_eq!(P::used(), used);
+    }
+
+    #[test]
+    fn slab_alignment() {
+        let _pool = P::open_no_root("slab_align.pool", O_CF).unwrap();
+        unsafe {
+            for len in [8, 16, 24, 40, 48, 64, 96, 128, 160, 192, 384, 512, 768, 1024] {
+                let (a, _, _) = P::alloc(len);
+                let (b, _, _) = P::alloc(len);
+                let align = (len & len.wrapping_neg()).min(4096);
+                assert_eq!(a as usize % align, 0, "misaligned block of size {}", len);
+                assert_eq!(b as usize % align, 0, "misaligned block of size {}", len);
+                P::dealloc(a, len);
+                P::dealloc(b, len);
+            }
+        }
+        assert!(P::verify());
+    }
+}
@@ -0,0 +1,379 @@
+//! On-media pool header
+
+use crate::cell::RootSchema;
+use crate::result::Result;
+use crate::Error;
+use std::mem;
+
+/// The signature of a pool file
+pub const POOL_SIGNATURE: [u8; 8] = *b"CRNDMPL\0";
+
+/// The version of the on-media format
+///
+/// It changes whenever the layout of the pool metadata or the persistent
+/// objects of the crate changes in an incompatible way.
+pub const POOL_FORMAT_VERSION: u32 = 3;
+
+/// Pool feature flag: `use_pspd` is enabled
+pub const POOL_FEATURE_USE_PSPD: u64 = 0x0000_0001;
+
+/// Pool feature flag: `use_vspd` is enabled
+pub const POOL_FEATURE_USE_VSPD: u64 = 0x0000_0002;
+
+/// Pool feature flag: `no_dyn_borrow_checking` is enabled
+pub const POOL_FEATURE_NO_DYN_BORROW_CHECKING: u64 = 0x0000_0004;
+
+/// Pool feature flag: `no_pthread` is enabled
+pub const POOL_FEATURE_NO_PTHREAD: u64 = 0x0000_0008;
+
+const FEATURE_NAMES: [(u64, &str); 4] = [
+    (POOL_FEATURE_USE_PSPD, "use_pspd"),
+    (POOL_FEATURE_USE_VSPD, "use_vspd"),
+    (POOL_FEATURE_NO_DYN_BORROW_CHECKING, "no_dyn_borrow_checking"),
+    (POOL_FEATURE_NO_PTHREAD, "no_pthread"),
+];
+
+/// Returns the feature flags of the current build which change the layout of
+/// the persistent objects
+pub fn pool_features() -> u64 {
+    let mut features = 0;
+    if cfg!(feature = "use_pspd") {
+        features |= POOL_FEATURE_USE_PSPD;
+    }
+    if cfg!(feature = "use_vspd") {
+        features |= POOL_FEATURE_USE_VSPD;
+    }
+    if cfg!(feature = "no_dyn_borrow_checking") {
+        features |= POOL_FEATURE_NO_DYN_BORROW_CHECKING;
+    }
+    if cfg!(any(feature = "no_pthread", windows)) {
+        features |= POOL_FEATURE_NO_PTHREAD;
+    }
+    features
+}
+
+/// Returns the page size of the system
+pub fn page_size() -> usize {
+    #[cfg(unix)]
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+
+    #[cfg(not(unix))]
+    let size = 4096;
+
+    size
+}
+
+fn feature_names(features: u64) -> String {
+    let names: Vec<&str> = FEATURE_NAMES
+        .iter()
+        .filter(|(f, _)| features & f != 0)
+        .map(|(_, n)| *n)
+        .collect();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The versioned header at the beginning of a pool file
+///
+/// It describes the build configuration which created the pool. When a pool
+/// is opened, the header is validated against the current build so that an
+/// incompatible pool image is rejected with an error instead of being
+/// misinterpreted.
+pub struct PoolHeader {
+    /// The pool file signature (`POOL_SIGNATURE`)
+    pub signature: [u8; 8],
+
+    /// The version of the on-media format
+    pub format_version: u32,
+
+    /// The width of pointers in bits
+    pub pointer_width: u32,
+
+    /// The version of the crate which created the pool (major, minor, patch)
+    pub crate_version: [u16; 4],
+
+    /// The page size of the system which created the pool
+    pub page_size: u64,
+
+    /// The number of allocation zones
+    pub zone_count: u64,
+
+    /// The feature flags which change the layout of the persistent objects
+    pub features: u64,
+}
+
+impl PoolHeader {
+    /// Creates a header for the current build
+    pub fn new(zone_count: usize) -> Self {
+        Self {
+            signature: POOL_SIGNATURE,
+            format_version: POOL_FORMAT_VERSION,
+            pointer_width: (mem::size_of::<usize>() * 8) as u32,
+            crate_version: [
+                env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0),
+                env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0),
+                env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or(0),
+                0,
+            ],
+            page_size: page_size() as u64,
+            zone_count: zone_count as u64,
+            features: pool_features(),
+        }
+    }
+
+    /// Returns the version of the crate which created the pool
+    pub fn crate_version(&self) -> String {
+        format!("{}.{}.{}",
+            self.crate_version[0], self.crate_version[1], self.crate_version[2])
+    }
+
+    /// Checks if the pool can be opened by the current build
+    ///
+    /// It returns an error describing the first incompatibility, if any.
+    pub fn validate(&self) -> Result<()> {
+        if self.signature != POOL_SIGNATURE {
+            return Err(Error::InvalidPool(
+                "Not a pool file or the pool was not formatted".to_string()
+            ));
+        }
+        if self.format_version != POOL_FORMAT_VERSION {
+            return Err(Error::InvalidPool(format!(
+                "Incompatible pool format version {} (created by version {}; expected format version {})",
+                self.format_version, self.crate_version(), POOL_FORMAT_VERSION
+            )));
+        }
+        let width = (mem::size_of::<usize>() * 8) as u32;
+        if self.pointer_width != width {
+            return Err(Error::InvalidPool(format!(
+                "Incompatible pointer width ({} bits; expected {} bits)",
+                self.pointer_width, width
+            )));
+        }
+        let page = page_size() as u64;
+        if self.page_size != page {
+            return Err(Error::InvalidPool(format!(
+                "Incompatible page size ({} bytes; expected {} bytes)",
+                self.page_size, page
+            )));
+        }
+        let features = pool_features();
+        if self.features != features {
+            return Err(Error::InvalidPool(format!(
+                "Incompatible features (pool: {}; current build: {})",
+                feature_names(self.features), feature_names(features)
+            )));
+        }
+        if self.zone_count == 0 {
+            return Err(Error::InvalidPool(
+                "Invalid number of zones in the pool header".to_string()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The maximum length of the root type name stored in the pool
+const ROOT_NAME_LEN: usize = 112;
+
+/// The recorded root name does not depend on the compiler
+const ROOT_NAME_STABLE: u16 = 0x0001;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// The fingerprint of the root type of a pool
+///
+/// It is stored in the pool when the root object is created, and it is
+/// checked against the requested root type whenever the pool is opened. It
+/// consists of the type name, size, and alignment, and the field layout hash
+/// of types deriving [`Root`](./derive.Root.html) (see [`RootSchema`]).
+///
+/// Types deriving `Root` have a stable name, which is the name of the type or
+/// the one given by `#[root(name = "...")]`. Other types are recorded with
+/// [`std::any::type_name`], which may change between compiler versions, so
+/// a pool whose root has no stable name is also checked against the type id
+/// recorded in the pool header. The persistent wrappers (`Parc`, `Prc`,
+/// `Pbox`, `PCell`, `PRefCell`, `PMutex`, `PRwLock`, and `PVec`) take the
+/// stable name and schema of the wrapped type.
+///
+/// [`RootSchema`]: ./trait.RootSchema.html
+/// [`RootSchema::root_name`]: ./trait.RootSchema.html#tymethod.root_name
+pub struct RootFingerprint {
+    name: [u8; ROOT_NAME_LEN],
+    name_len: u16,
+    flags: u16,
+    align: u32,
+    size: u64,
+    schema: u64,
+}
+
+impl Default for RootFingerprint {
+    fn default() -> Self {
+        Self {
+            name: [0; ROOT_NAME_LEN],
+            name_len: 0,
+            flags: 0,
+            align: 0,
+            size: 0,
+            schema: 0,
+        }
+    }
+}
+
+impl RootFingerprint {
+    /// Creates the fingerprint of type `T`
+    pub fn of<T: RootSchema>() -> Self {
+        let (full, flags) = match T::root_name() {
+            Some(name) => (name, ROOT_NAME_STABLE),
+            None => (std::any::type_name::<T>().to_string(), 0),
+        };
+        let mut len = full.len().min(ROOT_NAME_LEN);
+        while !full.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut name = [0; ROOT_NAME_LEN];
+        name[..len].copy_from_slice(&full.as_bytes()[..len]);
+        Self {
+            name,
+            name_len: len as u16,
+            flags,
+            align: mem::align_of::<T>() as u32,
+            size: mem::size_of::<T>() as u64,
+            schema: T::schema_hash(),
+        }
+    }
+
+    /// Indicates that no fingerprint is recorded
+    pub fn is_empty(&self) -> bool {
+        self.name_len == 0
+    }
+
+    /// Returns the (possibly truncated) type name
+    pub fn name(&self) -> &str {
+        let len = (self.name_len as usize).min(ROOT_NAME_LEN);
+        std::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// Indicates that the name is stable, i.e. it does not depend on the
+    /// compiler
+    pub fn has_stable_name(&self) -> bool {
+        self.flags & ROOT_NAME_STABLE != 0
+    }
+
+    /// Returns the size of the type in bytes
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Returns the alignment of the type in bytes
+    pub fn align(&self) -> usize {
+        self.align as usize
+    }
+
+    /// Returns the field layout hash, or 0 if it is unknown
+    pub fn schema(&self) -> u64 {
+        self.schema
+    }
+
+    /// Checks if the recorded root type is compatible with `requested`, and
+    /// describes the mismatch if it is not
+    pub fn check(&self, requested: &Self) -> Result<()> {
+        if self.flags != requested.flags
+            || self.name() != requested.name()
+            || self.size != requested.size
+            || self.align != requested.align
+        {
+            return Err(Error::RootTypeMismatch(format!(
+                "Incompatible root type: the pool root is `{}` ({} bytes, align {}), but `{}` ({} bytes, align {}) was requested",
+                self.name(), self.size, self.align,
+                requested.name(), requested.size, requested.align
+            )));
+        }
+        if self.schema != 0 && requested.schema != 0 && self.schema != requested.schema {
+            return Err(Error::RootTypeMismatch(format!(
+                "The fields of root type `{}` have changed since the pool was created",
+                self.name()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for RootFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RootFingerprint")
+            .field("name", &self.name())
+            .field("size", &self.size)
+            .field("align", &self.align)
+            .field("schema", &format_args!("{:016x}", self.schema))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_validation() {
+        let h = PoolHeader::new(4);
+        assert!(h.validate().is_ok());
+
+        let mut g = h;
+        g.signature = [0xff; 8];
+        assert!(matches!(g.validate(), Err(Error::InvalidPool(_))));
+
+        let mut g = h;
+        g.format_version += 1;
+        assert!(g.validate().unwrap_err().to_string().contains("format version"));
+
+        let mut g = h;
+        g.features ^= POOL_FEATURE_USE_PSPD;
+        assert!(g.validate().unwrap_err().to_string().contains("use_pspd"));
+
+        let mut g = h;
+        g.page_size *= 2;
+        assert!(g.validate().is_err());
+    }
+
+    #[test]
+    fn root_fingerprint() {
+        let a = RootFingerprint::of::<(u64, u32)>();
+        assert_eq!(a.size(), 16);
+        assert!(!a.has_stable_name());
+        assert!(RootFingerprint::of::<u64>().has_stable_name());
+        assert_eq!(RootFingerprint::of::<u64>().name(), "u64");
+        assert!(a.check(&RootFingerprint::of::<(u64, u32)>()).is_ok());
+
+        let err = a.check(&RootFingerprint::of::<(u64, u64, u64)>()).unwrap_err().to_string();
+        assert!(err.contains("(u64, u64, u64)"), "{}", err);
+
+        // Names are compared whether they are stable or not
+        let mut b = a;
+        b.name[1] = b'x';
+        assert!(matches!(a.check(&b), Err(Error::RootTypeMismatch(_))));
+
+        let mut c = a;
+        c.flags = ROOT_NAME_STABLE;
+        let mut d = b;
+        d.flags = ROOT_NAME_STABLE;
+        assert!(c.check(&c).is_ok());
+        assert!(matches!(c.check(&d), Err(Error::RootTypeMismatch(_))));
+        assert!(matches!(a.check(&c), Err(Error::RootTypeMismatch(_))));
+
+        let mut b = a;
+        b.size = 24;
+        assert!(matches!(a.check(&b), Err(Error::RootTypeMismatch(_))));
+
+        let mut b = a;
+        b.schema = 1;
+        assert!(a.check(&b).is_ok());
+        let mut c = a;
+        c.schema = 2;
+        assert!(c.check(&b).unwrap_err().to_string().contains("fields"));
+    }
+}
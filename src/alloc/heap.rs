@@ -102,7 +102,7 @@ unsafe impl MemPoolTraits for Heap {
     ) -> Result<RootCell<'a, U, Self>> {
         let slf = Self::open_no_root(path, flags)?;
         if std::mem::size_of::<U>() == 0 {
-            Err("root type cannot be a ZST".to_string())
+            Err(Error::InvalidArgument("root type cannot be a ZST".to_string()))
         } else {
             unsafe {
                 let root_off = Self::transaction(move |j| {
@@ -173,6 +173,30 @@ pub type Parc<T> = crate::sync::Parc<T, Heap>;
 /// `<T,`[`Heap`](./struct.Heap.html)`>`.
 pub type PMutex<T> = crate::sync::PMutex<T, Heap>;
 
+/// Compact form of [`PRwLock`](../../sync/struct.PRwLock.html)
+/// `<T,`[`Heap`](./struct.Heap.html)`>`.
+pub type PRwLock<T> = crate::sync::PRwLock<T, Heap>;
+
+/// Compact form of [`PCondvar`](../../sync/struct.PCondvar.html)
+/// `<`[`Heap`](./struct.Heap.html)`>`.
+pub type PCondvar = crate::sync::PCondvar<Heap>;
+
+/// Compact form of [`PAtomicU64`](../../sync/struct.PAtomicU64.html)
+/// `<`[`Heap`](./struct.Heap.html)`>`.
+pub type PAtomicU64 = crate::sync::PAtomicU64<Heap>;
+
+/// Compact form of [`PAtomicUsize`](../../sync/struct.PAtomicUsize.html)
+/// `<`[`Heap`](./struct.Heap.html)`>`.
+pub type PAtomicUsize = crate::sync::PAtomicUsize<Heap>;
+
+/// Compact form of [`PAtomicBool`](../../sync/struct.PAtomicBool.html)
+/// `<`[`Heap`](./struct.Heap.html)`>`.
+pub type PAtomicBool = crate::sync::PAtomicBool<Heap>;
+
+/// Compact form of [`PWord`](../../sync/struct.PWord.html)
+/// `<`[`Heap`](./struct.Heap.html)`>`.
+pub type PWord = crate::sync::PWord<Heap>;
+
 /// Compact form of [`PCell`](../../cell/struct.PCell.html)
 /// `<T,`[`Heap`](./struct.Heap.html)`>`.
 pub type PCell<T> = crate::cell::PCell<T, Heap>;
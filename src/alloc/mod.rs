@@ -1,12 +1,20 @@
 //! Persistent Memory allocation APIs
 
 mod alg;
+mod adapter;
+mod header;
 mod pool;
+mod stats;
 
 pub mod heap;
 
+pub use adapter::*;
+pub use header::*;
 pub use alg::buddy::*;
+pub use alg::libpmem::*;
+pub use alg::slab::*;
 pub use pool::*;
+pub use stats::*;
 
 /// Determines how much of the `MemPool` is used for the trait object.
 ///
@@ -5,6 +5,7 @@ use crate::result::Result;
 use crate::stm::*;
 use crate::utils::*;
 use crate::*;
+use std::alloc::AllocError;
 use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::ops::Range;
@@ -16,6 +17,14 @@ use std::{mem, ptr};
 /// Default pool memory size to be used while creating a new pool
 pub const DEFAULT_POOL_SIZE: u64 = 8 * 1024 * 1024;
 
+/// The default virtual address space reserved for a pool which is open for
+/// writing. A pool can [`grow`] up to this size without being remapped to
+/// another address. It can be changed with [`set_reserved_size`].
+///
+/// [`grow`]: ./trait.MemPoolTraits.html#method.grow
+/// [`set_reserved_size`]: ./trait.MemPoolTraits.html#method.set_reserved_size
+pub const POOL_RESERVED_SIZE: u64 = 16 << 30;
+
 /// Open pool flags
 pub mod open_flags {
     /// Open Flag: Create the pool memory file
@@ -86,6 +95,34 @@ pub mod open_flags {
 
     /// Open Flag: Open only to read info
     pub const O_READINFO: u32 = u32::MAX;
+
+    /// Open Flag: Opens an existing pool in read-only mode
+    ///
+    /// The file is mapped privately, so that the changes made while opening
+    /// the pool (e.g. recovery) stay in volatile memory and never reach the
+    /// file. Transactions cannot run in a read-only pool. It cannot be
+    /// combined with the other flags.
+    pub const O_RDONLY: u32 = 0x80000000;
+
+    /// Open Flag: Opens the pool in the shared mode, so that several
+    /// processes can open it for writing at the same time
+    ///
+    /// The first process which opens the pool recovers it; the others join
+    /// without recovery and use the same allocator, journals and locks. The
+    /// locks (e.g. [`PMutex`]) are process-shared, and they synchronize the
+    /// threads of all processes. It may be combined with the create flags,
+    /// which only take effect in the first process; [`O_C`] fails if the pool
+    /// is open in another process. A shared pool cannot grow, be quiesced or
+    /// be replicated.
+    ///
+    /// The processes share the volatile state of the pool, too. If one of
+    /// them crashes, its locks are not released and its transactions are not
+    /// rolled back until all processes close the pool and it is recovered on
+    /// the next open.
+    ///
+    /// [`PMutex`]: ../../sync/struct.PMutex.html
+    /// [`O_C`]: ./constant.O_C.html
+    pub const O_SHARED: u32 = 0x40000000;
 }
 
 use open_flags::*;
@@ -93,10 +130,24 @@ use open_flags::*;
 /// Shows that the pool has a root object
 pub const FLAG_HAS_ROOT: u64 = 0x0000_0001;
 
+/// Shows that the pool is open with a replica, which may lag behind the pool
+/// if it crashes
+pub const FLAG_REPLICATED: u64 = 0x0000_0002;
+
+/// Zone reconfiguration state: no reconfiguration is in progress
+pub const RECONF_IDLE: u64 = 0;
+
+/// Zone reconfiguration state: the new zone table is allocated
+pub const RECONF_ALLOCATED: u64 = 1;
+
+/// Zone reconfiguration state: the free ranges are staged, and the zones can
+/// be rebuilt from them
+pub const RECONF_STAGED: u64 = 2;
+
 /// This macro can be used to access static data of an arbitrary allocator
 #[macro_export]
 macro_rules! static_inner {
-    ($id:ident, $inner:ident, $body:block) => {
+    ($id:expr, $inner:ident, $body:block) => {
         unsafe {
             if let Some($inner) = $id {
                 let $inner = &mut *$inner;
@@ -218,6 +269,30 @@ where
     ///   * O_CFNE: create and format a memory pool file only if not exists
     /// 
     /// See [`open_flags`](./open_flags/index.html) for more options.
+    ///
+    /// # Errors
+    ///
+    /// The [`PoolHeader`] of an existing pool is validated against the current
+    /// build. It returns [`InvalidPool`] if the file is not a pool, or if it was
+    /// created with a different format version, pointer width, page size, or
+    /// set of layout-changing features (e.g. `use_pspd`). Other failures are:
+    ///
+    /// * [`IoError`] if the file cannot be created, opened, or mapped.
+    /// * [`TxRunning`] if a transaction is running in the current thread.
+    /// * [`ChaperonMissing`] if the pool needs the chaperon file of an
+    /// interrupted chaperoned session for recovery, but it does not exist.
+    /// * [`PoolLocked`] if the file is open by another pool, e.g. in another
+    /// process. An open pool holds an exclusive advisory lock (`flock`) on
+    /// its file, and opening with [`O_READINFO`] takes a shared lock. The
+    /// lock is checked before the flags truncate or format the file.
+    ///
+    /// [`PoolHeader`]: ./struct.PoolHeader.html
+    /// [`InvalidPool`]: ./enum.Error.html#variant.InvalidPool
+    /// [`IoError`]: ./enum.Error.html#variant.IoError
+    /// [`TxRunning`]: ./enum.Error.html#variant.TxRunning
+    /// [`ChaperonMissing`]: ./enum.Error.html#variant.ChaperonMissing
+    /// [`PoolLocked`]: ./enum.Error.html#variant.PoolLocked
+    /// [`O_READINFO`]: ./open_flags/constant.O_READINFO.html
     fn open_no_root(_path: &str, _flags: u32) -> Result<PoolGuard<Self>> {
         unimplemented!()
     }
@@ -330,7 +405,14 @@ where
     ///
     /// * A volatile memory pool (e.g. `Heap`) doesn't have a root object.
     /// * The pool should be open before accessing the root object.
-    ///
+    /// * It returns [`RootTypeMismatch`] if the pool was created with a
+    /// different root type.
+    /// * Other errors are the same as [`open_no_root`], e.g. [`PoolLocked`]
+    /// if the file is open by another pool.
+    ///
+    /// [`open_no_root`]: #method.open_no_root
+    /// [`PoolLocked`]: ./enum.Error.html#variant.PoolLocked
+    /// [`RootTypeMismatch`]: ./enum.Error.html#variant.RootTypeMismatch
     /// [`RootObj`]: ../stm/trait.RootObj.html
     /// [`Prc`]: ../prc/struct.Prc.html
     /// [`Parc`]: ../sync/parc/struct.Parc.html
@@ -344,6 +426,13 @@ where
         unimplemented!()
     }
 
+    /// Returns true if the pool is open in read-only mode
+    ///
+    /// See [`O_RDONLY`](./open_flags/constant.O_RDONLY.html).
+    fn is_read_only() -> bool {
+        false
+    }
+
     /// Returns true if the pool is open
     fn is_open() -> bool {
         unimplemented!()
@@ -354,23 +443,33 @@ where
         unimplemented!()
     }
 
-    /// Applies open pool flags
-    unsafe fn apply_flags(path: &str, flags: u32) -> Result<()> {
+    /// Applies open pool flags to the pool `file` at `path`
+    ///
+    /// The caller should hold an exclusive lock on `file` (see
+    /// [`lock_file`]), which is created empty if it did not exist. The file is
+    /// truncated in place, rather than replaced, so that the lock stays on the
+    /// file which is then mapped.
+    ///
+    /// [`lock_file`]: ./fn.lock_file.html
+    unsafe fn apply_flags(path: &str, flags: u32, file: &std::fs::File) -> Result<()> {
         let mut size: u64 = flags as u64 >> 4;
         if size.count_ones() > 1 {
-            return Err("Cannot have multiple size flags".to_string());
+            return Err(Error::InvalidArgument("Cannot have multiple size flags".to_string()));
         } else if size == 0 {
             size = DEFAULT_POOL_SIZE;
         } else {
             if flags & (O_C | O_CNE) == 0 {
-                return Err("Cannot use size flag without a create flag".to_string());
+                return Err(Error::InvalidArgument(
+                    "Cannot use size flag without a create flag".to_string()
+                ));
             }
             size <<= 30;
         }
-        let mut format = !Path::new(path).exists() && ((flags & O_F) != 0);
-        if ((flags & O_C) != 0) || ((flags & O_CNE != 0) && !Path::new(path).exists()) {
-            let _=std::fs::remove_file(path);
-            create_file(path, size)?;
+        let exists = file.metadata()?.len() != 0;
+        let mut format = !exists && ((flags & O_F) != 0);
+        if ((flags & O_C) != 0) || ((flags & O_CNE != 0) && !exists) {
+            file.set_len(0)?;
+            file.set_len(size)?;
             format = (flags & O_F) != 0;
         }
         if format {
@@ -496,7 +595,7 @@ where
         if Self::allocated(off, mem::size_of::<T>()) {
             Ok(Self::get_unchecked(off))
         } else {
-            Err(format!("Access Violation (0x{:x})", off))
+            Err(Error::AccessViolation(off))
         }
     }
 
@@ -506,7 +605,7 @@ where
         if Self::allocated(off, mem::size_of::<T>()) {
             Ok(Self::get_mut_unchecked(off))
         } else {
-            Err(format!("Access Violation (0x{:x})", off))
+            Err(Error::AccessViolation(off))
         }
     }
 
@@ -516,7 +615,7 @@ where
         if Self::valid(x) {
             Ok(x as *const u8 as u64 - Self::start())
         } else {
-            Err(format!("out of valid range ({:p})", x).to_string())
+            Err(Error::AccessViolation(x as *const u8 as u64))
         }
     }
 
@@ -552,6 +651,250 @@ where
         Self::size() - Self::available()
     }
 
+    /// Returns the statistics of the pool
+    ///
+    /// The statistics include the space usage and the free-list histograms of
+    /// all zones, the size of the largest free block, the fragmentation ratio,
+    /// the number of journal pages, and the number of allocated objects. For
+    /// more details, please see [`PoolStats`].
+    ///
+    /// [`PoolStats`]: ./struct.PoolStats.html
+    fn stats() -> PoolStats {
+        let mut stats = PoolStats::new(Self::size());
+        stats.available = Self::available();
+        stats.used = Self::used();
+        stats.overhead = stats.size - stats.used - stats.available;
+        stats
+    }
+
+    /// Extends the pool to `new_size` bytes
+    ///
+    /// It extends the backing file and atomically adds the new space to the
+    /// free lists. The pool stays at the same virtual address, so the existing
+    /// references remain valid. If `new_size` is not larger than the current
+    /// size, it does nothing.
+    ///
+    /// The pool cannot grow beyond the address space reserved when it was
+    /// opened (see [`set_reserved_size`]); it returns [`ReservationExceeded`]
+    /// if `new_size` is larger.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::default::*;
+    /// # type P = Allocator;
+    /// let _p = P::open_no_root("foo.pool", O_CF).unwrap();
+    /// let size = P::size();
+    /// P::grow(2 * size).unwrap();
+    /// assert!(P::size() > size);
+    /// ```
+    ///
+    /// [`set_reserved_size`]: #method.set_reserved_size
+    /// [`ReservationExceeded`]: ./enum.Error.html#variant.ReservationExceeded
+    fn grow(_new_size: usize) -> Result<()> {
+        Err(Error::Unsupported(format!("Pool `{}` is not growable", Self::name())))
+    }
+
+    /// Sets the virtual address space to reserve for the pool when it is
+    /// opened for writing next time
+    ///
+    /// The pool can [`grow`] up to `size` bytes while it is open. The default
+    /// is [`POOL_RESERVED_SIZE`]. A `size` of zero reserves no more than the
+    /// size of the pool file, so that it cannot grow. Pools which are open
+    /// read-only never reserve more than their file size.
+    ///
+    /// [`grow`]: #method.grow
+    /// [`POOL_RESERVED_SIZE`]: ./constant.POOL_RESERVED_SIZE.html
+    fn set_reserved_size(_size: usize) {}
+
+    /// Sets the auto-grow policy of the pool
+    ///
+    /// If `step` is non-zero, the pool grows by at least `step` bytes whenever
+    /// an allocation cannot be satisfied. A `step` of zero disables auto-grow,
+    /// which is the default behavior. The policy is volatile and should be set
+    /// again after reopening the pool.
+    fn set_auto_grow(_step: usize) {}
+
+    /// Returns an adapter implementing [`std::alloc::Allocator`] for this pool
+    ///
+    /// It can be used for placing standard containers in the pool's address
+    /// space. For more details, please see [`PoolAllocator`].
+    ///
+    /// [`PoolAllocator`]: ./struct.PoolAllocator.html
+    fn allocator() -> PoolAllocator<Self> where Self: MemPool {
+        PoolAllocator::new()
+    }
+
+    /// Copies all live objects to a freshly formatted pool at `path`
+    ///
+    /// It traverses the object graph from the root object of type `U`, and
+    /// copies every reachable object to a new pool file of `size` bytes. Every
+    /// persistent pointer is rewritten to refer to the new location of its
+    /// object. Unreachable (leaked) objects and the free space between objects
+    /// are left out, so the new image can be smaller than the original one.
+    /// The destination file is replaced if it exists, but only once the new
+    /// image is complete. If it is a pool which is open in this or another
+    /// process, it returns [`PoolLocked`].
+    ///
+    /// The pool should be open. The other threads cannot start a transaction
+    /// or access the pool outside a transaction while compacting; the running
+    /// ones are waited for. [`TxRunning`] is returned if the current thread is
+    /// in a transaction. The root type should implement [`Relocate`] which can
+    /// be derived.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::default::*;
+    /// # type P = Allocator;
+    /// let root = P::open::<PRefCell<PVec<i32>>>("foo.pool", O_CF).unwrap();
+    /// P::transaction(|j| root.borrow_mut(j).push(1, j)).unwrap();
+    /// P::compact_to::<PRefCell<PVec<i32>>>("foo-compact.pool", 1024 * 1024).unwrap();
+    /// ```
+    ///
+    /// [`Relocate`]: ../trait.Relocate.html
+    /// [`PoolLocked`]: ../enum.Error.html#variant.PoolLocked
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    fn compact_to<U: PSafe + Relocate<Self>>(_path: &str, _size: u64) -> Result<()>
+    where Self: MemPool {
+        Err(Error::Unsupported(format!("Pool `{}` cannot be compacted", Self::name())))
+    }
+
+    /// Copies the pool image to a new pool file at `path` while the pool is
+    /// in use
+    ///
+    /// It keeps new transactions from starting, waits until the running ones
+    /// finish, and copies the image while the pool is quiesced. The updates
+    /// which are made outside transactions (e.g. the non-transactional
+    /// functions of [`PAtomicU64`] and the allocations of [`PoolAllocator`])
+    /// wait for the copy as well. The copy is made with `copy_file_range`,
+    /// which lets the file system share the data with the original file
+    /// (reflink) when it can; otherwise, the mapped image is written to the
+    /// new file. The new file is a consistent pool image which can be opened
+    /// with the same pool type. It is written to a temporary file first,
+    /// which then replaces the destination file if it exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::default::*;
+    /// # type P = Allocator;
+    /// let root = P::open::<PRefCell<PVec<i32>>>("foo.pool", O_CF).unwrap();
+    /// P::transaction(|j| root.borrow_mut(j).push(1, j)).unwrap();
+    /// P::snapshot("foo-backup.pool").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It returns [`TxRunning`] if a transaction is running in the current
+    /// thread, [`PoolNotOpen`] if the pool is not open,
+    /// [`InvalidArgument`] if `path` is the pool file itself, and
+    /// [`PoolLocked`] if `path` is another pool which is open.
+    ///
+    /// [`PAtomicU64`]: ../sync/struct.PAtomicU64.html
+    /// [`PoolAllocator`]: ./struct.PoolAllocator.html
+    /// [`PoolLocked`]: ../enum.Error.html#variant.PoolLocked
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    /// [`PoolNotOpen`]: ../enum.Error.html#variant.PoolNotOpen
+    /// [`InvalidArgument`]: ../enum.Error.html#variant.InvalidArgument
+    fn snapshot(_path: &str) -> Result<()> {
+        Err(Error::Unsupported(format!("Pool `{}` does not support snapshots", Self::name())))
+    }
+
+    /// Moves up to `max_moves` live objects to lower addresses to coalesce the
+    /// free space
+    ///
+    /// It traverses the object graph from the root object of type `U`, and
+    /// moves the objects with the highest addresses to free blocks at lower
+    /// addresses, if there is any. Every persistent pointer to a moved object
+    /// is rewritten. All moves of a single call happen in one transaction, so
+    /// the pool is either defragmented by this step or left unchanged in case
+    /// of a failure. It returns the number of moved objects; calling it
+    /// repeatedly, e.g. from a background thread, defragments the pool
+    /// incrementally until it returns zero. The root object itself is never
+    /// moved.
+    ///
+    /// While a step runs, the other threads cannot start a transaction, a
+    /// read-only transaction, or a non-transactional update; the running ones
+    /// are waited for before any object is moved. Objects which are referred
+    /// to by volatile state, such as locks and [`Parc`]s with live `VWeak`
+    /// references, are pinned by their [`Relocate`] implementation and are
+    /// never moved. The root type should implement [`Relocate`] which can be
+    /// derived. [`TxRunning`] is returned if the current thread is in a
+    /// transaction.
+    ///
+    /// # Safety
+    ///
+    /// There should be no live volatile reference to any object other than the
+    /// root object obtained outside a transaction (e.g. a [`Ref`] returned by
+    /// `PRefCell::borrow()`), as the object may be moved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::default::*;
+    /// # type P = Allocator;
+    /// let root = P::open::<PRefCell<PVec<Pbox<i32>>>>("foo.pool", O_CF).unwrap();
+    /// P::transaction(|j| {
+    ///     let mut v = root.borrow_mut(j);
+    ///     for i in 0..10 {
+    ///         v.push(Pbox::new(i, j), j);
+    ///     }
+    /// }).unwrap();
+    /// while unsafe { P::defrag::<PRefCell<PVec<Pbox<i32>>>>(4) }.unwrap() != 0 {}
+    /// assert_eq!(*root.borrow()[9], 9);
+    /// ```
+    ///
+    /// [`Relocate`]: ../trait.Relocate.html
+    /// [`Parc`]: ../sync/struct.Parc.html
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    /// [`Ref`]: ../cell/struct.Ref.html
+    unsafe fn defrag<U: PSafe + Relocate<Self>>(_max_moves: usize) -> Result<usize>
+    where Self: MemPool {
+        Err(Error::Unsupported(format!("Pool `{}` cannot be defragmented", Self::name())))
+    }
+
+    /// Returns the number of allocation zones of the pool
+    ///
+    /// Each thread allocates from the zone associated with it, and steals from
+    /// the sibling zones with the most available space when its own zone
+    /// cannot satisfy the request.
+    fn zone_count() -> usize {
+        1
+    }
+
+    /// Changes the number of allocation zones of the pool to `count`
+    ///
+    /// The number of zones is fixed when the pool is created, and is usually
+    /// the number of CPUs of the machine. This function redistributes the free
+    /// space of the pool among `count` new zones of equal size, e.g. when the
+    /// pool is reopened on a machine with a different number of CPUs. The
+    /// allocated objects remain in place, and the new configuration persists
+    /// across reopening. The operation is failure-atomic: a crash in the middle
+    /// of it is either rolled back or redone when the pool is reopened.
+    ///
+    /// The pool should be open. The other threads cannot start a transaction
+    /// or access the pool outside a transaction while the zones are rebuilt;
+    /// the running ones are waited for. [`TxRunning`] is returned if the
+    /// current thread is in a transaction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::default::*;
+    /// # type P = Allocator;
+    /// let _p = P::open_no_root("foo.pool", O_CF).unwrap();
+    /// P::reconfigure_zones(4).unwrap();
+    /// assert_eq!(P::zone_count(), 4);
+    /// ```
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    fn reconfigure_zones(_count: usize) -> Result<()> {
+        Err(Error::Unsupported(format!(
+            "Zones of pool `{}` cannot be reconfigured", Self::name()
+        )))
+    }
+
     /// Checks if the reference `p` belongs to this pool
     #[inline]
     fn valid<T: ?Sized>(p: *const T) -> bool {
@@ -794,13 +1137,28 @@ where
 
     /// Allocates new memory and then places `x` into it with `DropOnFailure` log
     unsafe fn new<'a, T: PSafe + 'a>(x: T, j: &Journal<Self>) -> &'a mut T where Self: MemPool {
+        match Self::try_new(x, j) {
+            Ok(p) => p,
+            Err(_) => panic!("Memory exhausted")
+        }
+    }
+
+    /// Allocates new memory and then places `x` into it with `DropOnFailure`
+    /// log, or returns an [`AllocError`] if there is not enough space
+    ///
+    /// On failure, `x` is dropped and the journal only contains a neutral
+    /// log, so that the transaction can continue.
+    ///
+    /// [`AllocError`]: std::alloc::AllocError
+    unsafe fn try_new<'a, T: PSafe + 'a>(x: T, j: &Journal<Self>) -> std::result::Result<&'a mut T, AllocError>
+    where Self: MemPool {
         debug_assert!(mem::size_of::<T>() != 0, "Cannot allocated ZST");
 
         let mut log = Log::drop_on_failure(u64::MAX, 1, j);
-        let (p, off, len, z) = Self::atomic_new(x);
+        let (p, off, len, z) = Self::try_atomic_new(x)?;
         log.set(off, len, z);
         Self::perform(z);
-        p
+        Ok(p)
     }
 
     /// Allocates a new slice and then places `x` into it with `DropOnAbort` log
@@ -852,17 +1210,28 @@ where
 
     /// Allocates new memory and then places `x` into it without realizing the allocation
     unsafe fn atomic_new<'a, T: 'a>(x: T) -> (&'a mut T, u64, usize, usize) {
+        match Self::try_atomic_new(x) {
+            Ok(res) => res,
+            Err(_) => panic!("Memory exhausted")
+        }
+    }
+
+    /// Allocates new memory and then places `x` into it without realizing the
+    /// allocation, or returns an [`AllocError`] if there is not enough space
+    ///
+    /// [`AllocError`]: std::alloc::AllocError
+    unsafe fn try_atomic_new<'a, T: 'a>(x: T) -> std::result::Result<(&'a mut T, u64, usize, usize), AllocError> {
         log!(Self, White, "ALLOC", "TYPE: {}", std::any::type_name::<T>());
 
         let size = mem::size_of::<T>();
         let (raw, off, len, z) = Self::pre_alloc(size);
         if raw.is_null() {
-            panic!("Memory exhausted");
+            return Err(AllocError);
         }
         Self::drop_on_failure(off, len, z);
         let p = &mut *utils::read(raw);
         mem::forget(ptr::replace(p, x));
-        (p, off, size, z)
+        Ok((p, off, size, z))
     }
 
     /// Allocates new memory and then places `x` into it without realizing the allocation
@@ -898,17 +1267,29 @@ where
 
     /// Allocates new memory without copying data
     unsafe fn new_uninit_for_layout(size: usize, journal: &Journal<Self>) -> *mut u8 where Self: MemPool {
+        match Self::try_new_uninit_for_layout(size, journal) {
+            Ok(p) => p,
+            Err(_) => panic!("Memory exhausted")
+        }
+    }
+
+    /// Allocates new memory without copying data, or returns an
+    /// [`AllocError`] if there is not enough space
+    ///
+    /// [`AllocError`]: std::alloc::AllocError
+    unsafe fn try_new_uninit_for_layout(size: usize, journal: &Journal<Self>) -> std::result::Result<*mut u8, AllocError>
+    where Self: MemPool {
         log!(Self, White, "ALLOC", "{:?}", size);
 
         let mut log = Log::drop_on_abort(u64::MAX, 1, journal);
         let (p, off, len, z) = Self::pre_alloc(size);
         if p.is_null() {
-            panic!("Memory exhausted");
+            return Err(AllocError);
         }
         Self::drop_on_failure(off, len, z);
         log.set(off, len, z);
         Self::perform(z);
-        p
+        Ok(p)
     }
 
     /// Allocates new memory without copying data and realizing the allocation
@@ -973,6 +1354,36 @@ where
         unimplemented!()
     }
 
+    /// Registers an access to the pool outside a transaction for the current
+    /// thread, waiting while another thread quiesces the pool (e.g. in
+    /// [`defrag`]). It should be paired with [`end_access`].
+    ///
+    /// [`defrag`]: #method.defrag
+    /// [`end_access`]: #method.end_access
+    unsafe fn begin_access() { }
+
+    /// Ends an access registered by [`begin_access`]
+    ///
+    /// [`begin_access`]: #method.begin_access
+    unsafe fn end_access() { }
+
+    /// Binds the current thread to the pool instance that it currently
+    /// operates on, so that the dereferences in a transaction do not resolve
+    /// the instance again. It returns the previous binding which should be
+    /// passed to [`unbind_instance`].
+    ///
+    /// [`unbind_instance`]: #method.unbind_instance
+    #[doc(hidden)]
+    #[inline]
+    unsafe fn bind_instance() -> usize { 0 }
+
+    /// Restores the binding returned by [`bind_instance`]
+    ///
+    /// [`bind_instance`]: #method.bind_instance
+    #[doc(hidden)]
+    #[inline]
+    unsafe fn unbind_instance(_prev: usize) { }
+
     /// Recovers from a crash
     unsafe fn recover() {
         unimplemented!()
@@ -1162,11 +1573,19 @@ where
     /// 
     /// assert_eq!(new, old + 1);
     /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the body panics, the changes are rolled back and [`TxAborted`] is
+    /// returned with the panic message and the location of the transaction.
+    /// If the pool is open in read-only mode, it returns [`ReadOnly`].
     /// 
+    /// [`ReadOnly`]: ../enum.Error.html#variant.ReadOnly
     /// [`Journal`]: ../stm/journal/struct.Journal.html
     /// [`TxInSafe`]: ../trait.TxInSafe.html
     /// [`TxOutSafe`]: ../trait.TxOutSafe.html
     /// [`AssertTxInSafe`]: ../struct.AssertTxInSafe.html
+    /// [`TxAborted`]: ../enum.Error.html#variant.TxAborted
     /// 
     #[inline]
     #[track_caller]
@@ -1174,15 +1593,100 @@ where
     where
         F: TxInSafe + UnwindSafe,
         T: TxOutSafe, Self: alloc::pool::MemPool
+    {
+        Self::try_transaction(|j| Ok(body(j)))
+    }
+
+    /// Executes commands atomically, and rolls them back if the body returns
+    /// an error
+    ///
+    /// It is similar to [`transaction`], except that the body returns a
+    /// `Result`. If it returns `Ok`, the transaction commits. If it returns
+    /// `Err`, all changes are rolled back and the error is returned to the
+    /// caller as is, without unwinding the stack. This is useful for
+    /// business-logic failures (e.g. insufficient funds), which should undo
+    /// the persistent writes of the transaction. A panic inside the body
+    /// still rolls back the transaction, and it is returned as
+    /// [`TxAborted`] converted into `E`.
+    ///
+    /// A nested transaction shares the journal of the outermost one, and it
+    /// starts at a savepoint. If it fails, only the changes made since the
+    /// savepoint are rolled back, and the error is returned to the outer
+    /// transaction which can continue. The changes of a successful nested
+    /// transaction are committed along with the outermost transaction. With
+    /// the "pin_journals" or the scratchpad features, savepoints are not
+    /// available; then, a failed nested transaction taints the outer
+    /// transactions, and they are rolled back as well.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use corundum::default::*;
+    /// use corundum::Error;
+    ///
+    /// type P = Allocator;
+    ///
+    /// let root = P::open::<PCell<i32>>("foo.pool", O_CF).unwrap();
+    /// transaction(|j| root.set(10, j)).unwrap();
+    ///
+    /// let res = P::try_transaction(|j| {
+    ///     root.set(root.get() - 20, j);
+    ///     if root.get() < 0 {
+    ///         return Err(Error::Other("insufficient funds".to_string()));
+    ///     }
+    ///     Ok(())
+    /// });
+    ///
+    /// assert!(res.is_err());
+    /// assert_eq!(root.get(), 10);
+    /// ```
+    ///
+    /// If the pool is open in read-only mode, the body is not executed and
+    /// [`ReadOnly`] is returned.
+    ///
+    /// [`transaction`]: #method.transaction
+    /// [`TxAborted`]: ../enum.Error.html#variant.TxAborted
+    /// [`ReadOnly`]: ../enum.Error.html#variant.ReadOnly
+    #[inline]
+    #[track_caller]
+    fn try_transaction<T, E, F>(body: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&'static Journal<Self>) -> std::result::Result<T, E>,
+        F: TxInSafe + UnwindSafe,
+        T: TxOutSafe,
+        E: TxOutSafe + From<Error>,
+        Self: alloc::pool::MemPool
     {
         #[cfg(feature = "stat_perf")]
         let _perf = crate::stat::Measure::<Self>::Transaction;
         
+        if ReadTx::<Self>::is_running() {
+            return Err(Error::Unsupported(
+                "Cannot start a transaction inside a read-only transaction".to_string()
+            ).into());
+        }
+
+        if Self::is_read_only() {
+            return Err(Error::ReadOnly.into());
+        }
+
         #[cfg(feature = "check_allocator_cyclic_links")]
         debug_assert!(Self::verify());
 
+        // The instance is resolved once for the whole transaction, including
+        // the commit or the rollback
+        struct Unbind<P: MemPool>(usize, PhantomData<P>);
+        impl<P: MemPool> Drop for Unbind<P> {
+            fn drop(&mut self) {
+                unsafe { P::unbind_instance(self.0); }
+            }
+        }
+        let _bound = Unbind::<Self>(unsafe { Self::bind_instance() }, PhantomData);
+
         let mut chaperoned = false;
         let cptr = &mut chaperoned as *mut bool;
+        let mut savepoint = None;
+        let sptr = &mut savepoint as *mut Option<Savepoint>;
         let res = std::panic::catch_unwind(|| {
             let chaperon = Chaperon::current();
             if let Some(ptr) = chaperon {
@@ -1214,6 +1718,9 @@ where
 
                     unsafe {
                         let j = Journal::<Self>::current(true).unwrap();
+                        if *j.1 > 0 {
+                            *sptr = utils::as_mut(j.0).savepoint();
+                        }
                         *j.1 += 1;
                         utils::as_mut(j.0).unset(JOURNAL_COMMITTED);
                         &*j.0
@@ -1231,15 +1738,33 @@ where
         unsafe {
             crate::ll::sfence();
 
-            if let Ok(res) = res {
-                if !chaperoned {
-                    Self::commit();
+            match res {
+                Ok(Ok(res)) => {
+                    if !chaperoned {
+                        Self::commit();
+                    }
+                    Ok(res)
                 }
-                Ok(res)
-            } else {
-                if !chaperoned {
-                    Self::rollback();
-                    Err("Unsuccessful transaction".to_string())
+                Ok(Err(e)) => if !chaperoned {
+                    if let Some(sp) = savepoint {
+                        rollback_to_savepoint::<Self>(sp);
+                    } else {
+                        Self::rollback();
+                    }
+                    Err(e)
+                } else {
+                    // Propagates the failure to the top level in enforce rollback
+                    panic!("Unsuccessful chaperoned transaction");
+                }
+                Err(payload) => if !chaperoned {
+                    if let Some(sp) = savepoint {
+                        rollback_to_savepoint::<Self>(sp);
+                    } else if std::panic::catch_unwind(|| Self::rollback()).is_err() {
+                        // The transaction is nested; the original panic is
+                        // propagated to the outer transactions
+                        std::panic::resume_unwind(payload);
+                    }
+                    Err(Error::aborted(payload, std::panic::Location::caller()).into())
                 } else {
                     // Propagates the panic to the top level in enforce rollback
                     panic!("Unsuccessful chaperoned transaction");
@@ -1248,6 +1773,164 @@ where
         }
     }
 
+    /// Executes commands atomically, and re-executes them if the transaction
+    /// is aborted due to a deadlock
+    ///
+    /// [`PMutex`] holds its lock until the transaction commits. If two
+    /// transactions lock two mutexes in opposite orders, one of them is
+    /// aborted with [`Error::Deadlock`] and its changes are rolled back, so
+    /// that the other one can proceed. This function re-runs `body` according
+    /// to the given [`RetryPolicy`], waiting for an increasing amount of time
+    /// between the attempts. Other failures are returned immediately.
+    ///
+    /// If it is called inside another transaction, only the nested
+    /// transaction is rolled back and re-executed. The locks of the outer
+    /// transaction remain held, so the deadlock may persist until the
+    /// attempts run out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use corundum::default::*;
+    /// use corundum::stm::RetryPolicy;
+    /// use std::thread;
+    ///
+    /// type P = Allocator;
+    ///
+    /// #[derive(Root)]
+    /// struct Accounts {
+    ///     a: PMutex<i32>,
+    ///     b: PMutex<i32>,
+    /// }
+    ///
+    /// let root = P::open::<Parc<Accounts>>("foo.pool", O_CF).unwrap();
+    ///
+    /// let mut threads = vec![];
+    /// for i in 0..2 {
+    ///     let root = Parc::demote(&root);
+    ///     threads.push(thread::spawn(move || {
+    ///         P::transaction_with_retry(RetryPolicy::default(), |j| {
+    ///             if let Some(root) = root.promote(j) {
+    ///                 // Locks the mutexes in opposite orders
+    ///                 let (x, y) = if i == 0 { (&root.a, &root.b) } else { (&root.b, &root.a) };
+    ///                 *x.lock(j) += 1;
+    ///                 *y.lock(j) += 1;
+    ///             }
+    ///         }).unwrap();
+    ///     }));
+    /// }
+    /// for t in threads {
+    ///     t.join().unwrap();
+    /// }
+    ///
+    /// P::transaction(|j| {
+    ///     assert_eq!(*root.a.lock(j), *root.b.lock(j));
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`PMutex`]: ../sync/struct.PMutex.html
+    /// [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+    /// [`RetryPolicy`]: ../stm/struct.RetryPolicy.html
+    #[track_caller]
+    fn transaction_with_retry<T, F>(policy: RetryPolicy, body: F) -> Result<T>
+    where
+        F: Fn(&'static Journal<Self>) -> T,
+        F: TxInSafe + RefUnwindSafe,
+        T: TxOutSafe,
+        Self: MemPool
+    {
+        let mut attempt = 0;
+        loop {
+            match Self::transaction(|j| body(j)) {
+                Err(Error::Deadlock) if attempt + 1 < policy.max_attempts => {
+                    std::thread::sleep(policy.delay(attempt));
+                    attempt += 1;
+                }
+                res => return res
+            }
+        }
+    }
+
+    /// Executes a read-only transaction
+    ///
+    /// Unlike [`transaction`], it does not allocate a journal, and it takes
+    /// no logs. Instead, `body` receives a [`ReadTx`] which allows reading
+    /// the data protected by [`PMutex`] via [`PMutex::read`]. It takes a
+    /// shared access to the mutexes, which is held until the read-only
+    /// transaction ends, so that the values read in `body` form a consistent
+    /// snapshot. Other read-only transactions may read the same mutexes
+    /// concurrently, while write transactions locking them wait. Data which
+    /// needs no lock for reading (e.g. [`PCell`] and the reference-counted
+    /// pointers) can be read as usual. A [`PRefCell`] is read via its
+    /// `borrow()` which takes a shared borrow; since it cannot be shared
+    /// between threads, no other transaction can modify it meanwhile.
+    /// Read-only transactions can run concurrently with write transactions
+    /// which do not lock the same mutexes.
+    ///
+    /// A transaction cannot be started inside a read-only transaction, and a
+    /// read-only transaction cannot be nested in a transaction of the same
+    /// pool.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use corundum::default::*;
+    ///
+    /// type P = Allocator;
+    ///
+    /// #[derive(Root)]
+    /// struct Accounts {
+    ///     a: PMutex<i32>,
+    ///     b: PMutex<i32>,
+    /// }
+    ///
+    /// let root = P::open::<Parc<Accounts>>("foo.pool", O_CF).unwrap();
+    ///
+    /// P::transaction(|j| {
+    ///     *root.a.lock(j) -= 10;
+    ///     *root.b.lock(j) += 10;
+    /// }).unwrap();
+    ///
+    /// let total = P::read_transaction(|r| *root.a.read(r) + *root.b.read(r)).unwrap();
+    /// assert_eq!(total, 0);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If a transaction is running in the current thread, it returns
+    /// [`TxRunning`]. If the body panics, the locks are released and
+    /// [`TxAborted`] is returned, or [`Deadlock`] if waiting for a lock would
+    /// cause a deadlock.
+    ///
+    /// [`transaction`]: #method.transaction
+    /// [`ReadTx`]: ../stm/struct.ReadTx.html
+    /// [`PMutex`]: ../sync/struct.PMutex.html
+    /// [`PMutex::read`]: ../sync/struct.PMutex.html#method.read
+    /// [`PCell`]: ../cell/struct.PCell.html
+    /// [`PRefCell`]: ../cell/struct.PRefCell.html
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    /// [`TxAborted`]: ../enum.Error.html#variant.TxAborted
+    /// [`Deadlock`]: ../enum.Error.html#variant.Deadlock
+    #[track_caller]
+    fn read_transaction<T, F: FnOnce(&ReadTx<Self>) -> T>(body: F) -> Result<T>
+    where
+        F: TxInSafe + UnwindSafe,
+        T: TxOutSafe,
+        Self: MemPool
+    {
+        if Journal::<Self>::is_running() {
+            return Err(Error::TxRunning);
+        }
+        let location = std::panic::Location::caller();
+        let _access = AccessGuard::<Self>::new();
+        let tx = ReadTx::<Self>::new();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| body(&tx)));
+
+        // Releases the locks
+        drop(tx);
+        res.map_err(|payload| Error::aborted(payload, location))
+    }
+
     fn gen() -> u32 {
         0
     }
@@ -1268,6 +1951,25 @@ where
     }
 }
 
+/// Keeps an access to the pool outside a transaction registered while it is
+/// alive. See [`MemPoolTraits::begin_access`].
+///
+/// [`MemPoolTraits::begin_access`]: ./trait.MemPoolTraits.html#method.begin_access
+pub(crate) struct AccessGuard<P: MemPoolTraits>(PhantomData<(P, *const ())>);
+
+impl<P: MemPoolTraits> AccessGuard<P> {
+    pub(crate) fn new() -> Self {
+        unsafe { P::begin_access(); }
+        Self(PhantomData)
+    }
+}
+
+impl<P: MemPoolTraits> Drop for AccessGuard<P> {
+    fn drop(&mut self) {
+        unsafe { P::end_access(); }
+    }
+}
+
 pub struct PoolGuard<P: MemPoolTraits>(pub PhantomData<P>);
 
 impl<P: MemPoolTraits> PoolGuard<P> {
@@ -1301,17 +2003,211 @@ pub unsafe trait MemPool:
     RefUnwindSafe + 
     UnwindSafe {}
 
-pub(crate) fn create_file(filename: &str, size: u64) -> Result<()> {
-    let file = OpenOptions::new().write(true).create(true).open(filename);
-    if file.is_err() {
-        Err(format!("{}", file.err().unwrap()))
+/// Reverts a failed nested transaction to its savepoint
+unsafe fn rollback_to_savepoint<A: MemPool>(sp: Savepoint) {
+    if let Some(journal) = Journal::<A>::current(false) {
+        *journal.1 -= 1;
+        log!(A, White, "ROLLBACK_TO", "JRNL: {:?}", journal.0);
+        as_mut(journal.0).rollback_to(sp,
+            #[cfg(feature = "check_double_free")]
+            &mut *A::dealloc_history()
+        );
+    }
+}
+
+/// Takes an advisory lock on an open pool file
+///
+/// An exclusive lock is taken to open the pool for writing, and a shared lock
+/// to only view it. The lock is held until the file is closed. It does not
+/// wait for the lock, and returns [`PoolLocked`] if it is held by another
+/// open pool in this or another process.
+///
+/// The lock keeps processes apart, unless they open the pool in the shared
+/// mode (see [`O_SHARED`]). Such processes take a shared lock, and they join
+/// each other via [`join_shared`].
+///
+/// Advisory locks are only taken on Unix; elsewhere, it always succeeds.
+///
+/// [`PoolLocked`]: ./enum.Error.html#variant.PoolLocked
+/// [`O_SHARED`]: ./open_flags/constant.O_SHARED.html
+/// [`join_shared`]: ./fn.join_shared.html
+#[cfg(unix)]
+pub fn lock_file(file: &std::fs::File, path: &str, exclusive: bool) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let op = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+    if unsafe { libc::flock(file.as_raw_fd(), op | libc::LOCK_NB) } == 0 {
+        Ok(())
     } else {
-        if let Some(e) = file.unwrap().set_len(size).err() {
-            Err(format!("{}", e))
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+            Err(Error::PoolLocked(path.to_string()))
         } else {
-            Ok(())
+            Err(e.into())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn lock_file(_file: &std::fs::File, _path: &str, _exclusive: bool) -> Result<()> {
+    Ok(())
+}
+
+/// Joins the processes which have opened a pool file in the shared mode
+///
+/// The processes hold a read lock on the first byte of the file while the
+/// pool is open. It returns true if there is no other process, and then the
+/// current process is the first one, which should recover the pool and call
+/// [`shared_ready`]. It holds a write lock meanwhile, so that the other
+/// processes wait until the pool is recovered.
+///
+/// The locks are open file description locks, which are released when the
+/// file is closed. They are only available on Linux; elsewhere, it returns
+/// [`Unsupported`].
+///
+/// [`shared_ready`]: ./fn.shared_ready.html
+/// [`Unsupported`]: ./enum.Error.html#variant.Unsupported
+#[cfg(target_os = "linux")]
+pub fn join_shared(file: &std::fs::File) -> Result<bool> {
+    if lock_first_byte(file, libc::F_WRLCK, false)? {
+        Ok(true)
+    } else {
+        lock_first_byte(file, libc::F_RDLCK, true)?;
+        Ok(false)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn join_shared(_file: &std::fs::File) -> Result<bool> {
+    Err(Error::Unsupported("The shared mode is only supported on Linux".to_string()))
+}
+
+/// Lets the other processes join the shared pool after the first process
+/// recovered it. See [`join_shared`].
+///
+/// [`join_shared`]: ./fn.join_shared.html
+#[cfg(target_os = "linux")]
+pub fn shared_ready(file: &std::fs::File) -> Result<()> {
+    // Converting the lock is atomic, so no process can take the write lock
+    // in between
+    lock_first_byte(file, libc::F_RDLCK, false)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn shared_ready(_file: &std::fs::File) -> Result<()> {
+    Err(Error::Unsupported("The shared mode is only supported on Linux".to_string()))
+}
+
+/// Takes an open file description lock of type `ty` on the first byte of
+/// `file`. It returns false if it is held by another process and `wait` is
+/// false.
+#[cfg(target_os = "linux")]
+fn lock_first_byte(file: &std::fs::File, ty: libc::c_int, wait: bool) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = ty as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock.l_start = 0;
+    lock.l_len = 1;
+    let cmd = if wait { libc::F_OFD_SETLKW } else { libc::F_OFD_SETLK };
+    loop {
+        if unsafe { libc::fcntl(file.as_raw_fd(), cmd, &lock) } == 0 {
+            return Ok(true);
+        }
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EAGAIN) | Some(libc::EACCES) if !wait => return Ok(false),
+            _ => return Err(e.into()),
+        }
+    }
+}
+
+/// Copies a pool image to a new file at `path`
+///
+/// The data is copied from `file` with `copy_file_range`, so that the file
+/// system may share the extents instead of copying them. If it is not
+/// supported, or `file` is `None`, the data is written from `image`, which
+/// should be the mapped contents of `file`.
+///
+/// The image is written to a temporary file which then replaces `path`, so
+/// that `path` is either left intact or holds the complete image. If `path`
+/// is a pool which is open in this or another process, it returns
+/// [`PoolLocked`].
+///
+/// [`PoolLocked`]: ./enum.Error.html#variant.PoolLocked
+pub fn copy_image(file: Option<&std::fs::File>, image: &[u8], path: &str) -> Result<()> {
+    // The lock on the old file is held until it is replaced
+    let _old = if Path::new(path).exists() {
+        let old = OpenOptions::new().read(true).open(path)?;
+        lock_file(&old, path, true)?;
+        Some(old)
+    } else {
+        None
+    };
+
+    let tmp = format!("{}.tmp{}", path, std::process::id());
+    let res = write_image(file, image, &tmp).and_then(|_| {
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    });
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Writes the image to a new file at `path`. See [`copy_image`].
+///
+/// [`copy_image`]: ./fn.copy_image.html
+fn write_image(file: Option<&std::fs::File>, image: &[u8], path: &str) -> Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let mut dst = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    let len = image.len();
+    let copied = match file {
+        Some(src) => copy_range(src, &dst, len),
+        None => 0
+    };
+    if copied < len {
+        dst.seek(SeekFrom::Start(copied as u64))?;
+        dst.write_all(&image[copied..])?;
+    }
+    dst.sync_all()?;
+    Ok(())
+}
+
+/// Copies up to `len` bytes from the beginning of `src` to `dst`, and returns
+/// the number of bytes copied
+#[cfg(target_os = "linux")]
+fn copy_range(src: &std::fs::File, dst: &std::fs::File, len: usize) -> usize {
+    use std::os::unix::io::AsRawFd;
+
+    let mut off_in: libc::loff_t = 0;
+    let mut off_out: libc::loff_t = 0;
+    let mut copied = 0;
+    while copied < len {
+        let n = unsafe {
+            libc::copy_file_range(src.as_raw_fd(), &mut off_in,
+                dst.as_raw_fd(), &mut off_out, len - copied, 0)
+        };
+        if n <= 0 {
+            break;
         }
+        copied += n as usize;
     }
+    copied
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_range(_src: &std::fs::File, _dst: &std::fs::File, _len: usize) -> usize {
+    0
 }
 
 #[cfg(test)]
@@ -1331,13 +2227,75 @@ mod test {
                         println!("should print");
                         panic!("intentional");
                     });
-                    println!("should not print");
+                    println!("should print after the savepoint is restored");
                 });
-                println!("should not print");
+                println!("should print");
             });
-            println!("should not print");
+            println!("should print");
         }) {
             println!("Error: '{}'", e);
         }
     }
+
+    #[test]
+    fn nested_savepoints() {
+        let root = Allocator::open::<(PCell<i32>, PCell<i32>)>("savepoint.pool", O_CF).unwrap();
+        Allocator::transaction(|j| {
+            root.0.set(1, j);
+            root.1.set(1, j);
+        }).unwrap();
+
+        Allocator::transaction(|j| {
+            root.0.set(2, j);
+            let res = Allocator::try_transaction(|j| {
+                root.0.set(3, j);
+                root.1.set(3, j);
+                Err::<(), _>(crate::Error::Other("inner".to_string()))
+            });
+            assert!(res.is_err());
+            assert_eq!(root.0.get(), 2);
+            assert_eq!(root.1.get(), 1);
+
+            assert!(Allocator::transaction(|j| {
+                root.1.set(4, j);
+                panic!("intentional");
+            }).is_err());
+            assert_eq!(root.1.get(), 1);
+
+            Allocator::transaction(|j| root.1.set(5, j)).unwrap();
+        }).unwrap();
+        assert_eq!(root.0.get(), 2);
+        assert_eq!(root.1.get(), 5);
+
+        // The outer transaction rolls back the committed nested ones
+        assert!(Allocator::transaction(|_| {
+            Allocator::transaction(|j| root.0.set(6, j)).unwrap();
+            panic!("intentional");
+        }).is_err());
+        assert_eq!(root.0.get(), 2);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn join_shared_pool() {
+        use crate::alloc::{join_shared, shared_ready};
+        use std::fs::OpenOptions;
+
+        let path = "join_shared.pool";
+        let open = || OpenOptions::new().read(true).write(true).create(true)
+            .open(path).unwrap();
+        let first = open();
+        assert!(join_shared(&first).unwrap());
+        shared_ready(&first).unwrap();
+        let second = open();
+        assert!(!join_shared(&second).unwrap());
+
+        // The last process leaves, and the next one recovers the pool
+        drop(first);
+        drop(second);
+        let third = open();
+        assert!(join_shared(&third).unwrap());
+        drop(third);
+        let _ = std::fs::remove_file(path);
+    }
 }
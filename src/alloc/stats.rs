@@ -0,0 +1,185 @@
+//! Pool and zone statistics
+
+use std::fmt;
+
+/// Statistics of a single memory zone
+///
+/// See [`PoolStats`] for more details.
+///
+/// [`PoolStats`]: ./struct.PoolStats.html
+#[derive(Clone, Debug, Default)]
+pub struct ZoneStats {
+    /// Total size of the zone in bytes
+    pub size: usize,
+
+    /// Number of bytes in use
+    pub used: usize,
+
+    /// Number of available bytes
+    pub available: usize,
+
+    /// Free-list histogram by order. The `k`-th item is the number of free
+    /// blocks with a size in range `[2^k, 2^(k+1))`.
+    pub free_blocks: Vec<usize>,
+
+    /// Size of the largest free block in bytes
+    pub largest_free: usize,
+
+    /// Number of allocated objects
+    ///
+    /// After the zones of the pool are reconfigured, the objects allocated
+    /// before are accounted to the first zone, and the count of a zone which
+    /// frees them wraps around. Only the sum over all zones is exact.
+    pub objects: usize,
+}
+
+impl ZoneStats {
+    /// Creates an empty statistics object with a zeroed histogram
+    pub fn new(size: usize, available: usize, objects: usize) -> Self {
+        Self {
+            size,
+            used: size - available,
+            available,
+            free_blocks: vec![0; 64],
+            largest_free: 0,
+            objects,
+        }
+    }
+
+    /// Adds a free block of `len` bytes to the histogram
+    pub fn add_free_block(&mut self, len: usize) {
+        if len != 0 {
+            self.free_blocks[63 - len.leading_zeros() as usize] += 1;
+            self.largest_free = self.largest_free.max(len);
+        }
+    }
+
+    /// Returns the fragmentation ratio of the zone
+    ///
+    /// It is the fraction of the available space which is not in the largest
+    /// free block, ranging from 0 (no fragmentation) to close to 1.
+    pub fn fragmentation(&self) -> f64 {
+        fragmentation(self.largest_free, self.available)
+    }
+}
+
+/// Statistics of a memory pool
+///
+/// It is obtained using [`MemPool::stats()`], and contains a snapshot of the
+/// space usage and the free-list histograms of all zones, the number of
+/// active journals and their pages, and the number of allocated objects.
+///
+/// # Examples
+///
+/// ```
+/// # use corundum::default::*;
+/// # type P = Allocator;
+/// let _p = P::open_no_root("foo.pool", O_CF).unwrap();
+/// let stats = P::stats();
+/// assert_eq!(stats.used + stats.available, P::used() + P::available());
+/// assert_eq!(stats.size, stats.used + stats.available + stats.overhead);
+/// if stats.fragmentation() > 0.9 {
+///     eprintln!("pool is fragmented");
+/// }
+/// ```
+///
+/// [`MemPool::stats()`]: ./trait.MemPoolTraits.html#method.stats
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats {
+    /// Total size of the pool in bytes
+    ///
+    /// It is the sum of `used`, `available`, and `overhead`.
+    pub size: usize,
+
+    /// Number of bytes in use
+    pub used: usize,
+
+    /// Number of available bytes
+    pub available: usize,
+
+    /// Number of bytes which no zone manages, e.g. the tail of a zone which
+    /// the allocator cannot use. The pool metadata is counted as `used`.
+    pub overhead: usize,
+
+    /// Free-list histogram by order for all zones
+    pub free_blocks: Vec<usize>,
+
+    /// Size of the largest free block in bytes
+    pub largest_free: usize,
+
+    /// Number of allocated objects
+    pub objects: usize,
+
+    /// Number of journal objects
+    pub journals: usize,
+
+    /// Total number of journal pages
+    pub journal_pages: usize,
+
+    /// Statistics of the zones
+    pub zones: Vec<ZoneStats>,
+}
+
+impl PoolStats {
+    /// Creates an empty statistics object for a pool of `size` bytes
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            free_blocks: vec![0; 64],
+            ..Default::default()
+        }
+    }
+
+    /// Adds the statistics of a zone
+    pub fn add_zone(&mut self, zone: ZoneStats) {
+        self.used += zone.used;
+        self.available += zone.available;
+        self.overhead = self.size - self.used - self.available;
+        self.objects = self.objects.wrapping_add(zone.objects);
+        self.largest_free = self.largest_free.max(zone.largest_free);
+        for (i, n) in zone.free_blocks.iter().enumerate() {
+            self.free_blocks[i] += n;
+        }
+        self.zones.push(zone);
+    }
+
+    /// Returns the fragmentation ratio of the pool
+    ///
+    /// It is the fraction of the available space which is not in the largest
+    /// free block, ranging from 0 (no fragmentation) to close to 1.
+    pub fn fragmentation(&self) -> f64 {
+        fragmentation(self.largest_free, self.available)
+    }
+}
+
+fn fragmentation(largest: usize, available: usize) -> f64 {
+    if available == 0 {
+        0.0
+    } else {
+        1.0 - largest as f64 / available as f64
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "         Total: {} bytes", self.size)?;
+        writeln!(f, "          Used: {} bytes", self.used)?;
+        writeln!(f, "     Available: {} bytes", self.available)?;
+        writeln!(f, "      Overhead: {} bytes", self.overhead)?;
+        writeln!(f, " Largest Block: {} bytes", self.largest_free)?;
+        writeln!(f, " Fragmentation: {:.2}%", self.fragmentation() * 100.0)?;
+        writeln!(f, "       Objects: {}", self.objects)?;
+        writeln!(f, "      Journals: {} ({} pages)", self.journals, self.journal_pages)?;
+        for (i, z) in self.zones.iter().enumerate() {
+            write!(f, "  Zone #{:<3} used {:>12} / {:<12} frag {:>6.2}%  free blocks:",
+                i, z.used, z.size, z.fragmentation() * 100.0)?;
+            for (k, n) in z.free_blocks.iter().enumerate() {
+                if *n != 0 {
+                    write!(f, " 2^{}:{}", k, n)?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
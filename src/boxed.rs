@@ -5,7 +5,8 @@ use crate::cell::RootObj;
 use crate::clone::*;
 use crate::ptr::Ptr;
 use crate::stm::*;
-use crate::{PSafe, VSafe, TxOutSafe};
+use crate::{PSafe, VSafe, TxOutSafe, Relocate, Relocator};
+use std::alloc::AllocError;
 use std::cmp::Ordering;
 use std::convert::From;
 use std::fmt;
@@ -138,6 +139,39 @@ impl<T: PSafe, A: MemPool> Pbox<T, A> {
         }
     }
 
+    /// Allocates memory on the persistent heap and then places `x` into it,
+    /// or returns an [`AllocError`] if the pool is out of space.
+    ///
+    /// Unlike [`new`], it does not panic on failure, and the transaction can
+    /// continue. `x` is dropped if the allocation fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::default::*;
+    /// # use corundum::Error;
+    /// # type P = Allocator;
+    /// # let _p = P::open_no_root("foo.pool", O_CF).unwrap();
+    /// P::try_transaction(|j| {
+    ///     let five = Pbox::try_new(5, j)?;
+    ///     assert_eq!(*five, 5);
+    ///     Ok::<_, Error>(())
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`new`]: #method.new
+    /// [`AllocError`]: std::alloc::AllocError
+    pub fn try_new(x: T, journal: &Journal<A>) -> Result<Pbox<T, A>, AllocError> {
+        if mem::size_of::<T>() == 0 {
+            Ok(Pbox(Ptr::dangling(), 0))
+        } else {
+            unsafe {
+                let p = A::try_new(x, journal)?;
+                Ok(Pbox(Ptr::from_mut(p), 0))
+            }
+        }
+    }
+
     pub fn off(&self) -> u64 {
         self.0.off()
     }
@@ -255,7 +289,7 @@ impl<T: PSafe, A: MemPool> Pbox<T, A> {
             "Pbox::initialize() cannot be used inside a transaction"
         );
         match boxed {
-            Some(_) => Err("already initialized".to_string()),
+            Some(_) => Err(crate::Error::AlreadyInitialized),
             None => if A::valid(boxed) {
                 unsafe {
                     let new = A::atomic_new(value);
@@ -270,7 +304,9 @@ impl<T: PSafe, A: MemPool> Pbox<T, A> {
                 }
                 Ok(())
             } else {
-                Err("The object is not in the PM".to_string())
+                Err(crate::Error::InvalidArgument(
+                    "The object is not in the PM".to_string()
+                ))
             }
         }
     }
@@ -449,6 +485,14 @@ impl<T: RootObj<A> + PSafe, A: MemPool> RootObj<A> for Pbox<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for Pbox<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        if r.visit(self.0.off_ref(), mem::size_of::<T>()) {
+            self.0.as_ref().visit(r)
+        }
+    }
+}
+
 impl<T: PSafe + PClone<A> + ?Sized, A: MemPool> PClone<A> for Pbox<T, A> {
     /// Returns a new box with a `pclone()` of this box's contents.
     ///
@@ -471,6 +471,19 @@ impl<T: PSafe + Default, A: MemPool> PCell<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for PCell<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        unsafe {
+            #[cfg(any(feature = "use_pspd", feature = "use_vspd"))] {
+                (*self.value.get()).visit(r)
+            }
+            #[cfg(not(any(feature = "use_pspd", feature = "use_vspd")))] {
+                (*self.value.get()).1.visit(r)
+            }
+        }
+    }
+}
+
 impl<T: fmt::Debug + PSafe + Copy, A: MemPool> fmt::Debug for PCell<T, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         #[cfg(any(feature = "use_pspd", feature = "use_vspd"))] {
@@ -195,6 +195,19 @@ impl<T: PSafe + RootObj<A>, A: MemPool> RootObj<A> for PRefCell<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for PRefCell<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        unsafe {
+            #[cfg(any(feature = "use_pspd", feature = "use_vspd"))] {
+                (*self.value.get()).visit(r)
+            }
+            #[cfg(not(any(feature = "use_pspd", feature = "use_vspd")))] {
+                (*self.value.get()).1.visit(r)
+            }
+        }
+    }
+}
+
 impl<T: PSafe + Display + ?Sized, A: MemPool> Display for PRefCell<T, A> {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         self.as_ref().fmt(fmt)
@@ -100,6 +100,134 @@ pub trait RootObj<A: MemPool> {
     fn init(journal: &Journal<A>) -> Self;
 }
 
+/// Describes the field layout of a root type
+///
+/// The fingerprint of the root type is recorded in the pool when the root
+/// object is created, and it is checked when the pool is opened again (see
+/// [`RootFingerprint`]). `#[derive(Root)]` implements this trait with a hash of
+/// the names and the types of the fields, so that adding, removing, renaming,
+/// or retyping a field is detected even if the size of the type stays the
+/// same. The field types are hashed as they are spelled, without their paths
+/// (e.g. `std::vec::Vec<u8>` as `Vec<u8>`), so moving a type between modules
+/// keeps the schema. Other types are only identified by their size and
+/// alignment.
+///
+/// The derived root type is recorded with its name, which can be changed with
+/// `#[root(name = "...")]`, e.g. to rename the type without invalidating the
+/// pools created with its old name.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// type P = Allocator;
+///
+/// mod v1 {
+///     use corundum::default::*;
+///     #[derive(Root)]
+///     pub struct Data { pub a: PCell<u64> }
+/// }
+///
+/// mod v2 {
+///     use corundum::default::*;
+///     #[derive(Root)]
+///     pub struct Data { pub a: PCell<u64>, pub b: PCell<u64> }
+/// }
+///
+/// {
+///     let _root = P::open::<v1::Data>("schema.pool", O_CF).unwrap();
+/// }
+/// let err = P::open::<v2::Data>("schema.pool", 0).err().unwrap();
+/// assert!(err.to_string().starts_with("Incompatible root type"));
+///
+/// #[derive(Root)]
+/// #[root(name = "Data")]
+/// struct Renamed { a: PCell<u64> }
+///
+/// let _root = P::open::<Renamed>("schema.pool", 0).unwrap();
+/// ```
+///
+/// [`RootFingerprint`]: ./struct.RootFingerprint.html
+pub trait RootSchema {
+    /// Returns the hash of the field layout, or 0 if it is unknown
+    fn schema_hash() -> u64;
+
+    /// Returns the stable name of the type, or `None` if it has none
+    fn root_name() -> Option<String>;
+}
+
+impl<T: ?Sized> RootSchema for T {
+    default fn schema_hash() -> u64 {
+        0
+    }
+
+    default fn root_name() -> Option<String> {
+        None
+    }
+}
+
+macro_rules! primitive_schema {
+    ($($t:ty),*) => {
+        $(impl RootSchema for $t {
+            fn schema_hash() -> u64 {
+                0
+            }
+
+            fn root_name() -> Option<String> {
+                Some(stringify!($t).to_string())
+            }
+        })*
+    };
+}
+
+primitive_schema!(bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+// The persistent wrappers carry the name and the schema of the wrapped type,
+// so that e.g. `Parc<Root>` is checked as thoroughly as `Root`
+macro_rules! wrapper_schema {
+    ($($name:literal => $t:ident[$($bound:tt)*]),*) => {
+        $(impl<T: $($bound)*, A: MemPool> RootSchema for $t<T, A> {
+            fn schema_hash() -> u64 {
+                match T::schema_hash() {
+                    0 => 0,
+                    s => schema_hash($name, &[s]),
+                }
+            }
+
+            fn root_name() -> Option<String> {
+                T::root_name().map(|n| format!("{}<{}>", $name, n))
+            }
+        })*
+    };
+}
+
+wrapper_schema!(
+    "Pbox" => Pbox[PSafe + ?Sized],
+    "Prc" => Prc[PSafe + ?Sized],
+    "Parc" => Parc[PSafe + ?Sized],
+    "PCell" => PCell[PSafe + ?Sized],
+    "PRefCell" => PRefCell[PSafe + ?Sized],
+    "PMutex" => PMutex[],
+    "PRwLock" => PRwLock[],
+    "PVec" => PVec[PSafe]
+);
+
+/// Combines the description of a type layout and the schema hashes of its
+/// fields into a single hash
+///
+/// It uses 64-bit FNV-1a, which is stable across compilers and platforms.
+/// It is used by `#[derive(Root)]`.
+pub fn schema_hash(layout: &str, fields: &[u64]) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    let bytes = fields.iter().flat_map(|f| f.to_le_bytes());
+    for b in layout.bytes().chain(bytes) {
+        h ^= b as u64;
+        h = h.wrapping_mul(PRIME);
+    }
+    h
+}
+
 impl<T: Default, A: MemPool> RootObj<A> for T {
     default fn init(_journal: &Journal<A>) -> Self {
         T::default()
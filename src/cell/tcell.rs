@@ -1,7 +1,7 @@
 use crate::RootObj;
 use crate::stm::Journal;
 use crate::alloc::MemPool;
-use crate::{PSafe, VSafe, utils};
+use crate::{PSafe, VSafe, Relocate, Relocator, utils};
 use std::cmp::*;
 use std::mem::*;
 use std::marker::PhantomData;
@@ -130,6 +130,10 @@ impl<T: Default + VSafe, A: MemPool> RootObj<A> for TCell<T, A> {
     }
 }
 
+unsafe impl<T: Default + VSafe, A: MemPool> Relocate<A> for TCell<T, A> {
+    fn visit(&self, _r: &mut Relocator<A>) {}
+}
+
 impl<T: Default + VSafe, A: MemPool> Deref for TCell<T, A> {
     type Target = T;
 
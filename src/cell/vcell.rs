@@ -1,5 +1,5 @@
 use crate::alloc::MemPool;
-use crate::{PSafe, VSafe, utils};
+use crate::{PSafe, VSafe, Relocate, Relocator, utils};
 use std::cmp::*;
 use std::mem::*;
 use std::marker::PhantomData;
@@ -96,6 +96,11 @@ impl<T: Default + VSafe, A: MemPool> VCell<T, A> {
     }
 }
 
+/// Volatile data is reset on every open, so there is nothing to relocate
+unsafe impl<T: Default + VSafe, A: MemPool> Relocate<A> for VCell<T, A> {
+    fn visit(&self, _r: &mut Relocator<A>) {}
+}
+
 impl<T: Default + VSafe, A: MemPool> Default for VCell<T, A> {
     fn default() -> Self {
         Self {
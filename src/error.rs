@@ -0,0 +1,181 @@
+//! Error type of the pool operations
+
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::panic::Location;
+
+/// The error type of the pool operations
+///
+/// It is returned by the functions opening and formatting pools, running
+/// transactions and chaperoned sessions, and managing pools (e.g. growing,
+/// compacting, and defragmenting), so that the callers can react to the
+/// failures programmatically.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// use corundum::Error;
+/// type P = Allocator;
+///
+/// let _p = P::open_no_root("foo.pool", O_CF).unwrap();
+/// match P::transaction(|_| panic!("intentional")) {
+///     Err(Error::TxAborted { payload, .. }) => assert_eq!(payload, "intentional"),
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An I/O error while accessing a pool or a chaperon file
+    IoError(io::Error),
+
+    /// The file is not a valid pool image, or it is not compatible with the
+    /// current build
+    InvalidPool(String),
+
+    /// The requested root type does not match the root object of the pool
+    RootTypeMismatch(String),
+
+    /// The pool has no root object
+    NoRootObject,
+
+    /// There is not enough space in the pool for `requested` bytes
+    OutOfMemory { requested: usize },
+
+    /// The pool cannot grow to `requested` bytes, because it is beyond the
+    /// `reserved` address space of the open pool
+    ReservationExceeded { requested: usize, reserved: usize },
+
+    /// The transaction was rolled back because its body panicked
+    TxAborted {
+        /// The panic message
+        payload: String,
+
+        /// The location where the transaction was started
+        location: &'static Location<'static>,
+    },
+
+    /// An uncommitted transaction exists in the pool
+    TxRunning,
+
+    /// The transaction was aborted because waiting for a lock would cause a
+    /// deadlock; it can be retried
+    Deadlock,
+
+    /// A chaperon file which is required for recovering the pool is missing
+    ChaperonMissing(String),
+
+    /// Another chaperoned session is open in the current thread
+    ChaperonBusy,
+
+    /// The pool is not open
+    PoolNotOpen,
+
+    /// The pool file is locked by another open pool, e.g. in another process
+    PoolLocked(String),
+
+    /// The pool is open in read-only mode, and it cannot be modified
+    ReadOnly,
+
+    /// The object is already initialized
+    AlreadyInitialized,
+
+    /// The offset or the pointer is not in the valid range of the pool
+    AccessViolation(u64),
+
+    /// The operation is not supported by the pool type
+    Unsupported(String),
+
+    /// An argument is invalid
+    InvalidArgument(String),
+
+    /// Other errors
+    Other(String),
+}
+
+impl Error {
+    /// Creates a [`TxAborted`](#variant.TxAborted) error out of a panic
+    /// payload. If the payload is an `Error` itself, it is returned as is.
+    pub fn aborted(payload: Box<dyn Any + Send>, location: &'static Location<'static>) -> Self {
+        let payload = match payload.downcast::<Error>() {
+            Ok(e) => return *e,
+            Err(payload) => payload
+        };
+        let payload = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "Box<dyn Any>".to_string()
+        };
+        Error::TxAborted { payload, location }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::IoError(e) => write!(f, "{}", e),
+            Error::InvalidPool(s) => write!(f, "{}", s),
+            Error::RootTypeMismatch(s) => write!(f, "{}", s),
+            Error::NoRootObject => write!(f, "The pool has no root object"),
+            Error::OutOfMemory { requested } => {
+                write!(f, "No space left (requested = {})", requested)
+            }
+            Error::ReservationExceeded { requested, reserved } => write!(f,
+                "Cannot grow the pool to {} bytes beyond its reserved address space ({} bytes)",
+                requested, reserved),
+            Error::TxAborted { payload, location } => {
+                write!(f, "Unsuccessful transaction at {}: {}", location, payload)
+            }
+            Error::TxRunning => write!(f, "An uncommitted transaction exists in the pool"),
+            Error::Deadlock => write!(f, "Deadlock detected; the transaction was aborted"),
+            Error::ChaperonMissing(s) => write!(f, "Missing chaperon file `{}`", s),
+            Error::ChaperonBusy => write!(f, "Another chaperoned transaction is open"),
+            Error::PoolNotOpen => write!(f, "No memory pool is open"),
+            Error::PoolLocked(s) => write!(f, "The pool file `{}` is in use by another pool", s),
+            Error::ReadOnly => write!(f, "The pool is open in read-only mode"),
+            Error::AlreadyInitialized => write!(f, "already initialized"),
+            Error::AccessViolation(off) => write!(f, "Access Violation (0x{:x})", off),
+            Error::Unsupported(s) => write!(f, "{}", s),
+            Error::InvalidArgument(s) => write!(f, "{}", s),
+            Error::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::IoError(e)
+    }
+}
+
+impl From<std::alloc::AllocError> for Error {
+    /// The size of the failed request is not known, and it is reported as 0
+    fn from(_: std::alloc::AllocError) -> Self {
+        Error::OutOfMemory { requested: 0 }
+    }
+}
+
+impl From<String> for Error {
+    fn from(s: String) -> Self {
+        Error::Other(s)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(s: &str) -> Self {
+        Error::Other(s.to_string())
+    }
+}
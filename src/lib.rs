@@ -53,6 +53,11 @@
 //! with dynamically checked borrow rules for a value of type `T` in pool `P`.
 //! * [`PMutex<T,P>`] (or [`PMutex<T>`]): A mutual exclusion primitive useful for
 //! protecting shared persistent data of type `T` in pool `P`.
+//! * [`PRwLock<T,P>`] (or [`PRwLock<T>`]): A reader-writer lock which allows
+//! concurrent readers of shared persistent data of type `T` in pool `P`.
+//! * [`PAtomicU64<P>`] (or [`PAtomicU64`]), `PAtomicUsize` and `PAtomicBool`:
+//! Persistent atomic values which can also be updated outside transactions,
+//! e.g. for statistics and sequence numbers.
 //! 
 //! The following example creates a pool file for a linked-list-based stack, and
 //! obtains the root object of type `Node`.
@@ -99,6 +104,10 @@
 //! [`PRefCell<T>`]: ./alloc/default/type.PRefCell.html
 //! [`PMutex<T,P>`]: ./sync/struct.PMutex.html
 //! [`PMutex<T>`]: ./alloc/default/type.PMutex.html
+//! [`PRwLock<T,P>`]: ./sync/struct.PRwLock.html
+//! [`PRwLock<T>`]: ./alloc/default/type.PRwLock.html
+//! [`PAtomicU64<P>`]: ./sync/struct.PAtomicU64.html
+//! [`PAtomicU64`]: ./alloc/default/type.PAtomicU64.html
 //! [`open<T>()`]: ./alloc/struct.MemPool.html#method.open
 
 #![feature(auto_traits)]
@@ -157,32 +166,36 @@ mod alloc;
 mod boxed;
 mod cell;
 mod clone;
+mod relocate;
 mod str;
 pub mod vec;
 mod convert;
+mod error;
 mod marker;
 mod tests;
 
 pub use cell::RootObj;
-pub use stm::transaction;
+pub use stm::{read_transaction, transaction, transaction_with_retry, try_transaction};
 pub use marker::*;
 pub use crndm_derive::*;
 pub use boxed::*;
 pub use prc::Prc;
-pub use sync::{Parc,PMutex};
+pub use sync::{Parc,PAtomicBool,PAtomicU64,PAtomicUsize,PCondvar,PMutex,PRwLock};
 pub use clone::*;
+pub use relocate::*;
 pub use vec::Vec as PVec;
 pub use self::str::{String as PString, ToPString, ToPStringSlice};
 pub use cell::*;
 pub use alloc::*;
 pub use convert::*;
 pub use stm::Journal;
+pub use error::Error;
 
 // This is an example of defining a new buddy allocator type
 // `Allocator` is the default allocator with Buddy Allocation algorithm
 crate::pool!(default);
 
-/// A `Result` type with string error messages
+/// A `Result` type with [`Error`](../enum.Error.html) as the error type
 pub mod result {
-    pub type Result<T: ?Sized> = std::result::Result<T, String>;
+    pub type Result<T: ?Sized> = std::result::Result<T, crate::Error>;
 }
\ No newline at end of file
@@ -2,7 +2,9 @@
 #![allow(unused)]
 
 use crate::alloc::MemPool;
+use crate::cell::LazyCell;
 use std::arch::asm;
+use std::sync::RwLock;
 
 #[inline(always)]
 pub fn cpu() -> usize {
@@ -53,6 +55,9 @@ pub fn persist<T: ?Sized>(ptr: *const T, len: usize, fence: bool) {
             {
                 panic!("persist failed");
             }
+
+            #[cfg(feature = "replicate")]
+            mirror(ptr as usize, len);
         }
     }
 }
@@ -82,7 +87,21 @@ pub fn persist_obj<T: ?Sized>(obj: &T, fence: bool) {
 pub fn clflush<T: ?Sized>(ptr: *const T, len: usize, fence: bool) {
     #[cfg(not(feature = "no_persist"))]
     {
-        let ptr = ptr as *const u8 as *mut u8;
+        flush_lines(ptr as *const u8, len);
+
+        #[cfg(feature = "replicate")]
+        mirror(ptr as *const u8 as usize, len);
+    }
+    if (fence) {
+        sfence();
+    }
+}
+
+#[inline(always)]
+fn flush_lines(ptr: *const u8, len: usize) {
+    #[cfg(not(feature = "no_persist"))]
+    {
+        let ptr = ptr as *mut u8;
         let mut start = ptr as usize;
         start = (start >> 9) << 9;
         let end = start + len;
@@ -114,8 +133,88 @@ pub fn clflush<T: ?Sized>(ptr: *const T, len: usize, fence: bool) {
             start += 64;
         }
     }
-    if (fence) {
-        sfence();
+}
+
+/// The mirrored address ranges as `(start, end, replica)`
+static mut REPLICAS: LazyCell<RwLock<Vec<(usize, usize, usize)>>> =
+    LazyCell::new(|| RwLock::new(Vec::new()));
+
+/// Indicates if the flushes can be mirrored to replicas, which requires the
+/// "replicate" feature
+pub const REPLICATION: bool = cfg!(feature = "replicate");
+
+fn replicas() -> &'static RwLock<Vec<(usize, usize, usize)>> {
+    unsafe { &*REPLICAS }
+}
+
+/// Mirrors the flushes in `[start, start + len)` to the same offsets from
+/// `replica`
+///
+/// # Safety
+///
+/// `replica` should point to a mapping of at least `len` bytes which remains
+/// valid until [`remove_replica`] is called for `start`.
+///
+/// [`remove_replica`]: ./fn.remove_replica.html
+pub unsafe fn add_replica(start: usize, len: usize, replica: usize) {
+    let mut replicas = match replicas().write() {
+        Ok(g) => g,
+        Err(p) => p.into_inner()
+    };
+    replicas.retain(|r| r.0 != start);
+    replicas.push((start, start + len, replica));
+}
+
+/// Changes the length of a mirrored range
+///
+/// # Safety
+///
+/// The replica mapping should be valid for `len` bytes.
+pub unsafe fn resize_replica(start: usize, len: usize) {
+    let mut replicas = match replicas().write() {
+        Ok(g) => g,
+        Err(p) => p.into_inner()
+    };
+    for r in replicas.iter_mut() {
+        if r.0 == start {
+            r.1 = start + len;
+        }
+    }
+}
+
+/// Stops mirroring the range starting at `start`
+pub fn remove_replica(start: usize) {
+    let mut replicas = match replicas().write() {
+        Ok(g) => g,
+        Err(p) => p.into_inner()
+    };
+    replicas.retain(|r| r.0 != start);
+}
+
+/// Copies a flushed range to its replica and flushes it there
+#[cfg(feature = "replicate")]
+fn mirror(ptr: usize, len: usize) {
+    let replicas = match replicas().read() {
+        Ok(g) => g,
+        Err(p) => p.into_inner()
+    };
+    for &(start, end, replica) in replicas.iter() {
+        if ptr >= start && ptr < end {
+            let len = len.min(end - ptr);
+            let dst = replica + (ptr - start);
+            unsafe {
+                std::ptr::copy_nonoverlapping(ptr as *const u8, dst as *mut u8, len);
+
+                #[cfg(not(feature = "use_msync"))]
+                flush_lines(dst as *const u8, len);
+
+                #[cfg(feature = "use_msync")] {
+                    let off = (dst >> 12) << 12;
+                    libc::msync(off as *mut libc::c_void, dst + len - off, libc::MS_SYNC);
+                }
+            }
+            break;
+        }
     }
 }
 
@@ -99,6 +99,7 @@ impl<T: ?Sized> !TxOutSafe for &mut T {}
 impl<T: ?Sized> !TxOutSafe for UnsafeCell<T> {}
 
 unsafe impl TxOutSafe for String {}
+unsafe impl TxOutSafe for crate::Error {}
 unsafe impl<T> TxOutSafe for std::thread::JoinHandle<T> {}
 unsafe impl<T> TxOutSafe for Vec<std::thread::JoinHandle<T>> {}
 
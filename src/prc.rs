@@ -316,8 +316,35 @@ impl<T: PSafe, A: MemPool> Prc<T, A> {
     /// }).unwrap();
     /// ```
     pub fn new(value: T, journal: &Journal<A>) -> Prc<T, A> {
+        match Self::try_new(value, journal) {
+            Ok(p) => p,
+            Err(_) => panic!("Memory exhausted")
+        }
+    }
+
+    /// Constructs a new `Prc<T>`, or returns an [`AllocError`] if the pool
+    /// is out of space.
+    ///
+    /// Unlike [`new`], it does not panic on failure, and the transaction can
+    /// continue. `value` is dropped if the allocation fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::alloc::heap::*;
+    /// use corundum::prc::Prc;
+    ///
+    /// Heap::transaction(|j| {
+    ///     let five = Prc::try_new(5, j).unwrap();
+    ///     assert_eq!(*five, 5);
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`new`]: #method.new
+    /// [`AllocError`]: std::alloc::AllocError
+    pub fn try_new(value: T, journal: &Journal<A>) -> Result<Prc<T, A>, std::alloc::AllocError> {
         unsafe {
-            let ptr = Ptr::new_unchecked(A::new(
+            let ptr = Ptr::new_unchecked(A::try_new(
                 PrcBox::<T, A> {
                     counter: Counter {
                         strong: 1,
@@ -343,8 +370,8 @@ impl<T: PSafe, A: MemPool> Prc<T, A> {
                     value,
                 },
                 journal,
-            ));
-            Self::from_inner(ptr)
+            )?);
+            Ok(Self::from_inner(ptr))
         }
     }
 
@@ -771,7 +798,7 @@ impl<T: PSafe, A: MemPool> Prc<T, A> {
             "Prc::initialize() cannot be used inside a transaction"
         );
         match rc {
-            Some(_) => Err("already initialized".to_string()),
+            Some(_) => Err(crate::Error::AlreadyInitialized),
             None => if A::valid(rc) {
                 unsafe {
                     let new = A::atomic_new(
@@ -810,7 +837,9 @@ impl<T: PSafe, A: MemPool> Prc<T, A> {
                 }
                 Ok(())
             } else {
-                Err("The object is not in the PM".to_string())
+                Err(crate::Error::InvalidArgument(
+                    "The object is not in the PM".to_string()
+                ))
             }
         }
     }
@@ -908,6 +937,18 @@ impl<T: RootObj<A> + PSafe, A: MemPool> RootObj<A> for Prc<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for Prc<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        if !self.inner().vlist.is_empty() {
+            // The volatile weak references point to the current location
+            r.pin(self.inner());
+        }
+        if r.visit(self.ptr.off_ref(), mem::size_of::<PrcBox<T, A>>()) {
+            self.inner().value.visit(r)
+        }
+    }
+}
+
 // impl<T: Default + PSafe + ?Sized, A: MemPool> RootObj<A> for Prc<T, A> {
 //     #[inline]
 //     default fn init(journal: &Journal<A>) -> Prc<T, A> {
@@ -1161,6 +1202,19 @@ impl<T: PSafe + ?Sized, A: MemPool> RootObj<A> for Weak<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for Weak<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        if r.visit(self.ptr.off_ref(), mem::size_of::<PrcBox<T, A>>()) {
+            // The value is already dropped if there is no strong reference
+            if let Some(inner) = self.inner() {
+                if inner.counter.strong != 0 {
+                    inner.value.visit(r)
+                }
+            }
+        }
+    }
+}
+
 trait PrcBoxPtr<T: PSafe + ?Sized, A: MemPool> {
     #[allow(clippy::mut_from_ref)]
     fn count(&self) -> &mut Counter<A>;
@@ -1478,6 +1532,10 @@ struct VWeakList {
 }
 
 impl VWeakList {
+    fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
     fn append(&mut self) -> *mut VWeakValid {
         let new = Box::into_raw(Box::new(VWeakValid {
             valid: true,
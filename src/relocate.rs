@@ -0,0 +1,192 @@
+//! The `Relocate` trait for types that may be moved to another pool image
+
+use crate::alloc::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+
+/// Collects live blocks and the persistent pointers referring to them
+///
+/// A `Relocator` is filled by visiting the object graph reachable from the
+/// root object via [`Relocate::visit`]. Every allocated block is recorded by
+/// its offset and size, and every persistent pointer field is recorded by its
+/// own offset. [`MemPool::compact_to`] uses this information to copy the
+/// blocks to a freshly formatted pool and rewrite the pointer fields.
+///
+/// [`MemPool::compact_to`]: ./alloc/trait.MemPoolTraits.html#method.compact_to
+pub struct Relocator<A: MemPool> {
+    blocks: BTreeMap<u64, usize>,
+    links: Vec<u64>,
+    pinned: BTreeSet<u64>,
+    phantom: PhantomData<A>,
+}
+
+impl<A: MemPool> Relocator<A> {
+    /// Creates an empty `Relocator`
+    pub fn new() -> Self {
+        Self {
+            blocks: BTreeMap::new(),
+            links: vec![],
+            pinned: BTreeSet::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Records a live block of `len` bytes at offset `off`
+    ///
+    /// It returns `true` if the block is seen for the first time, indicating
+    /// that the caller should visit the contents of the block.
+    pub fn add_block(&mut self, off: u64, len: usize) -> bool {
+        if off == u64::MAX || len == 0 || self.blocks.contains_key(&off) {
+            false
+        } else {
+            self.blocks.insert(off, len);
+            true
+        }
+    }
+
+    /// Records a persistent pointer field referring to a block of `len` bytes
+    ///
+    /// `field` should be a reference to the offset stored in the pointer which
+    /// lives inside another live block. It returns `true` if the block is seen
+    /// for the first time, indicating that the caller should visit the
+    /// contents of the block.
+    pub fn visit(&mut self, field: &u64, len: usize) -> bool {
+        if *field == u64::MAX || len == 0 {
+            return false;
+        }
+        self.links.push(unsafe { A::off_unchecked(field) });
+        self.add_block(*field, len)
+    }
+
+    /// Marks the block containing `obj` as immovable
+    ///
+    /// Objects that are referred to by volatile state (e.g. a [`PMutex`]
+    /// which may have waiters, or a [`Parc`] with live [`VWeak`] references)
+    /// call this in their [`Relocate::visit`] implementation, so that
+    /// [`MemPool::defrag`] leaves them in place. It has no effect on objects
+    /// outside the pool.
+    ///
+    /// [`PMutex`]: ./sync/struct.PMutex.html
+    /// [`Parc`]: ./sync/struct.Parc.html
+    /// [`VWeak`]: ./sync/struct.VWeak.html
+    /// [`MemPool::defrag`]: ./alloc/trait.MemPoolTraits.html#method.defrag
+    pub fn pin<T: ?Sized>(&mut self, obj: &T) {
+        if let Ok(off) = A::off(obj as *const T) {
+            self.pinned.insert(off);
+        }
+    }
+
+    /// Returns `true` if the block at offset `off` with `len` bytes contains
+    /// a pinned object
+    pub fn is_pinned(&self, off: u64, len: usize) -> bool {
+        self.pinned.range(off..off + len as u64).next().is_some()
+    }
+
+    /// Returns an iterator over the recorded blocks as `(offset, len)` pairs,
+    /// sorted by offset
+    pub fn blocks(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.blocks.iter().map(|(off, len)| (*off, *len))
+    }
+
+    /// Returns the offsets of the recorded pointer fields
+    pub fn links(&self) -> &[u64] {
+        &self.links
+    }
+
+    /// Returns the offset of the block containing the given offset
+    pub fn block_of(&self, off: u64) -> Option<u64> {
+        self.blocks.range(..=off).next_back().and_then(|(b, len)| {
+            if off < b + *len as u64 { Some(*b) } else { None }
+        })
+    }
+}
+
+/// A common trait for visiting the persistent pointers of an object
+///
+/// `Relocate` is used by [`MemPool::compact_to`] to discover all live objects
+/// reachable from the root object. The implementation should call
+/// [`Relocator::visit`] for every persistent pointer it owns, and visit the
+/// referent when `visit` returns `true`. Types without persistent pointers
+/// have a trivial implementation.
+///
+/// ## Derivable
+///
+/// This trait can be used with `#[derive]` if all fields are `Relocate`. The
+/// `derive`d implementation of `Relocate` visits each field. It uses
+/// [`default::Allocator`] by default. It is possible to change the pool
+/// type(s) by using `pools()` attribute.
+///
+/// ```
+/// # use corundum::*;
+/// # use corundum::default::*;
+/// #[derive(Relocate)]
+/// struct Node {
+///     value: i32,
+///     next: Option<Pbox<Node>>,
+/// }
+/// ```
+///
+/// # Safety
+///
+/// Missing a pointer field leaves a dangling offset in the compacted pool.
+///
+/// [`MemPool::compact_to`]: ./alloc/trait.MemPoolTraits.html#method.compact_to
+/// [`default::Allocator`]: ./alloc/default/struct.Allocator.html
+pub unsafe trait Relocate<A: MemPool> {
+    /// Visits all persistent pointers owned by `self`
+    fn visit(&self, r: &mut Relocator<A>);
+}
+
+unsafe impl<T: Relocate<A>, A: MemPool> Relocate<A> for Option<T> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        if let Some(x) = self {
+            x.visit(r)
+        }
+    }
+}
+
+unsafe impl<T: Relocate<A>, A: MemPool, const N: usize> Relocate<A> for [T; N] {
+    fn visit(&self, r: &mut Relocator<A>) {
+        for x in self {
+            x.visit(r)
+        }
+    }
+}
+
+unsafe impl<T: ?Sized, A: MemPool> Relocate<A> for PhantomData<T> {
+    fn visit(&self, _r: &mut Relocator<A>) {}
+}
+
+use impl_trait_for_tuples::*;
+
+#[impl_for_tuples(32)]
+unsafe impl<A: MemPool> Relocate<A> for Tuple {
+    fn visit(&self, r: &mut Relocator<A>) {
+        for_tuples!( #( Tuple.visit(r); )* );
+    }
+}
+
+/// Implementations of `Relocate` for primitive types.
+mod impls {
+
+    use super::{Relocate, Relocator};
+    use crate::alloc::MemPool;
+
+    macro_rules! impl_relocate {
+        ($($t:ty)*) => {
+            $(
+                unsafe impl<A: MemPool> Relocate<A> for $t {
+                    #[inline]
+                    fn visit(&self, _r: &mut Relocator<A>) {}
+                }
+            )*
+        }
+    }
+
+    impl_relocate! {
+        usize u8 u16 u32 u64 u128
+        isize i8 i16 i32 i64 i128
+        f32 f64
+        bool char
+    }
+}
@@ -27,6 +27,13 @@ impl<K: PartialEq + Hash + PSafe, V: PSafe, P: MemPool> RootObj<P> for HashMap<K
     }
 }
 
+unsafe impl<K: PSafe + Relocate<P>, V: PSafe + Relocate<P>, P: MemPool> Relocate<P> for HashMap<K, V, P> {
+    fn visit(&self, r: &mut Relocator<P>) {
+        self.buckets.visit(r);
+        self.values.visit(r);
+    }
+}
+
 impl<K: PSafe, V: PSafe, P: MemPool> HashMap<K, V, P> {
     pub fn foreach<F: FnMut(&K, &V) -> ()>(&self, mut f: F) {
         for i in 0..BUCKETS_MAX {
@@ -1,2 +1,6 @@
 mod hashmap;
-pub use hashmap::HashMap;
\ No newline at end of file
+mod mwcas;
+mod queue;
+pub use hashmap::HashMap;
+pub use mwcas::{MwCasQueue, MwCasStack};
+pub use queue::BoundedQueue;
\ No newline at end of file
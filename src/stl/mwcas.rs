@@ -0,0 +1,463 @@
+use crate::*;
+use crate::alloc::*;
+use crate::ll::persist_obj;
+use crate::result::Result;
+use crate::stm::Journal;
+use crate::sync::{mwcas_pin, PMwCas, PWord};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::ptr;
+
+/// The offset of a missing node
+const NIL: u64 = u64::MAX >> 1;
+
+/// An item which is moved into the transaction of an operation
+///
+/// A `PSafe` item holds no volatile pointer, so it can be safely moved into
+/// the transaction and then into the persistent memory.
+struct Carry<T>(T);
+
+unsafe impl<T: PSafe> TxInSafe for Carry<T> {}
+impl<T: PSafe> UnwindSafe for Carry<T> {}
+
+impl<T> Carry<T> {
+    fn take(self) -> T {
+        self.0
+    }
+}
+
+#[inline]
+fn next_version<A: MemPool>(v: u64) -> u64 {
+    if v == PWord::<A>::MAX { 0 } else { v + 1 }
+}
+
+struct StackNode<T> {
+    value: MaybeUninit<T>,
+    next: u64,
+}
+
+/// A persistent stack updated by [`PMwCas`]
+///
+/// Every operation runs in its own transaction, and it is linearized by a
+/// [`PMwCas`] which updates the head of the stack along with a version number.
+/// The version number protects the operations against the ABA problem, as the
+/// nodes are reclaimed and reused. Contending threads retry instead of
+/// blocking on a [`PMutex`], and a crash in the middle of an operation is
+/// recovered via the journal. Since the threads help the in-flight
+/// [`PMwCas`] of each other, a thread which is stalled in the middle of an
+/// operation does not delay the others; the stack is lock-free.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// use corundum::stl::MwCasStack;
+/// use std::thread;
+///
+/// type P = Allocator;
+///
+/// let stack = P::open::<MwCasStack<u64, P>>("foo.pool", O_CF).unwrap();
+///
+/// let threads: Vec<_> = (0..4).map(|i| {
+///     let stack = stack.clone();
+///     thread::spawn(move || stack.push(i).unwrap())
+/// }).collect();
+/// for t in threads {
+///     t.join().unwrap();
+/// }
+///
+/// let mut sum = 0;
+/// while let Some(v) = stack.pop().unwrap() {
+///     sum += v;
+/// }
+/// assert_eq!(sum, 6);
+/// ```
+///
+/// [`PMwCas`]: ../sync/struct.PMwCas.html
+/// [`PMutex`]: ../sync/struct.PMutex.html
+pub struct MwCasStack<T: PSafe, A: MemPool> {
+    head: PWord<A>,
+    version: PWord<A>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: PSafe, A: MemPool> UnwindSafe for MwCasStack<T, A> {}
+impl<T: PSafe, A: MemPool> RefUnwindSafe for MwCasStack<T, A> {}
+
+unsafe impl<T: PSafe, A: MemPool> TxInSafe for MwCasStack<T, A> {}
+unsafe impl<T: PSafe + Send, A: MemPool> Send for MwCasStack<T, A> {}
+unsafe impl<T: PSafe + Send, A: MemPool> Sync for MwCasStack<T, A> {}
+
+impl<T: PSafe, A: MemPool> Default for MwCasStack<T, A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PSafe, A: MemPool> MwCasStack<T, A> {
+    /// Creates an empty stack
+    pub fn new() -> Self {
+        Self {
+            head: PWord::new(NIL),
+            version: PWord::new(0),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Pushes an item onto the stack
+    ///
+    /// # Errors
+    ///
+    /// It runs its own transaction, so it cannot be called inside another
+    /// transaction; otherwise, it returns [`TxRunning`].
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    pub fn push(&self, item: T) -> Result<()> {
+        if Journal::<A>::is_running() {
+            return Err(Error::TxRunning);
+        }
+        let item = Carry(item);
+        A::transaction(move |j| unsafe {
+            let node = A::new(StackNode { value: MaybeUninit::new(item.take()), next: NIL }, j);
+            let off = A::off_unchecked(node);
+            loop {
+                let version = self.version.load();
+                node.next = self.head.load();
+                persist_obj(node, true);
+                let mut cas = PMwCas::new();
+                cas.compare_exchange(&self.version, version, next_version::<A>(version));
+                cas.compare_exchange(&self.head, node.next, off);
+                if cas.execute(j) {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Removes the top item of the stack, or returns `None` if it is empty
+    ///
+    /// # Errors
+    ///
+    /// It runs its own transaction, so it cannot be called inside another
+    /// transaction; otherwise, it returns [`TxRunning`].
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    pub fn pop(&self) -> Result<Option<T>> where T: TxOutSafe {
+        if Journal::<A>::is_running() {
+            return Err(Error::TxRunning);
+        }
+        A::transaction(|j| unsafe {
+            loop {
+                // The node is not reclaimed while it is read
+                let _guard = mwcas_pin();
+                let version = self.version.load();
+                let head = self.head.load();
+                if head == NIL {
+                    if self.version.load() == version {
+                        return None;
+                    }
+                    continue;
+                }
+                // The node may be already popped by another thread; then, the
+                // version has changed and the values are discarded.
+                let node = A::get_unchecked::<StackNode<T>>(head);
+                let next = ptr::read_volatile(&node.next);
+                let value = ptr::read_volatile(&node.value);
+                if next > PWord::<A>::MAX {
+                    continue;
+                }
+                let mut cas = PMwCas::new();
+                cas.compare_exchange(&self.version, version, next_version::<A>(version));
+                cas.compare_exchange(&self.head, head, next);
+                cas.reclaim_on_success(node);
+                if cas.execute(j) {
+                    return Some(value.assume_init());
+                }
+            }
+        })
+    }
+
+    /// Returns true if the stack is empty
+    pub fn is_empty(&self) -> bool {
+        self.head.load() == NIL
+    }
+}
+
+impl<T: PSafe, A: MemPool> Drop for MwCasStack<T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut curr = self.head.load();
+            while curr != NIL {
+                let node = A::get_mut_unchecked::<StackNode<T>>(curr);
+                curr = node.next;
+                ptr::drop_in_place(node.value.as_mut_ptr());
+                A::free(node);
+            }
+        }
+    }
+}
+
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for MwCasStack<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        let size = std::mem::size_of::<StackNode<T>>();
+        let mut link = self.head.as_u64();
+        while *link != NIL && r.visit(link, size) {
+            let node = unsafe { A::get_unchecked::<StackNode<T>>(*link) };
+            unsafe { (*node.value.as_ptr()).visit(r); }
+            link = &node.next;
+        }
+    }
+}
+
+struct QueueNode<T, A: MemPool> {
+    value: MaybeUninit<T>,
+    next: PWord<A>,
+}
+
+/// A persistent queue updated by [`PMwCas`]
+///
+/// The queue is a linked list with a sentinel node at its head. An item is
+/// appended by a [`PMwCas`] which links the new node to the last one and
+/// moves the tail at once, and it is removed by moving the head to the next
+/// node. Same as [`MwCasStack`], every operation also updates a version
+/// number, runs in its own transaction, never blocks on a [`PMutex`], and it
+/// is lock-free.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// use corundum::stl::MwCasQueue;
+/// use std::thread;
+///
+/// type P = Allocator;
+///
+/// let queue = P::open::<MwCasQueue<u64, P>>("foo.pool", O_CF).unwrap();
+///
+/// let producer = {
+///     let queue = queue.clone();
+///     thread::spawn(move || {
+///         for i in 0..100 {
+///             queue.enqueue(i).unwrap();
+///         }
+///     })
+/// };
+/// producer.join().unwrap();
+///
+/// // The items are removed in the same order
+/// for i in 0..100 {
+///     assert_eq!(queue.dequeue().unwrap(), Some(i));
+/// }
+/// assert_eq!(queue.dequeue().unwrap(), None);
+/// ```
+///
+/// [`PMwCas`]: ../sync/struct.PMwCas.html
+/// [`PMutex`]: ../sync/struct.PMutex.html
+/// [`MwCasStack`]: ./struct.MwCasStack.html
+pub struct MwCasQueue<T: PSafe, A: MemPool> {
+    head: PWord<A>,
+    tail: PWord<A>,
+    version: PWord<A>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: PSafe, A: MemPool> UnwindSafe for MwCasQueue<T, A> {}
+impl<T: PSafe, A: MemPool> RefUnwindSafe for MwCasQueue<T, A> {}
+
+unsafe impl<T: PSafe, A: MemPool> TxInSafe for MwCasQueue<T, A> {}
+unsafe impl<T: PSafe + Send, A: MemPool> Send for MwCasQueue<T, A> {}
+unsafe impl<T: PSafe + Send, A: MemPool> Sync for MwCasQueue<T, A> {}
+
+impl<T: PSafe, A: MemPool> RootObj<A> for MwCasQueue<T, A> {
+    fn init(j: &Journal<A>) -> Self {
+        Self::new(j)
+    }
+}
+
+impl<T: PSafe, A: MemPool> MwCasQueue<T, A> {
+    /// Creates an empty queue
+    pub fn new(j: &Journal<A>) -> Self {
+        let sentinel = unsafe {
+            A::new(QueueNode::<T, A> { value: MaybeUninit::uninit(), next: PWord::new(NIL) }, j)
+        };
+        persist_obj(sentinel, true);
+        let off = unsafe { A::off_unchecked(sentinel) };
+        Self {
+            head: PWord::new(off),
+            tail: PWord::new(off),
+            version: PWord::new(0),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Appends an item to the queue
+    ///
+    /// # Errors
+    ///
+    /// It runs its own transaction, so it cannot be called inside another
+    /// transaction; otherwise, it returns [`TxRunning`].
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    pub fn enqueue(&self, item: T) -> Result<()> {
+        if Journal::<A>::is_running() {
+            return Err(Error::TxRunning);
+        }
+        let item = Carry(item);
+        A::transaction(move |j| unsafe {
+            let node = A::new(QueueNode::<T, A> {
+                value: MaybeUninit::new(item.take()),
+                next: PWord::new(NIL)
+            }, j);
+            persist_obj(node, true);
+            let off = A::off_unchecked(node);
+            loop {
+                // The last node is not reclaimed while it is updated
+                let _guard = mwcas_pin();
+                let version = self.version.load();
+                let tail = self.tail.load();
+                let last = A::get_unchecked::<QueueNode<T, A>>(tail);
+                let mut cas = PMwCas::new();
+                cas.compare_exchange(&self.version, version, next_version::<A>(version));
+                cas.compare_exchange(&self.tail, tail, off);
+                cas.compare_exchange(&last.next, NIL, off);
+                if cas.execute(j) {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Removes the first item of the queue, or returns `None` if it is empty
+    ///
+    /// # Errors
+    ///
+    /// It runs its own transaction, so it cannot be called inside another
+    /// transaction; otherwise, it returns [`TxRunning`].
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    pub fn dequeue(&self) -> Result<Option<T>> where T: TxOutSafe {
+        if Journal::<A>::is_running() {
+            return Err(Error::TxRunning);
+        }
+        A::transaction(|j| unsafe {
+            loop {
+                // The first two nodes are not reclaimed while they are read
+                let _guard = mwcas_pin();
+                let version = self.version.load();
+                let head = self.head.load();
+                let first = A::get_unchecked::<QueueNode<T, A>>(head);
+                let next = first.next.load();
+                // If the version has not changed, `next` is a live node
+                if self.version.load() != version {
+                    continue;
+                }
+                if next == NIL {
+                    return None;
+                }
+                let value = ptr::read_volatile(
+                    &A::get_unchecked::<QueueNode<T, A>>(next).value);
+                let mut cas = PMwCas::new();
+                cas.compare_exchange(&self.version, version, next_version::<A>(version));
+                cas.compare_exchange(&self.head, head, next);
+                cas.reclaim_on_success(first);
+                if cas.execute(j) {
+                    return Some(value.assume_init());
+                }
+            }
+        })
+    }
+
+    /// Returns true if the queue is empty
+    pub fn is_empty(&self) -> bool {
+        let _guard = mwcas_pin();
+        let head = unsafe { A::get_unchecked::<QueueNode<T, A>>(self.head.load()) };
+        head.next.load() == NIL
+    }
+}
+
+impl<T: PSafe, A: MemPool> Drop for MwCasQueue<T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            let sentinel = A::get_mut_unchecked::<QueueNode<T, A>>(self.head.load());
+            let mut curr = sentinel.next.load();
+            A::free(sentinel);
+            while curr != NIL {
+                let node = A::get_mut_unchecked::<QueueNode<T, A>>(curr);
+                curr = node.next.load();
+                ptr::drop_in_place(node.value.as_mut_ptr());
+                A::free(node);
+            }
+        }
+    }
+}
+
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for MwCasQueue<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        let size = std::mem::size_of::<QueueNode<T, A>>();
+        let head = self.head.as_u64();
+        if !r.visit(head, size) {
+            return;
+        }
+        r.visit(self.tail.as_u64(), size);
+        let sentinel = unsafe { A::get_unchecked::<QueueNode<T, A>>(*head) };
+        let mut link = sentinel.next.as_u64();
+        while *link != NIL {
+            r.visit(link, size);
+            let node = unsafe { A::get_unchecked::<QueueNode<T, A>>(*link) };
+            unsafe { (*node.value.as_ptr()).visit(r); }
+            link = node.next.as_u64();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::default::*;
+    use crate::stl::{MwCasQueue, MwCasStack};
+    use std::thread;
+
+    type P = Allocator;
+
+    #[test]
+    fn concurrent_push_pop() {
+        let stack = P::open::<MwCasStack<u64, P>>("mwcas_stack.pool", O_CF).unwrap();
+
+        let threads: Vec<_> = (0..4).map(|t| {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                let mut sum = 0;
+                for i in 0..250 {
+                    stack.push(t * 250 + i).unwrap();
+                    sum += stack.pop().unwrap().unwrap();
+                }
+                sum
+            })
+        }).collect();
+        let sum: u64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 499500);
+        assert!(stack.is_empty());
+
+        assert!(P::transaction(|_| stack.push(0).is_err()).unwrap());
+    }
+
+    #[test]
+    fn concurrent_enqueue_dequeue() {
+        let queue = P::open::<MwCasQueue<u64, P>>("mwcas_queue.pool", O_CF).unwrap();
+
+        let threads: Vec<_> = (0..4).map(|t| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut sum = 0;
+                for i in 0..250 {
+                    queue.enqueue(t * 250 + i).unwrap();
+                    sum += queue.dequeue().unwrap().unwrap();
+                }
+                sum
+            })
+        }).collect();
+        let sum: u64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 499500);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::*;
+use crate::alloc::*;
+use crate::result::Result;
+use crate::stm::Journal;
+use crate::sync::{PCondvar, PMutex};
+use crate::vec::Vec as PVec;
+use std::panic::RefUnwindSafe;
+
+struct Ring<T: PSafe, P: MemPool> {
+    slots: PVec<Option<T>, P>,
+    head: usize,
+    len: usize,
+}
+
+/// The items are only accessed while the mutex of the queue is locked
+unsafe impl<T: PSafe + Send, P: MemPool> Send for Ring<T, P> {}
+
+impl<T: PSafe, P: MemPool> Ring<T, P> {
+    fn is_full(&self) -> bool {
+        self.len == self.slots.len()
+    }
+
+    fn push(&mut self, item: T, j: &Journal<P>) {
+        let i = (self.head + self.len) % self.slots.len();
+        self.slots.as_slice_mut(j)[i] = Some(item);
+        self.len += 1;
+    }
+
+    fn pop(&mut self, j: &Journal<P>) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            let item = self.slots.as_slice_mut(j)[self.head].take();
+            self.head = (self.head + 1) % self.slots.len();
+            self.len -= 1;
+            item
+        }
+    }
+}
+
+/// A bounded multi-producer multi-consumer persistent queue
+///
+/// The items are kept in a fixed-size ring buffer protected by a [`PMutex`].
+/// The non-blocking operations ([`try_push`] and [`try_pop`]) are
+/// transactional. The blocking operations ([`push_with`] and [`pop_with`])
+/// run their own transactions, and they sleep on a [`PCondvar`] while the
+/// queue is full or empty, respectively, instead of polling it. A blocked
+/// thread is woken up only after the transaction which makes room or adds an
+/// item commits.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// use corundum::stl::BoundedQueue;
+/// use std::thread;
+///
+/// type P = Allocator;
+///
+/// let queue = P::open::<BoundedQueue<u64, P>>("foo.pool", O_CF).unwrap();
+///
+/// let producer = {
+///     let queue = queue.clone();
+///     thread::spawn(move || {
+///         for i in 0..1000 {
+///             // Blocks while the queue is full
+///             queue.push_with(|_| i).unwrap();
+///         }
+///     })
+/// };
+///
+/// let mut sum = 0;
+/// for _ in 0..1000 {
+///     // Blocks while the queue is empty
+///     sum += queue.pop_with(|v, _| v).unwrap();
+/// }
+/// producer.join().unwrap();
+/// assert_eq!(sum, 499500);
+/// ```
+///
+/// [`PMutex`]: ../sync/struct.PMutex.html
+/// [`PCondvar`]: ../sync/struct.PCondvar.html
+/// [`try_push`]: #method.try_push
+/// [`try_pop`]: #method.try_pop
+/// [`push_with`]: #method.push_with
+/// [`pop_with`]: #method.pop_with
+pub struct BoundedQueue<T: PSafe, P: MemPool> {
+    ring: PMutex<Ring<T, P>, P>,
+    not_empty: PCondvar<P>,
+    not_full: PCondvar<P>,
+}
+
+/// The default capacity of a queue created as a root object
+const DEFAULT_CAPACITY: usize = 64;
+
+impl<T: PSafe, P: MemPool> RootObj<P> for BoundedQueue<T, P> {
+    fn init(j: &Journal<P>) -> Self {
+        Self::new(DEFAULT_CAPACITY, j)
+    }
+}
+
+unsafe impl<T: PSafe + Relocate<P>, P: MemPool> Relocate<P> for Ring<T, P> {
+    fn visit(&self, r: &mut Relocator<P>) {
+        self.slots.visit(r);
+    }
+}
+
+unsafe impl<T: PSafe + Relocate<P>, P: MemPool> Relocate<P> for BoundedQueue<T, P> {
+    fn visit(&self, r: &mut Relocator<P>) {
+        self.ring.visit(r);
+    }
+}
+
+impl<T: PSafe, P: MemPool> BoundedQueue<T, P> {
+    /// Creates an empty queue which holds at most `capacity` items
+    ///
+    /// # Panics
+    ///
+    /// It panics if `capacity` is zero.
+    pub fn new(capacity: usize, j: &Journal<P>) -> Self {
+        assert!(capacity > 0, "The capacity of a queue cannot be zero");
+        let mut slots = PVec::with_capacity(capacity, j);
+        for _ in 0..capacity {
+            slots.push(None, j);
+        }
+        Self {
+            ring: PMutex::new(Ring { slots, head: 0, len: 0 }),
+            not_empty: PCondvar::new(),
+            not_full: PCondvar::new(),
+        }
+    }
+
+    /// Returns the maximum number of items in the queue
+    pub fn capacity(&self, j: &Journal<P>) -> usize {
+        self.ring.lock(j).slots.len()
+    }
+
+    /// Returns the number of items in the queue
+    pub fn len(&self, j: &Journal<P>) -> usize {
+        self.ring.lock(j).len
+    }
+
+    /// Returns true if the queue is empty
+    pub fn is_empty(&self, j: &Journal<P>) -> bool {
+        self.len(j) == 0
+    }
+
+    /// Appends an item to the queue if it is not full; otherwise, it returns
+    /// the item back
+    ///
+    /// The consumers waiting in [`pop_with`] are woken up when the
+    /// transaction commits.
+    ///
+    /// [`pop_with`]: #method.pop_with
+    pub fn try_push(&self, item: T, j: &Journal<P>) -> std::result::Result<(), T> {
+        let mut ring = self.ring.lock(j);
+        if ring.is_full() {
+            Err(item)
+        } else {
+            ring.push(item, j);
+            self.not_empty.notify_one(j);
+            Ok(())
+        }
+    }
+
+    /// Removes the first item of the queue, or returns `None` if it is empty
+    ///
+    /// The producers waiting in [`push_with`] are woken up when the
+    /// transaction commits.
+    ///
+    /// [`push_with`]: #method.push_with
+    pub fn try_pop(&self, j: &Journal<P>) -> Option<T> {
+        let mut ring = self.ring.lock(j);
+        let item = ring.pop(j);
+        if item.is_some() {
+            self.not_full.notify_one(j);
+        }
+        item
+    }
+
+    /// Blocks until there is room in the queue, and then appends the item
+    /// created by `item` in a transaction
+    ///
+    /// # Errors
+    ///
+    /// It cannot be called inside a transaction; otherwise, it returns
+    /// [`TxRunning`]. If `item` panics, the transaction is rolled back and
+    /// the error is returned.
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    pub fn push_with<F>(&self, item: F) -> Result<()>
+    where
+        F: Fn(&'static Journal<P>) -> T,
+        F: TxInSafe + RefUnwindSafe,
+    {
+        self.not_full.wait(|j| {
+            let mut ring = self.ring.lock(j);
+            if ring.is_full() {
+                None
+            } else {
+                ring.push(item(j), j);
+                self.not_empty.notify_one(j);
+                Some(())
+            }
+        })
+    }
+
+    /// Blocks until there is an item in the queue, and then removes it and
+    /// passes it to `f` in a transaction
+    ///
+    /// The return value of `f` is returned to the caller.
+    ///
+    /// # Errors
+    ///
+    /// It cannot be called inside a transaction; otherwise, it returns
+    /// [`TxRunning`]. If `f` panics, the transaction is rolled back, the item
+    /// remains in the queue, and the error is returned.
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    pub fn pop_with<U, F>(&self, f: F) -> Result<U>
+    where
+        F: Fn(T, &'static Journal<P>) -> U,
+        F: TxInSafe + RefUnwindSafe,
+        U: TxOutSafe,
+    {
+        self.not_empty.wait(|j| {
+            let item = self.ring.lock(j).pop(j)?;
+            self.not_full.notify_one(j);
+            Some(f(item, j))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::default::*;
+    use crate::stl::BoundedQueue;
+    use std::thread;
+
+    type P = Allocator;
+
+    #[test]
+    fn producer_consumer() {
+        let queue = P::open::<BoundedQueue<u64, P>>("queue.pool", O_CF).unwrap();
+
+        let consumers: Vec<_> = (0..4).map(|_| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut sum = 0;
+                for _ in 0..250 {
+                    sum += queue.pop_with(|v, _| v).unwrap();
+                }
+                sum
+            })
+        }).collect();
+
+        for i in 0..1000 {
+            queue.push_with(|_| i).unwrap();
+        }
+        let sum: u64 = consumers.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 499500);
+
+        P::transaction(|j| {
+            assert!(queue.is_empty(j));
+            for i in 0..queue.capacity(j) as u64 {
+                assert!(queue.try_push(i, j).is_ok());
+            }
+            assert_eq!(queue.try_push(0, j), Err(0));
+            assert_eq!(queue.try_pop(j), Some(0));
+        }).unwrap();
+        assert_eq!(queue.pop_with(|v, _| v).unwrap(), 1);
+    }
+}
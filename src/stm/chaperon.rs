@@ -90,10 +90,9 @@ fn new_chaperon(filename: &str) -> Result<*mut Chaperon> {
     };
     let tid = thread::current().id();
     if clist.contains_key(&tid) {
-        return Err("Another chaperoned transaction is open".to_string());
+        return Err(crate::Error::ChaperonBusy);
     }
-    let c = Chaperon::new(filename.to_string())
-        .expect(&format!("could not create chaperon file `{}`", filename));
+    let c = Chaperon::new(filename.to_string())?;
     clist.entry(tid).or_insert(SyncBox::new(c));
     Ok(clist.get(&tid).unwrap().get())
 }
@@ -356,7 +355,17 @@ impl Chaperon {
     /// }
     /// ```
     /// 
+    /// # Errors
+    ///
+    /// It returns [`ChaperonBusy`] if another session is open in the current
+    /// thread, an [`IoError`] if the chaperon file cannot be created, and
+    /// [`TxAborted`] if the body panics and the session is rolled back.
+    ///
     /// [`transaction`]: ./fn.transaction.html
+    /// [`ChaperonBusy`]: ../enum.Error.html#variant.ChaperonBusy
+    /// [`IoError`]: ../enum.Error.html#variant.IoError
+    /// [`TxAborted`]: ../enum.Error.html#variant.TxAborted
+    #[track_caller]
     pub fn session<T, F: FnOnce() -> T>(filename: &str, body: F) -> Result<T>
     where
         F: panic::UnwindSafe,
@@ -364,14 +373,17 @@ impl Chaperon {
     {
         let chaperon = unsafe { &mut *new_chaperon(filename)? };
         let res = panic::catch_unwind(|| body());
-        if let Ok(res) = res {
-            chaperon.execute_delayed_commits();
-            drop_chaperon();
-            Ok(res)
-        } else {
-            chaperon.execute_delayed_rollbacks();
-            drop_chaperon();
-            Err("Unsuccessful chaperoned transaction".to_string())
+        match res {
+            Ok(res) => {
+                chaperon.execute_delayed_commits();
+                drop_chaperon();
+                Ok(res)
+            }
+            Err(payload) => {
+                chaperon.execute_delayed_rollbacks();
+                drop_chaperon();
+                Err(crate::Error::aborted(payload, panic::Location::caller()))
+            }
         }
     }
 }
@@ -22,6 +22,12 @@ use crate::stm::vspd::Scratchpad;
 /// Determines that the changes are committed
 pub const JOURNAL_COMMITTED: u64 = 0x0000_0001;
 
+/// Determines that a [`PMwCas`] has succeeded in the transaction, so that no
+/// more changes can be logged in it
+///
+/// [`PMwCas`]: ../sync/struct.PMwCas.html
+pub const JOURNAL_MWCAS_COMMITTED: u64 = 0x0000_0002;
+
 /// A Journal object to be used for writing logs onto
 ///
 /// Each transaction, hence each thread, may have only one journal for every
@@ -82,6 +88,15 @@ impl<A: MemPool> !LooseTxInUnsafe for Journal<A> {}
 impl<A: MemPool> !std::panic::RefUnwindSafe for Journal<A> {}
 impl<A: MemPool> !std::panic::UnwindSafe for Journal<A> {}
 
+/// A position in a [`Journal`] which a nested transaction can roll back to
+///
+/// [`Journal`]: ./struct.Journal.html
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Savepoint {
+    page: u64,
+    len: usize,
+}
+
 #[derive(Clone, Copy)]
 struct Page<A: MemPool> {
     len: usize,
@@ -191,6 +206,44 @@ impl<A: MemPool> Page<A> {
         }
     }
 
+    /// Reverts the logs from index `from`, in the reverse order
+    #[cfg(not(any(feature = "pin_journals", feature = "use_pspd", feature = "use_vspd")))]
+    unsafe fn rollback_from(&mut self, from: usize) {
+        for i in (from..self.len).rev() {
+            self.logs[i].rollback();
+        }
+    }
+
+    #[cfg(not(any(feature = "pin_journals", feature = "use_pspd", feature = "use_vspd")))]
+    unsafe fn rollback_dealloc_from(&mut self, from: usize,
+        #[cfg(feature = "check_double_free")]
+        check_double_free: &mut HashSet<u64>
+    ) {
+        for i in from..self.len {
+            self.logs[i].rollback_drop_on_abort(
+                #[cfg(feature = "check_double_free")]
+                check_double_free
+            );
+        }
+    }
+
+    /// Clears the logs from index `from` and drops them from the page
+    #[cfg(not(any(feature = "pin_journals", feature = "use_pspd", feature = "use_vspd")))]
+    unsafe fn truncate(&mut self, from: usize,
+        #[cfg(feature = "check_double_free")]
+        check_double_free: &mut HashSet<u64>
+    ) {
+        for i in from..self.len {
+            self.logs[i].clear(
+                #[cfg(feature = "check_double_free")]
+                check_double_free
+            );
+        }
+        self.len = from;
+        self.head = self.head.min(from);
+        persist_obj(&self.len, true);
+    }
+
     fn into_iter(&self) -> std::vec::IntoIter<Log<A>> {
         Vec::from(self.logs).into_iter()
     }
@@ -284,10 +337,26 @@ impl<A: MemPool> Journal<A> {
     /// Writes a new log to the journal
     #[cfg(feature = "pin_journals")]
     pub(crate) fn write(&self, log: LogEnum, notifier: Notifier<A>) -> Ptr<Log<A>, A> {
+        self.check_open();
         let mut page = self.next_page(self.current);
         page.as_mut().write(log, notifier)
     }
 
+    /// Panics if the transaction has passed the commit point of a
+    /// [`PMwCas`]
+    ///
+    /// The transaction commits even if it panics afterwards, so a change
+    /// logged after it would be committed with only a part of the body.
+    /// Rejecting it keeps the committed state as it was at the commit point.
+    ///
+    /// [`PMwCas`]: ../sync/struct.PMwCas.html
+    #[inline]
+    fn check_open(&self) {
+        if self.is_set(JOURNAL_MWCAS_COMMITTED) {
+            panic!("Cannot log a change after a successful PMwCas in the same transaction");
+        }
+    }
+
     #[inline]
     fn new_page(&self) -> Ptr<Page<A>, A> {
         #[cfg(feature = "stat_perf")]
@@ -316,6 +385,7 @@ impl<A: MemPool> Journal<A> {
     /// Writes a new log to the journal
     #[cfg(not(feature = "pin_journals"))]
     pub(crate) fn write(&self, log: LogEnum, notifier: Notifier<A>) -> Ptr<Log<A>, A> {
+        self.check_open();
         let mut page = if self.pages.is_dangling() {
             self.new_page()
         } else if self.pages.is_full() {
@@ -326,6 +396,27 @@ impl<A: MemPool> Journal<A> {
         page.as_mut().write(log, notifier)
     }
 
+    /// Returns the last log written on the journal, if any
+    #[cfg(not(feature = "pin_journals"))]
+    pub(crate) fn last_log(&self) -> Option<Ptr<Log<A>, A>> {
+        let mut pages = self.pages;
+        let page = pages.as_option()?;
+        if page.len == 0 {
+            None
+        } else {
+            Some(unsafe { Ptr::new_unchecked(&page.logs[page.len - 1]) })
+        }
+    }
+
+    /// Returns the last log written on the journal, if any
+    ///
+    /// The pages of a pinned journal are reused, so the last log is not
+    /// tracked.
+    #[cfg(feature = "pin_journals")]
+    pub(crate) fn last_log(&self) -> Option<Ptr<Log<A>, A>> {
+        None
+    }
+
     /// Writes a new log to the journal
     #[cfg(feature = "pin_journals")]
     pub unsafe fn drop_pages(&mut self) {
@@ -450,11 +541,39 @@ impl<A: MemPool> Journal<A> {
         self.set(JOURNAL_COMMITTED);
     }
 
+    /// Returns true if a [`PMwCas`] has succeeded in this journal
+    ///
+    /// A successful [`PMwCas`] is the commit point of the transaction, as
+    /// other threads may have already observed its changes.
+    ///
+    /// [`PMwCas`]: ../sync/struct.PMwCas.html
+    fn has_committed_mwcas(&self) -> bool {
+        let mut curr = self.pages;
+        while let Some(page) = curr.as_option() {
+            if page.logs[..page.len].iter().any(|log| log.is_committed_mwcas()) {
+                return true;
+            }
+            curr = page.next;
+        }
+        false
+    }
+
     /// Reverts all changes
+    ///
+    /// If a [`PMwCas`] has succeeded in the transaction, the changes are
+    /// committed instead.
+    ///
+    /// [`PMwCas`]: ../sync/struct.PMwCas.html
     pub unsafe fn rollback(&mut self, 
         #[cfg(feature = "check_double_free")]
         check_double_free: &mut HashSet<u64>
     ) {
+        if self.has_committed_mwcas() {
+            return self.commit(
+                #[cfg(feature = "check_double_free")]
+                check_double_free
+            );
+        }
         #[cfg(any(feature = "use_pspd", feature = "use_vspd"))] {
             self.spd.rollback();
         }
@@ -480,11 +599,107 @@ impl<A: MemPool> Journal<A> {
         self.set(JOURNAL_COMMITTED);
     }
 
+    /// Marks the current position of the journal, so that the logs written
+    /// after it can be reverted by [`rollback_to`]
+    ///
+    /// The data logged before the savepoint is notified, so that it is
+    /// logged again if it is modified after the savepoint. It returns `None`
+    /// if the journal cannot be partially reverted.
+    ///
+    /// [`rollback_to`]: #method.rollback_to
+    #[cfg(not(any(feature = "pin_journals", feature = "use_pspd", feature = "use_vspd")))]
+    pub(crate) fn savepoint(&mut self) -> Option<Savepoint> {
+        let mut curr = self.pages;
+        while let Some(page) = curr.as_option() {
+            unsafe { page.notify(); }
+            curr = page.next;
+        }
+        let mut head = self.pages;
+        Some(Savepoint {
+            page: head.off(),
+            len: head.as_option().map_or(0, |page| page.len),
+        })
+    }
+
+    #[cfg(any(feature = "pin_journals", feature = "use_pspd", feature = "use_vspd"))]
+    pub(crate) fn savepoint(&mut self) -> Option<Savepoint> {
+        None
+    }
+
+    /// Reverts the changes logged after the savepoint `sp`, and drops their
+    /// logs
+    ///
+    /// The locks acquired after the savepoint are released, as the data they
+    /// protect is reverted to its committed state. If a [`PMwCas`] has
+    /// succeeded in the transaction, nothing is reverted. A crash during this
+    /// procedure rolls back the whole transaction on recovery.
+    ///
+    /// [`PMwCas`]: ../sync/struct.PMwCas.html
+    #[cfg(not(any(feature = "pin_journals", feature = "use_pspd", feature = "use_vspd")))]
+    pub(crate) unsafe fn rollback_to(&mut self, sp: Savepoint,
+        #[cfg(feature = "check_double_free")]
+        check_double_free: &mut HashSet<u64>
+    ) {
+        if self.has_committed_mwcas() {
+            return;
+        }
+        let mut curr = self.pages;
+        while let Some(page) = curr.as_option() {
+            let at_sp = page.off() == sp.page;
+            page.rollback_from(if at_sp { sp.len } else { 0 });
+            if at_sp { break; }
+            curr = page.next;
+        }
+        let mut curr = self.pages;
+        while let Some(page) = curr.as_option() {
+            let at_sp = page.off() == sp.page;
+            page.rollback_dealloc_from(if at_sp { sp.len } else { 0 },
+                #[cfg(feature = "check_double_free")]
+                check_double_free
+            );
+            if at_sp { break; }
+            curr = page.next;
+        }
+        sfence();
+        while let Some(page) = self.pages.as_option() {
+            if page.off() == sp.page {
+                page.truncate(sp.len,
+                    #[cfg(feature = "check_double_free")]
+                    check_double_free
+                );
+                break;
+            }
+            let nxt = page.next;
+            page.truncate(0,
+                #[cfg(feature = "check_double_free")]
+                check_double_free
+            );
+            let z = A::pre_dealloc(page.as_mut_ptr() as *mut u8, std::mem::size_of::<Page<A>>());
+            A::log64(A::off_unchecked(self.pages.off_ref()), nxt.off(), z);
+            A::perform(z);
+        }
+    }
+
+    #[cfg(any(feature = "pin_journals", feature = "use_pspd", feature = "use_vspd"))]
+    pub(crate) unsafe fn rollback_to(&mut self, _sp: Savepoint,
+        #[cfg(feature = "check_double_free")]
+        _check_double_free: &mut HashSet<u64>
+    ) {
+        unreachable!("Savepoints are not supported")
+    }
+
     /// Recovers from a crash or power failure
     pub unsafe fn recover(&mut self, 
         #[cfg(feature = "check_double_free")]
         check_double_free: &mut HashSet<u64>
     ) {
+        if !self.is_set(JOURNAL_COMMITTED) && self.has_committed_mwcas() {
+            // The transaction has passed its commit point
+            self.commit(
+                #[cfg(feature = "check_double_free")]
+                check_double_free
+            );
+        }
         let mut curr = self.pages;
         while let Some(page) = curr.as_option() {
             page.notify();
@@ -554,6 +769,7 @@ impl<A: MemPool> Journal<A> {
         // if let Ok(next) = A::deref_mut::<Self>(self.next_off) {
         //     next.prev_off = self.prev_off;
         // }
+        self.unset(JOURNAL_MWCAS_COMMITTED);
         self.complete();
 
         #[cfg(not(feature = "pin_journals"))] {
@@ -594,7 +810,7 @@ impl<A: MemPool> Journal<A> {
             false
         } else {
             if self.sec_id != 0 && !self.chaperon.is_empty() {
-                let s = String::from_utf8(self.chaperon.to_vec()).unwrap();
+                let s = self.chaperon_file();
                 let c = unsafe { Chaperon::load(&s)
                     .expect(&format!("Missing chaperon file `{}`", s)) };
                 c.completed()
@@ -604,6 +820,25 @@ impl<A: MemPool> Journal<A> {
         }
     }
 
+    /// Returns the name of the chaperon file of the session
+    fn chaperon_file(&self) -> String {
+        let len = self.chaperon.iter().position(|c| *c == 0).unwrap_or(64);
+        String::from_utf8_lossy(&self.chaperon[..len]).to_string()
+    }
+
+    /// Returns the name of the chaperon file if the journal is committed in
+    /// a chaperoned session, but the chaperon file does not exist. The
+    /// journal cannot be recovered without it.
+    pub fn missing_chaperon(&self) -> Option<String> {
+        if self.is_set(JOURNAL_COMMITTED) && self.sec_id != 0 {
+            let s = self.chaperon_file();
+            if !s.is_empty() && !std::path::Path::new(&s).exists() {
+                return Some(s);
+            }
+        }
+        None
+    }
+
     pub(crate) fn start_session(&mut self, chaperon: &mut Chaperon) {
         let mut filename = [0u8; 64]; 
         let s = chaperon.filename().as_bytes();
@@ -646,6 +881,17 @@ impl<A: MemPool> Journal<A> {
         unsafe { Ptr::from_off_unchecked(self.next_off) }
     }
 
+    /// Returns the number of pages allocated for this journal
+    pub fn page_count(&self) -> usize {
+        let mut cnt = 0;
+        let mut curr = self.pages;
+        while let Some(page) = curr.as_option() {
+            cnt += 1;
+            curr = page.next;
+        }
+        cnt
+    }
+
     /// Returns the offset of the next journal, if any. Otherwise, returns `u64::MAX`
     pub unsafe fn next_off(&self) -> u64 {
         self.next_off
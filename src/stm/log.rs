@@ -13,6 +13,17 @@ use std::collections::HashSet;
 
 type Offset = u64;
 
+/// Marks the target of an [`AtomicAdd`] log while the delta is being added
+///
+/// [`AtomicAdd`]: ./enum.LogEnum.html#variant.AtomicAdd
+const ATOMIC_ADD_APPLYING: u64 = 1 << 63;
+
+/// Marks the target of an [`AtomicAdd`] log while the delta is being
+/// subtracted
+///
+/// [`AtomicAdd`]: ./enum.LogEnum.html#variant.AtomicAdd
+const ATOMIC_ADD_UNDOING: u64 = 1 << 62;
+
 /// Log Types
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum LogEnum {
@@ -42,6 +53,33 @@ pub enum LogEnum {
 
     /// Unlocks a [`PMutex`](../sync/struct.PMutex.html) on transaction commit.
     UnlockOnCommit(u64),
+
+    /// `(cv, all)`: Wakes up one or all of the threads waiting on a
+    /// [`PCondvar`](../sync/struct.PCondvar.html) after the transaction
+    /// commits.
+    NotifyOnCommit(u64, bool),
+
+    /// `desc`: A multi-word compare-and-swap described at offset `desc`. On
+    /// recovery, the words which are still marked with the descriptor are
+    /// updated if it has succeeded, or restored otherwise. A successful
+    /// [`PMwCas`](../sync/struct.PMwCas.html) is the commit point of the
+    /// transaction.
+    MwCas(u64),
+
+    /// `(src, delta, before)`: An atomic addition of `delta` to the 64-bit
+    /// word at `src` which is compensated by subtracting `delta` on failure.
+    /// Unlike a [`DataLog`], it keeps the concurrent additions of other
+    /// threads.
+    ///
+    /// The log is neutral (`src = u64::MAX`) until the addition starts. The
+    /// addition and its compensation are done while the word is locked by the
+    /// [`Atomic`] notifier of the log, and `src` is marked in the meantime, so
+    /// the word is either `before` or `before` plus (minus) `delta`. That
+    /// tells the recovery whether the word already reflects the update.
+    ///
+    /// [`DataLog`]: #variant.DataLog
+    /// [`Atomic`]: ./enum.Notifier.html#variant.Atomic
+    AtomicAdd(u64, u64, u64),
     None,
 }
 
@@ -62,6 +100,9 @@ impl Debug for LogEnum {
             DropOnFailure(off, _)    => write!(f, "DropOnFailure   ({})", offset_to_str(off)),
             RecountOnFailure(off, _) => write!(f, "RecountOnFailure({})", offset_to_str(off)),
             UnlockOnCommit(off)      => write!(f, "UnlockOnCommit  ({})", offset_to_str(off)),
+            NotifyOnCommit(off, _)   => write!(f, "NotifyOnCommit  ({})", offset_to_str(off)),
+            MwCas(off)               => write!(f, "MwCas           ({})", offset_to_str(off)),
+            AtomicAdd(off, _, _)     => write!(f, "AtomicAdd       ({})", offset_to_str(off)),
             None                     => write!(f, "None"),
         }
     }
@@ -266,7 +307,8 @@ impl<A: MemPool> Log<A> {
                 A::log64(A::off_unchecked(offset), off, zone);
                 A::log64(A::off_unchecked(length), len as u64, zone);
             },
-            RecountOnFailure(offset, _) => unsafe {
+            RecountOnFailure(offset, _) |
+            MwCas(offset) => unsafe {
                 A::log64(A::off_unchecked(offset), off, zone);
             }
             _ => {}
@@ -282,6 +324,9 @@ impl<A: MemPool> Log<A> {
             DropOnFailure(_, _) => "DropOnFailure",
             RecountOnFailure(_, _) => "RecountOnFailure",
             UnlockOnCommit(_) => "UnlockOnCommit",
+            NotifyOnCommit(_, _) => "NotifyOnCommit",
+            MwCas(_) => "MwCas",
+            AtomicAdd(_, _, _) => "AtomicAdd",
             None => "None"
         }.to_string()
     }
@@ -290,6 +335,15 @@ impl<A: MemPool> Log<A> {
     pub fn inner(&self) -> LogEnum {
         self.0
     }
+
+    /// Returns true if it is a [`MwCas`](./enum.LogEnum.html#variant.MwCas)
+    /// log of a successful operation
+    pub(crate) fn is_committed_mwcas(&self) -> bool {
+        match self.0 {
+            MwCas(desc) => desc != u64::MAX && crate::sync::mwcas_succeeded::<A>(desc),
+            _ => false
+        }
+    }
 }
 
 use LogEnum::*;
@@ -496,13 +550,59 @@ impl<A: MemPool> Log<A> {
         }
         #[cfg(not(any(feature = "no_pthread", windows)))] {
             let b = &mut *(virt_addr as *mut (bool, libc::pthread_mutex_t, 
-                libc::pthread_mutexattr_t));
+                libc::pthread_mutexattr_t, u64));
             if b.0 { return; }
         };
 
         Self::write_on_journal(UnlockOnCommit(virt_addr), journal, Notifier::None);
     }
 
+    /// Creates a new [`NotifyOnCommit`](./enum.LogEnum.html#variant.NotifyOnCommit)
+    /// to wake up the waiters of a condition variable when the transaction
+    /// commits
+    #[inline]
+    #[track_caller]
+    pub unsafe fn notify_on_commit(
+        virt_addr: u64,
+        all: bool,
+        journal: &Journal<A>,
+    ) {
+        log!(A, Yellow, "NEW LOG", "FOR:         v@{:<18} NotifyOnCommit", virt_addr);
+
+        Self::write_on_journal(NotifyOnCommit(virt_addr, all), journal, Notifier::None);
+    }
+
+    /// Creates a new neutral [`MwCas`](./enum.LogEnum.html#variant.MwCas)
+    /// log and writes it on `journal`
+    ///
+    /// The offset of the descriptor is set via [`set`](#method.set) in the
+    /// same low-level atomic section as its allocation. If the last log of
+    /// the journal is a neutral `MwCas` (e.g. left by a failed operation), it
+    /// is reused instead.
+    #[inline]
+    #[track_caller]
+    pub(crate) unsafe fn mwcas(journal: &Journal<A>) -> Ptr<Log<A>, A> {
+        if let Some(log) = journal.last_log() {
+            if let MwCas(u64::MAX) = log.0 {
+                return log;
+            }
+        }
+
+        log!(A, Yellow, "NEW LOG", "FOR:         ({:>6}) MwCas", offset_to_str(u64::MAX));
+
+        Self::write_on_journal(MwCas(u64::MAX), journal, Notifier::None)
+    }
+
+    /// Makes a [`MwCas`](./enum.LogEnum.html#variant.MwCas) log neutral in
+    /// the low-level atomic section of `zone`, after its descriptor is
+    /// reclaimed in the same section
+    #[inline]
+    pub(crate) unsafe fn reset_mwcas(&mut self, zone: usize) {
+        if let MwCas(desc) = &mut self.0 {
+            A::log64(A::off_unchecked(desc), u64::MAX, zone);
+        }
+    }
+
     /// Creates a new [`RecountOnFailure`](./enum.LogEnum.html#variant.RecountOnFailure)
     /// log and writes it on `journal`
     #[inline]
@@ -516,6 +616,117 @@ impl<A: MemPool> Log<A> {
         Self::write_on_journal(RecountOnFailure(offset, inc), journal, Notifier::None)
     }
 
+    /// Creates a new neutral [`AtomicAdd`](./enum.LogEnum.html#variant.AtomicAdd)
+    /// log for adding `delta` to a 64-bit word, and writes it on `journal`.
+    /// The addition is done via [`apply`](#method.apply).
+    ///
+    /// `notifier` should be an `Atomic` notifier pointing to the lock flag of
+    /// the word. The flag is not set here; it is released in the recovery if
+    /// the program crashes while it is held.
+    #[inline]
+    #[track_caller]
+    pub(crate) unsafe fn atomic_add(delta: u64, journal: &Journal<A>,
+        notifier: Notifier<A>) -> Ptr<Log<A>, A>
+    {
+        log!(A, Yellow, "NEW LOG", "FOR:         ({:>6}) += {:<6} AtomicAdd",
+            offset_to_str(u64::MAX), delta
+        );
+        journal.write(AtomicAdd(u64::MAX, delta, 0), notifier)
+    }
+
+    /// Returns the lock flag of an `Atomic` notifier
+    unsafe fn atomic_flag(&self) -> &'static std::sync::atomic::AtomicU8 {
+        match &self.1 {
+            Notifier::Atomic(n) => &*(n.as_ptr() as *const std::sync::atomic::AtomicU8),
+            _ => unreachable!("AtomicAdd needs an Atomic notifier")
+        }
+    }
+
+    /// Updates the fields of an [`AtomicAdd`](./enum.LogEnum.html#variant.AtomicAdd)
+    /// log; `before` is persisted before `src`, so that a marked `src` always
+    /// comes with its `before`
+    unsafe fn set_atomic_add(&mut self, off: u64, before: u64) {
+        if let AtomicAdd(src, _, b) = &mut self.0 {
+            *b = before;
+            persist_obj(b, true);
+            *src = off;
+            persist_obj(src, true);
+        }
+    }
+
+    /// Adds the delta of a neutral [`AtomicAdd`](./enum.LogEnum.html#variant.AtomicAdd)
+    /// log to the word at offset `off`, and returns the previous value
+    ///
+    /// The word is locked meanwhile, and the log records the previous value,
+    /// so a crash at any point either leaves the word unchanged or lets the
+    /// recovery compensate the addition exactly once.
+    pub(crate) unsafe fn apply(&mut self, off: u64) -> u64 {
+        let delta = match self.0 {
+            AtomicAdd(_, delta, _) => delta,
+            _ => unreachable!()
+        };
+        let word = &*(A::get_mut_unchecked::<u64>(off) as *const u64
+            as *const std::sync::atomic::AtomicU64);
+        let flag = self.atomic_flag();
+        crate::sync::lock_word(flag, word);
+        let before = word.load(std::sync::atomic::Ordering::Acquire);
+        self.set_atomic_add(off | ATOMIC_ADD_APPLYING, before);
+        word.store(before.wrapping_add(delta), std::sync::atomic::Ordering::Release);
+        persist_obj(word, true);
+        self.set_atomic_add(off, before);
+        crate::sync::unlock_word(flag);
+        before
+    }
+
+    /// Compensates an [`AtomicAdd`](./enum.LogEnum.html#variant.AtomicAdd)
+    /// log, and makes it neutral
+    ///
+    /// If `locked` is false, there is no concurrent access (e.g. in the
+    /// recovery), and the word is not locked.
+    unsafe fn rollback_atomic_add(&mut self, locked: bool) {
+        let (src, delta, before) = match self.0 {
+            AtomicAdd(src, delta, before) => (src, delta, before),
+            _ => return
+        };
+        if src == u64::MAX {
+            if !locked {
+                // The program may have crashed after locking the word
+                crate::sync::unlock_word(self.atomic_flag());
+            }
+            return;
+        }
+        let off = src & !(ATOMIC_ADD_APPLYING | ATOMIC_ADD_UNDOING);
+        let word = &*(A::get_mut_unchecked::<u64>(off) as *const u64
+            as *const std::sync::atomic::AtomicU64);
+        let flag = self.atomic_flag();
+        if locked {
+            crate::sync::lock_word(flag, word);
+        }
+        let cur = word.load(std::sync::atomic::Ordering::Acquire);
+        log!(A, Magenta, "ROLLBACK", "FOR:         ({:>6x}) -= {:<6} AtomicAdd", off, delta);
+        if src & ATOMIC_ADD_APPLYING != 0 {
+            // The word has not changed since, unless by this addition
+            if cur == before.wrapping_add(delta) {
+                word.store(before, std::sync::atomic::Ordering::Release);
+                persist_obj(word, true);
+            }
+        } else {
+            let before = if src & ATOMIC_ADD_UNDOING != 0 {
+                before
+            } else {
+                self.set_atomic_add(off | ATOMIC_ADD_UNDOING, cur);
+                cur
+            };
+            // The word has not changed since, unless by this subtraction
+            if word.load(std::sync::atomic::Ordering::Acquire) == before {
+                word.store(before.wrapping_sub(delta), std::sync::atomic::Ordering::Release);
+                persist_obj(word, true);
+            }
+        }
+        self.set_atomic_add(u64::MAX, 0);
+        crate::sync::unlock_word(flag);
+    }
+
     fn rollback_datalog(src: &mut u64, log: &mut u64, len: &usize) {
         debug_assert_ne!(*len, 0);
 
@@ -552,6 +763,18 @@ impl<A: MemPool> Log<A> {
                 #[cfg(feature = "check_allocator_cyclic_links")]
                 debug_assert!(A::verify());
             }
+            NotifyOnCommit(src, _) => {
+                // The waiters are not notified of an aborted transaction
+                *src = u64::MAX;
+            }
+            MwCas(desc) => {
+                if *desc != u64::MAX {
+                    crate::sync::recover_mwcas::<A>(*desc);
+                }
+            }
+            AtomicAdd(_, _, _) => {
+                self.rollback_atomic_add(true);
+            }
             _ => {}
         }
     }
@@ -645,6 +868,23 @@ impl<A: MemPool> Log<A> {
             UnlockOnCommit(src) => {
                 *src = u64::MAX;
             }
+            NotifyOnCommit(src, _) => {
+                *src = u64::MAX;
+            }
+            MwCas(desc) => {
+                if *desc != u64::MAX {
+                    crate::sync::recover_mwcas::<A>(*desc);
+                }
+            }
+            AtomicAdd(_, _, _) => {
+                if rollback {
+                    self.rollback_atomic_add(false);
+                } else {
+                    // The flag is left locked if the program crashed before
+                    // releasing it
+                    crate::sync::unlock_word(self.atomic_flag());
+                }
+            }
             _ => {}
         }
     }
@@ -704,6 +944,11 @@ impl<A: MemPool> Log<A> {
     /// the allocation for the log.
     /// * If it is a [`UnlockOnCommit`](./enum.LogEnum.html#variant.UnlockOnCommit),
     /// it unlocks the mutex.
+    /// * If it is a [`NotifyOnCommit`](./enum.LogEnum.html#variant.NotifyOnCommit)
+    /// which is not rolled back, it wakes up the waiters of the condition
+    /// variable.
+    /// * If it is a [`MwCas`](./enum.LogEnum.html#variant.MwCas), it reclaims
+    /// the descriptor.
     /// 
     pub unsafe fn clear(&mut self, 
         #[cfg(feature = "check_double_free")]
@@ -740,24 +985,27 @@ impl<A: MemPool> Log<A> {
             UnlockOnCommit(src) => {
                 if *src != u64::MAX {
                     log!(A, Magenta, "UNLOCK", "FOR:          v@{}", *src);
-                    #[cfg(not(any(feature = "no_pthread", windows)))] {
-                        let b = &mut *(*src as *mut (bool, libc::pthread_mutex_t, libc::pthread_mutexattr_t));
-                        b.0 = false;
-                        let lock = &mut b.1;
-                        let attr = &mut b.2;
-                        let result = libc::pthread_mutex_unlock(lock);
-                        if result != 0 {
-                            crate::sync::init_lock(lock, attr);
+                    crate::sync::release_lock(*src);
+                    *src = u64::MAX;
+                }
+            }
+            NotifyOnCommit(src, all) => {
+                if *src != u64::MAX {
+                    log!(A, Magenta, "NOTIFY", "FOR:          v@{}", *src);
+                    crate::sync::notify_waiters(*src, *all);
+                    *src = u64::MAX;
+                }
+            }
+            MwCas(desc) => {
+                if *desc != u64::MAX {
+                    #[cfg(feature = "check_double_free")] {
+                        if check_double_free.contains(&*desc) {
+                            return;
                         }
+                        check_double_free.insert(*desc);
                     }
-                    #[cfg(any(feature = "no_pthread", windows))] {
-                        let b = &mut *(*src as *mut (bool, u64));
-                        b.0 = false;
-                        let lock = &mut b.1;
-                        std::intrinsics::atomic_store_rel(lock, 0);
-                    }
-
-                    *src = u64::MAX;
+                    log!(A, Magenta, "DEL LOG", "FOR:         ({:>6x}) MwCas", *desc);
+                    crate::sync::clear_mwcas::<A>(desc);
                 }
             }
             _ => {}
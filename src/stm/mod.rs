@@ -3,6 +3,8 @@
 mod chaperon;
 mod journal;
 mod log;
+mod read;
+mod retry;
 pub mod pspd;
 pub mod vspd;
 
@@ -14,11 +16,14 @@ use std::panic::UnwindSafe;
 pub use chaperon::*;
 pub use journal::*;
 pub use log::*;
+pub use read::*;
+pub use retry::*;
 
 /// Atomically executes commands
 /// 
 /// See [`MemPool::transaction()`](../alloc/trait.MemPool.html#method.transaction)
 /// for more details.
+#[track_caller]
 pub fn transaction<T, F: FnOnce(&'static Journal<A>) -> T, A: MemPool>(body: F) -> Result<T>
 where
     F: TxInSafe + UnwindSafe,
@@ -26,3 +31,47 @@ where
 {
     A::transaction(body)
 }
+
+/// Atomically executes commands, and rolls them back if the body returns an
+/// error
+/// 
+/// See [`MemPool::try_transaction()`](../alloc/trait.MemPool.html#method.try_transaction)
+/// for more details.
+#[track_caller]
+pub fn try_transaction<T, E, F, A: MemPool>(body: F) -> std::result::Result<T, E>
+where
+    F: FnOnce(&'static Journal<A>) -> std::result::Result<T, E>,
+    F: TxInSafe + UnwindSafe,
+    T: TxOutSafe,
+    E: TxOutSafe + From<crate::Error>,
+{
+    A::try_transaction(body)
+}
+
+/// Atomically executes commands, and re-executes them if the transaction is
+/// aborted due to a deadlock
+/// 
+/// See [`MemPool::transaction_with_retry()`](../alloc/trait.MemPool.html#method.transaction_with_retry)
+/// for more details.
+#[track_caller]
+pub fn transaction_with_retry<T, F, A: MemPool>(policy: RetryPolicy, body: F) -> Result<T>
+where
+    F: Fn(&'static Journal<A>) -> T,
+    F: TxInSafe + std::panic::RefUnwindSafe,
+    T: TxOutSafe,
+{
+    A::transaction_with_retry(policy, body)
+}
+
+/// Executes a read-only transaction without creating a journal
+/// 
+/// See [`MemPool::read_transaction()`](../alloc/trait.MemPool.html#method.read_transaction)
+/// for more details.
+#[track_caller]
+pub fn read_transaction<T, F: FnOnce(&ReadTx<A>) -> T, A: MemPool>(body: F) -> Result<T>
+where
+    F: TxInSafe + UnwindSafe,
+    T: TxOutSafe,
+{
+    A::read_transaction(body)
+}
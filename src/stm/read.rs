@@ -0,0 +1,71 @@
+//! Read-only transactions
+use crate::alloc::MemPool;
+use crate::sync::MutexInner;
+use crate::*;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+
+thread_local! {
+    /// The number of open read-only transactions in the current thread
+    static READERS: Cell<usize> = Cell::new(0);
+}
+
+/// A read-only transaction
+///
+/// It is created by [`read_transaction`] and it is passed to its body as a
+/// proof of being inside a read-only transaction. Unlike [`Journal`], it does
+/// not reside in the pool and it creates no logs. It only keeps track of the
+/// locks which are entered for shared reading (e.g. via [`PMutex::read`]) to
+/// leave them together when the transaction ends. No writer can enter those
+/// locks meanwhile. Therefore, all values read through the same `ReadTx`
+/// belong to the same consistent snapshot.
+///
+/// [`read_transaction`]: ../alloc/trait.MemPool.html#method.read_transaction
+/// [`Journal`]: ./struct.Journal.html
+/// [`PMutex::read`]: ../sync/struct.PMutex.html#method.read
+pub struct ReadTx<A: MemPool> {
+    locks: RefCell<Vec<*const MutexInner>>,
+    phantom: PhantomData<A>,
+}
+
+impl<A: MemPool> !PSafe for ReadTx<A> {}
+impl<A: MemPool> !Send for ReadTx<A> {}
+impl<A: MemPool> !Sync for ReadTx<A> {}
+impl<A: MemPool> !TxOutSafe for ReadTx<A> {}
+impl<A: MemPool> !TxInSafe for ReadTx<A> {}
+impl<A: MemPool> !LooseTxInUnsafe for ReadTx<A> {}
+
+impl<A: MemPool> ReadTx<A> {
+    pub(crate) fn new() -> Self {
+        READERS.with(|r| r.set(r.get() + 1));
+        Self {
+            locks: RefCell::new(vec![]),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns true if there is an open read-only transaction on the current
+    /// thread
+    pub fn is_running() -> bool {
+        READERS.with(|r| r.get() != 0)
+    }
+
+    /// Returns true if the lock is already entered by this transaction
+    pub(crate) fn holds(&self, lock: &MutexInner) -> bool {
+        self.locks.borrow().contains(&(lock as *const _))
+    }
+
+    /// Registers an entered lock to be left when the transaction ends
+    pub(crate) fn hold(&self, lock: &MutexInner) {
+        self.locks.borrow_mut().push(lock);
+    }
+}
+
+impl<A: MemPool> Drop for ReadTx<A> {
+    fn drop(&mut self) {
+        for lock in self.locks.get_mut().drain(..).rev() {
+            unsafe { (*lock).leave_read(); }
+        }
+        READERS.with(|r| r.set(r.get() - 1));
+    }
+}
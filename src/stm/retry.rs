@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+/// The policy of re-executing a transaction aborted due to a conflict
+///
+/// It is used by [`transaction_with_retry`]. The transaction is executed at
+/// most `max_attempts` times. After each failed attempt, the thread waits for
+/// `backoff` which doubles after every attempt, but does not exceed
+/// `max_backoff`.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::stm::RetryPolicy;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::new(5)
+///     .with_backoff(Duration::from_millis(1), Duration::from_millis(4));
+///
+/// assert_eq!(policy.delay(0), Duration::from_millis(1));
+/// assert_eq!(policy.delay(1), Duration::from_millis(2));
+/// assert_eq!(policy.delay(3), Duration::from_millis(4));
+/// ```
+///
+/// [`transaction_with_retry`]: ../alloc/trait.MemPool.html#method.transaction_with_retry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of executions, including the first one
+    pub max_attempts: usize,
+
+    /// The waiting time after the first failed attempt
+    pub backoff: Duration,
+
+    /// The maximum waiting time between two attempts
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy which executes the transaction at most `max_attempts`
+    /// times with the default backoff
+    pub fn new(max_attempts: usize) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the initial and the maximum waiting time between the attempts
+    pub fn with_backoff(self, backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            backoff,
+            max_backoff,
+            ..self
+        }
+    }
+
+    /// Returns the waiting time after the failed attempt number `attempt`
+    /// (starting from 0)
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let factor = 1u32.checked_shl(attempt.min(31) as u32).unwrap_or(u32::MAX);
+        self.backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Executes the transaction at most 16 times, waiting from 100µs up to
+    /// 50ms between the attempts
+    fn default() -> Self {
+        Self {
+            max_attempts: 16,
+            backoff: Duration::from_micros(100),
+            max_backoff: Duration::from_millis(50),
+        }
+    }
+}
@@ -1,11 +1,12 @@
 //! Persistent unicode string slices
 
-use crate::RootObj;
+use crate::{RootObj, Relocate, Relocator};
 use crate::convert::PFrom;
 use crate::alloc::MemPool;
 use crate::clone::PClone;
 use crate::stm::*;
 use crate::vec::Vec;
+use std::alloc::AllocError;
 use std::string::FromUtf8Error;
 use std::borrow::{Cow, ToOwned};
 use std::char::decode_utf16;
@@ -554,6 +555,35 @@ impl<A: MemPool> String<A> {
         self.vec.extend_from_slice(string.as_bytes(), j)
     }
 
+    /// Appends a given string slice onto the end of this `String`, or returns
+    /// an [`AllocError`] if the pool is out of space.
+    ///
+    /// On failure, the `String` remains unchanged, and the transaction can
+    /// continue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::alloc::heap::*;
+    /// # use corundum::str::String;
+    /// # use corundum::convert::PFrom;
+    /// corundum::transaction(|j| {
+    ///     let mut s = String::<Heap>::pfrom("foo", j);
+    ///
+    ///     s.try_push_str("bar", j).unwrap();
+    ///
+    ///     assert_eq!("foobar", s);
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`AllocError`]: std::alloc::AllocError
+    #[inline]
+    pub fn try_push_str(&mut self, string: &str, j: &Journal<A>) -> Result<(), AllocError> {
+        self.vec.try_reserve(string.len(), j)?;
+        self.vec.extend_from_slice(string.as_bytes(), j);
+        Ok(())
+    }
+
     /// Returns this `String`'s capacity, in bytes.
     ///
     /// # Examples
@@ -631,6 +661,29 @@ impl<A: MemPool> String<A> {
         self.vec.reserve(additional, j)
     }
 
+    /// Tries to reserve capacity for at least `additional` more bytes, and
+    /// returns an [`AllocError`] if the pool is out of space.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::alloc::heap::*;
+    /// # use corundum::str::String;
+    /// # use corundum::convert::PFrom;
+    /// corundum::transaction(|j| {
+    ///     let mut s = String::<Heap>::pfrom("foo", j);
+    ///
+    ///     s.try_reserve(100, j).unwrap();
+    ///     assert!(s.capacity() >= 103);
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`AllocError`]: std::alloc::AllocError
+    #[inline]
+    pub fn try_reserve(&mut self, additional: usize, j: &Journal<A>) -> Result<(), AllocError> {
+        self.vec.try_reserve(additional, j)
+    }
+
     /// Shrinks the capacity of this `String` to match its length.
     ///
     /// # Examples
@@ -719,6 +772,16 @@ impl<A: MemPool> String<A> {
         }
     }
 
+    /// Appends the given [`char`] to the end of this `String`, or returns an
+    /// [`AllocError`] if the pool is out of space.
+    ///
+    /// [`char`]: ../../std/primitive.char.html
+    /// [`AllocError`]: std::alloc::AllocError
+    #[inline]
+    pub fn try_push(&mut self, ch: char, j: &Journal<A>) -> Result<(), AllocError> {
+        self.try_push_str(ch.encode_utf8(&mut [0; 4]), j)
+    }
+
     /// Returns a byte slice of this `String`'s contents.
     ///
     /// The inverse of this method is [`from_utf8`].
@@ -1228,6 +1291,12 @@ impl<A: MemPool> PClone<A> for String<A> {
     }
 }
 
+unsafe impl<A: MemPool> Relocate<A> for String<A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        self.vec.visit(r)
+    }
+}
+
 // impl<A: MemPool> Clone for String<A> {
 //     fn clone(&self) -> Self {
 //         let journal = &Journal::try_current().expect("This function should be called only inside a transaction").0;
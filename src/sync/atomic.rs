@@ -0,0 +1,402 @@
+use crate::alloc::{AccessGuard, MemPool};
+use crate::ll::persist_obj;
+use crate::ptr::Ptr;
+use crate::stm::{Journal, Log, Notifier};
+use crate::*;
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+const WRITER_STRIPES: usize = 64;
+const NO_WRITERS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of in-flight non-transactional updates, striped by the address
+/// of the word
+///
+/// It is volatile, so a crash cannot leave a word locked.
+static WRITERS: [AtomicUsize; WRITER_STRIPES] = [NO_WRITERS; WRITER_STRIPES];
+
+#[inline]
+fn writers<T>(word: &T) -> &'static AtomicUsize {
+    &WRITERS[(word as *const T as usize >> 3) % WRITER_STRIPES]
+}
+
+/// Locks `word` for a transactional addition: takes its `flag`, and waits for
+/// the in-flight non-transactional updates of it to finish
+pub(crate) fn lock_word(flag: &AtomicU8, word: &AtomicU64) {
+    while flag.compare_exchange_weak(0, 1, Ordering::SeqCst, Ordering::Relaxed).is_err() {
+        std::hint::spin_loop();
+    }
+    let writers = writers(word);
+    while writers.load(Ordering::SeqCst) != 0 {
+        std::hint::spin_loop();
+    }
+}
+
+/// Releases the `flag` of a word, and persists it before the log holding it
+/// can be cleared
+pub(crate) fn unlock_word(flag: &AtomicU8) {
+    flag.store(0, Ordering::Release);
+    persist_obj(flag, true);
+}
+
+macro_rules! persistent_atomic {
+    ($(#[$attr:meta])* $name:ident, $atomic:ident, $t:ty, $example:literal) => {
+        $(#[$attr])*
+        ///
+        /// Every update is followed by a `persist` of the value, so it is
+        /// durable when the function returns. The non-transactional functions
+        /// (e.g. `store`) need no [`Journal`] and they cannot be rolled back.
+        /// They wait while the pool is quiesced, e.g. by a snapshot.
+        ///
+        /// The transactional variants are logged in the journal of each
+        /// transaction separately. `fetch_add_tx` and `fetch_sub_tx` take a
+        /// compensating log, so an aborted transaction subtracts (or adds
+        /// back) only its own change, and the concurrent updates of other
+        /// threads are kept. The value is locked by a flag only while the
+        /// change is applied or compensated, so that the recovery can tell
+        /// whether it is reflected in the value. `store_tx` and `swap_tx` take an undo log of the
+        /// previous value which is written back on abort; therefore, all
+        /// writers of the value should be serialized by a lock (e.g. a
+        /// [`PMutex`]) while such a transaction is running.
+        ///
+        /// A `load` may observe a value which is stored by another thread but
+        /// is not persisted yet.
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// use corundum::default::*;
+        /// use std::sync::atomic::Ordering;
+        ///
+        /// type P = Allocator;
+        ///
+        #[doc = $example]
+        /// ```
+        ///
+        /// [`Journal`]: ../stm/struct.Journal.html
+        /// [`PMutex`]: ./struct.PMutex.html
+        pub struct $name<A: MemPool> {
+            heap: PhantomData<A>,
+            value: $atomic,
+            lock: AtomicU8,
+        }
+
+        impl<A: MemPool> !TxOutSafe for $name<A> {}
+        impl<A: MemPool> UnwindSafe for $name<A> {}
+        impl<A: MemPool> RefUnwindSafe for $name<A> {}
+
+        unsafe impl<A: MemPool> TxInSafe for $name<A> {}
+        unsafe impl<A: MemPool> PSafe for $name<A> {}
+        unsafe impl<A: MemPool> Send for $name<A> {}
+        unsafe impl<A: MemPool> Sync for $name<A> {}
+        unsafe impl<A: MemPool> PSend for $name<A> {}
+
+        impl<A: MemPool> $name<A> {
+            /// Creates a new persistent atomic value
+            pub const fn new(v: $t) -> Self {
+                Self {
+                    heap: PhantomData,
+                    value: $atomic::new(v),
+                    lock: AtomicU8::new(0),
+                }
+            }
+
+            #[inline]
+            fn persist(&self) {
+                persist_obj(&self.value, true);
+            }
+
+            /// Applies a non-transactional update `f` and persists the value
+            ///
+            /// It waits while a transactional update of the value is being
+            /// applied or compensated.
+            #[inline]
+            fn update<R>(&self, f: impl FnOnce(&$atomic) -> R) -> R {
+                let _access = AccessGuard::<A>::new();
+                let writers = writers(&self.value);
+                loop {
+                    writers.fetch_add(1, Ordering::SeqCst);
+                    if self.lock.load(Ordering::SeqCst) == 0 {
+                        break;
+                    }
+                    writers.fetch_sub(1, Ordering::SeqCst);
+                    while self.lock.load(Ordering::Acquire) != 0 {
+                        std::hint::spin_loop();
+                    }
+                }
+                let res = f(&self.value);
+                self.persist();
+                writers.fetch_sub(1, Ordering::Release);
+                res
+            }
+
+            /// Takes an undo log of the current value in the journal of the
+            /// running transaction
+            #[inline]
+            fn log(&self, journal: &Journal<A>) {
+                assert!(A::valid(self), "The object is not in the pool's valid range");
+                unsafe {
+                    Log::create(&self.value, journal, Notifier::None);
+                }
+            }
+
+            /// Loads the value
+            #[inline]
+            pub fn load(&self, order: Ordering) -> $t {
+                self.value.load(order)
+            }
+
+            /// Stores a value and persists it
+            #[inline]
+            pub fn store(&self, v: $t, order: Ordering) {
+                self.update(|a| a.store(v, order))
+            }
+
+            /// Stores a value, persists it, and returns the previous value
+            #[inline]
+            pub fn swap(&self, v: $t, order: Ordering) -> $t {
+                self.update(|a| a.swap(v, order))
+            }
+
+            /// Stores `new` if the current value is `current`, and persists it
+            ///
+            /// The return value is the previous value wrapped in `Ok` if the
+            /// value was updated; otherwise, it is `Err`.
+            #[inline]
+            pub fn compare_exchange(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> std::result::Result<$t, $t> {
+                self.update(|a| a.compare_exchange(current, new, success, failure))
+            }
+
+            /// Stores a value transactionally
+            ///
+            /// The value is persisted and the previous value is restored if
+            /// the transaction aborts. The other writers should be excluded
+            /// by a lock until the transaction ends.
+            #[inline]
+            pub fn store_tx(&self, v: $t, journal: &Journal<A>) {
+                self.log(journal);
+                self.store(v, Ordering::Release);
+            }
+
+            /// Stores a value transactionally and returns the previous value
+            ///
+            /// The other writers should be excluded by a lock until the
+            /// transaction ends.
+            #[inline]
+            pub fn swap_tx(&self, v: $t, journal: &Journal<A>) -> $t {
+                self.log(journal);
+                self.swap(v, Ordering::AcqRel)
+            }
+
+            /// Consumes the atomic and returns the contained value
+            #[inline]
+            pub fn into_inner(self) -> $t {
+                self.value.into_inner()
+            }
+        }
+
+        impl<A: MemPool> Default for $name<A> {
+            fn default() -> Self {
+                Self::new(<$t>::default())
+            }
+        }
+
+        unsafe impl<A: MemPool> Relocate<A> for $name<A> {
+            fn visit(&self, _r: &mut Relocator<A>) {}
+        }
+
+        impl<A: MemPool> fmt::Debug for $name<A> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&self.load(Ordering::SeqCst), f)
+            }
+        }
+    };
+}
+
+macro_rules! persistent_atomic_int {
+    ($name:ident, $t:ty) => {
+        impl<A: MemPool> $name<A> {
+            /// Adds to the current value, persists it, and returns the
+            /// previous value
+            ///
+            /// This operation wraps around on overflow.
+            #[inline]
+            pub fn fetch_add(&self, v: $t, order: Ordering) -> $t {
+                self.update(|a| a.fetch_add(v, order))
+            }
+
+            /// Subtracts from the current value, persists it, and returns the
+            /// previous value
+            ///
+            /// This operation wraps around on overflow.
+            #[inline]
+            pub fn fetch_sub(&self, v: $t, order: Ordering) -> $t {
+                self.update(|a| a.fetch_sub(v, order))
+            }
+
+            /// Sets the current value to the maximum of itself and `v`,
+            /// persists it, and returns the previous value
+            #[inline]
+            pub fn fetch_max(&self, v: $t, order: Ordering) -> $t {
+                self.update(|a| a.fetch_max(v, order))
+            }
+
+            /// Adds `delta` as a 64-bit word with a compensating log which
+            /// notifies the lock flag of the value
+            #[inline]
+            fn add_tx(&self, delta: u64, journal: &Journal<A>) -> $t {
+                assert!(A::valid(self), "The object is not in the pool's valid range");
+                let _access = AccessGuard::<A>::new();
+                unsafe {
+                    let flag = &*(&self.lock as *const AtomicU8 as *const u8);
+                    let mut log = Log::atomic_add(delta, journal,
+                        Notifier::Atomic(Ptr::from_ref(flag)));
+                    log.apply(A::off_unchecked(&self.value)) as $t
+                }
+            }
+
+            /// Adds to the current value transactionally and returns the
+            /// previous value
+            ///
+            /// If the transaction aborts, `v` is subtracted from the value,
+            /// keeping the updates of other threads in the meantime.
+            #[inline]
+            pub fn fetch_add_tx(&self, v: $t, journal: &Journal<A>) -> $t {
+                self.add_tx(v as u64, journal)
+            }
+
+            /// Subtracts from the current value transactionally and returns
+            /// the previous value
+            ///
+            /// If the transaction aborts, `v` is added back to the value,
+            /// keeping the updates of other threads in the meantime.
+            #[inline]
+            pub fn fetch_sub_tx(&self, v: $t, journal: &Journal<A>) -> $t {
+                self.add_tx((v as u64).wrapping_neg(), journal)
+            }
+        }
+    };
+}
+
+persistent_atomic!(
+    /// A persistent `u64` which can be safely shared between threads
+    PAtomicU64, AtomicU64, u64,
+    "let seq = P::open::<PAtomicU64>(\"foo.pool\", O_CF).unwrap();
+let id = seq.fetch_add(1, Ordering::SeqCst);
+assert_eq!(seq.load(Ordering::SeqCst), id + 1);
+
+// The transactional update is rolled back
+let _ = P::transaction(|j| {
+    seq.fetch_add_tx(10, j);
+    panic!(\"abort\");
+});
+assert_eq!(seq.load(Ordering::SeqCst), id + 1);"
+);
+persistent_atomic_int!(PAtomicU64, u64);
+
+persistent_atomic!(
+    /// A persistent `usize` which can be safely shared between threads
+    PAtomicUsize, AtomicUsize, usize,
+    "let count = P::open::<PAtomicUsize>(\"foo.pool\", O_CF).unwrap();
+let prev = count.fetch_add(1, Ordering::SeqCst);
+assert_eq!(count.load(Ordering::SeqCst), prev + 1);"
+);
+persistent_atomic_int!(PAtomicUsize, usize);
+
+persistent_atomic!(
+    /// A persistent boolean which can be safely shared between threads
+    PAtomicBool, AtomicBool, bool,
+    "let flag = P::open::<PAtomicBool>(\"foo.pool\", O_CF).unwrap();
+flag.store(true, Ordering::SeqCst);
+assert!(flag.swap(false, Ordering::SeqCst));"
+);
+
+impl<A: MemPool> PAtomicBool<A> {
+    /// Performs a logical "or" with the current value, persists it, and
+    /// returns the previous value
+    #[inline]
+    pub fn fetch_or(&self, v: bool, order: Ordering) -> bool {
+        self.update(|a| a.fetch_or(v, order))
+    }
+
+    /// Performs a logical "and" with the current value, persists it, and
+    /// returns the previous value
+    #[inline]
+    pub fn fetch_and(&self, v: bool, order: Ordering) -> bool {
+        self.update(|a| a.fetch_and(v, order))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::default::*;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    type P = Allocator;
+
+    #[test]
+    fn concurrent_counter() {
+        let counter = P::open::<PAtomicU64>("atomic.pool", O_CF).unwrap();
+        let threads: Vec<_> = (0..4).map(|_| {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 4000);
+
+        P::transaction(|j| {
+            counter.fetch_add_tx(1, j);
+            counter.fetch_add_tx(1, j);
+        }).unwrap();
+        assert!(P::transaction(|j| {
+            counter.store_tx(0, j);
+            panic!("abort");
+        }).is_err());
+        assert_eq!(counter.load(Ordering::SeqCst), 4002);
+    }
+
+    #[test]
+    fn concurrent_tx_counter() {
+        let counter = P::open::<PAtomicU64>("atomic_tx.pool", O_CF).unwrap();
+        let threads: Vec<_> = (0..4).map(|i| {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                for k in 0..200 {
+                    if i % 2 == 0 {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        // Every other transaction aborts
+                        let _ = P::transaction(|j| {
+                            counter.fetch_add_tx(10, j);
+                            counter.fetch_sub_tx(3, j);
+                            if k % 2 == 0 {
+                                panic!("abort");
+                            }
+                        });
+                    }
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        // Only the committed changes remain, and no concurrent update is lost
+        assert_eq!(counter.load(Ordering::SeqCst), 2 * 200 + 2 * 100 * 7);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::alloc::MemPool;
+use crate::cell::VCell;
+use crate::result::Result;
+use crate::stm::{Journal, Log};
+use crate::*;
+use std::fmt;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// A transaction-aware condition variable
+///
+/// A condition variable blocks a thread until the persistent state protected
+/// by a [`PMutex`] changes. Since [`PMutex`] holds its lock until the
+/// transaction commits, a thread cannot wait in the middle of a transaction.
+/// Instead, [`wait`] runs a transaction which returns `None` if it cannot make
+/// progress. In that case, the transaction is rolled back, and the thread
+/// sleeps until another transaction calls [`notify_one`] or [`notify_all`]
+/// and commits. Then, the transaction is executed again.
+///
+/// The notifications are logged as [`NotifyOnCommit`] and they take effect
+/// only when the notifying transaction commits; if it is aborted, no thread
+/// is woken up. Same as [`PMutex`], the waiting state is kept in a
+/// [`VCell`], so it resets after a restart.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// use std::thread;
+///
+/// type P = Allocator;
+///
+/// #[derive(Root)]
+/// struct Job {
+///     ready: PMutex<bool>,
+///     cv: PCondvar,
+/// }
+///
+/// let root = P::open::<Parc<Job>>("foo.pool", O_CF).unwrap();
+///
+/// let job = Parc::demote(&root);
+/// let t = thread::spawn(move || {
+///     P::transaction(move |j| {
+///         let job = job.promote(j).unwrap();
+///         *job.ready.lock(j) = true;
+///         job.cv.notify_all(j);
+///     }).unwrap();
+/// });
+///
+/// // Blocks until the other transaction commits
+/// root.cv.wait(|j| if *root.ready.lock(j) { Some(()) } else { None }).unwrap();
+/// t.join().unwrap();
+/// ```
+///
+/// [`PMutex`]: ./struct.PMutex.html
+/// [`VCell`]: ../cell/struct.VCell.html
+/// [`wait`]: #method.wait
+/// [`notify_one`]: #method.notify_one
+/// [`notify_all`]: #method.notify_all
+/// [`NotifyOnCommit`]: ../stm/enum.LogEnum.html#variant.NotifyOnCommit
+pub struct PCondvar<A: MemPool> {
+    inner: VCell<CondvarInner, A>,
+}
+
+#[derive(Default)]
+pub(crate) struct CondvarInner {
+    /// The number of committed notifications
+    seq: Mutex<u64>,
+    cond: Condvar,
+}
+
+impl CondvarInner {
+    fn seq(&self) -> MutexGuard<'_, u64> {
+        match self.seq.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner()
+        }
+    }
+
+    /// Blocks the current thread until a notification after `seen` arrives
+    fn wait_for_change(&self, seen: u64) {
+        let mut seq = self.seq();
+        while *seq == seen {
+            seq = match self.cond.wait(seq) {
+                Ok(g) => g,
+                Err(p) => p.into_inner()
+            };
+        }
+    }
+
+    fn notify(&self, all: bool) {
+        *self.seq() += 1;
+        if all {
+            self.cond.notify_all();
+        } else {
+            self.cond.notify_one();
+        }
+    }
+}
+
+/// The result of a waiting transaction which is rolled back
+enum Wait {
+    Retry,
+    Failed(Error),
+}
+
+impl From<Error> for Wait {
+    fn from(e: Error) -> Self {
+        Wait::Failed(e)
+    }
+}
+
+impl<A: MemPool> !TxOutSafe for PCondvar<A> {}
+impl<A: MemPool> UnwindSafe for PCondvar<A> {}
+impl<A: MemPool> RefUnwindSafe for PCondvar<A> {}
+
+unsafe impl<A: MemPool> TxInSafe for PCondvar<A> {}
+unsafe impl<A: MemPool> PSafe for PCondvar<A> {}
+unsafe impl<A: MemPool> Send for PCondvar<A> {}
+unsafe impl<A: MemPool> Sync for PCondvar<A> {}
+unsafe impl<A: MemPool> PSend for PCondvar<A> {}
+
+impl<A: MemPool> PCondvar<A> {
+    /// Creates a new condition variable
+    pub fn new() -> Self {
+        Self {
+            inner: VCell::new(CondvarInner::default()),
+        }
+    }
+
+    fn id(&self) -> u64 {
+        &*self.inner as *const CondvarInner as u64
+    }
+
+    /// Wakes up one thread blocked on this condition variable when the
+    /// current transaction commits
+    ///
+    /// If the transaction is rolled back, no thread is woken up.
+    pub fn notify_one(&self, journal: &Journal<A>) {
+        unsafe { Log::notify_on_commit(self.id(), false, journal); }
+    }
+
+    /// Wakes up all threads blocked on this condition variable when the
+    /// current transaction commits
+    ///
+    /// If the transaction is rolled back, no thread is woken up.
+    pub fn notify_all(&self, journal: &Journal<A>) {
+        unsafe { Log::notify_on_commit(self.id(), true, journal); }
+    }
+
+    /// Runs `body` in a transaction, and blocks the current thread until it
+    /// makes progress
+    ///
+    /// If `body` returns `Some`, the transaction commits and the value is
+    /// returned. If it returns `None`, the transaction is rolled back, and the
+    /// current thread blocks until this condition variable receives a
+    /// notification from a committed transaction. Then, `body` is executed in
+    /// a new transaction. A notification which is committed after `body`
+    /// starts is not missed.
+    ///
+    /// # Errors
+    ///
+    /// If a transaction is running in the current thread, it returns
+    /// [`TxRunning`], as the thread cannot block while holding the locks of
+    /// the transaction. If the body panics, the transaction is rolled back and
+    /// the error is returned as in [`transaction`].
+    ///
+    /// [`TxRunning`]: ../enum.Error.html#variant.TxRunning
+    /// [`transaction`]: ../alloc/trait.MemPool.html#method.transaction
+    #[track_caller]
+    pub fn wait<T, F>(&self, body: F) -> Result<T>
+    where
+        F: Fn(&'static Journal<A>) -> Option<T>,
+        F: TxInSafe + RefUnwindSafe,
+        T: TxOutSafe,
+    {
+        if Journal::<A>::is_running() {
+            return Err(Error::TxRunning);
+        }
+        loop {
+            let seen = *self.inner.seq();
+            match A::try_transaction(|j| body(j).ok_or(Wait::Retry)) {
+                Ok(v) => return Ok(v),
+                Err(Wait::Failed(e)) => return Err(e),
+                Err(Wait::Retry) => self.inner.wait_for_change(seen),
+            }
+        }
+    }
+}
+
+/// Wakes up the waiters of a [`PCondvar`] given the address of its inner
+/// state
+///
+/// [`PCondvar`]: ./struct.PCondvar.html
+pub(crate) unsafe fn notify_waiters(id: u64, all: bool) {
+    let inner = &*(id as *const CondvarInner);
+    inner.notify(all);
+}
+
+impl<A: MemPool> Default for PCondvar<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<A: MemPool> Relocate<A> for PCondvar<A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        // The condition variable may have waiters, so it cannot be moved
+        r.pin(self);
+    }
+}
+
+impl<A: MemPool> fmt::Debug for PCondvar<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("PCondvar { .. }")
+    }
+}
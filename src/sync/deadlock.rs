@@ -0,0 +1,231 @@
+//! Deadlock detection for transaction-wide locks
+//!
+//! [`PMutex`] holds its lock until the owner transaction commits. Therefore,
+//! two transactions locking two mutexes in opposite orders wait for each other
+//! forever. To prevent it, every thread which blocks on a lock records the
+//! lock in a process-wide wait-for graph. The owner of each lock is kept in
+//! the lock itself, so acquiring and releasing a lock without blocking does
+//! not touch the graph. A thread that is about to close a cycle in the graph
+//! does not block; instead, it aborts its transaction with
+//! [`Error::Deadlock`], so that its locks are released and the other
+//! transactions can proceed. Such transactions can be re-executed using
+//! [`transaction_with_retry`].
+//!
+//! Locks which can be shared by readers keep track of their readers, too.
+//! Each thread counts its read accesses locally, and it publishes them in the
+//! graph only while it is blocked: a reader which is not blocked cannot be a
+//! part of a cycle. A writer which waits for the readers to leave blocks on
+//! all of them, so a writer that is itself one of the readers is detected as
+//! well.
+//!
+//! [`PMutex`]: ./struct.PMutex.html
+//! [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+//! [`transaction_with_retry`]: ../alloc/trait.MemPool.html#method.transaction_with_retry
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+thread_local! {
+    /// The locks that the current thread has entered for reading, and the
+    /// number of times it has entered each of them
+    static READS: RefCell<HashMap<u64, usize>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the id of the current thread as it is kept in the locks
+fn current() -> u64 {
+    super::mutex::current_tid()
+}
+
+#[derive(Default)]
+struct WaitGraph {
+    /// The blocked threads which hold each lock for reading
+    readers: HashMap<u64, HashSet<u64>>,
+
+    /// The lock that each blocked thread is waiting for, and whether it waits
+    /// for the readers of the lock to leave
+    waiting: HashMap<u64, (u64, bool)>,
+}
+
+impl WaitGraph {
+    /// Returns the threads that a thread blocked on `lock` waits for
+    fn holders(&self, lock: u64, readers: bool) -> Vec<u64> {
+        if readers {
+            self.readers.get(&lock).map_or(vec![], |r| r.iter().cloned().collect())
+        } else {
+            // A blocked thread keeps its lock alive, so it can be read
+            match unsafe { super::mutex::lock_owner(lock) } {
+                0 => vec![],
+                owner => vec![owner]
+            }
+        }
+    }
+
+    /// Publishes or withdraws the read accesses of thread `tid`
+    fn publish(&mut self, tid: u64, publish: bool) {
+        READS.with(|reads| {
+            for lock in reads.borrow().keys() {
+                if publish {
+                    self.readers.entry(*lock).or_default().insert(tid);
+                } else if let Some(readers) = self.readers.get_mut(lock) {
+                    readers.remove(&tid);
+                    if readers.is_empty() {
+                        self.readers.remove(lock);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Blocks the current thread on `lock` if it does not close a cycle
+    fn wait(&mut self, lock: u64, readers: bool) -> bool {
+        let tid = current();
+        self.publish(tid, true);
+        let mut stack = vec![(lock, readers)];
+        let mut visited = HashSet::new();
+        while let Some(next) = stack.pop() {
+            if !visited.insert(next) {
+                continue;
+            }
+            for owner in self.holders(next.0, next.1) {
+                if owner == tid {
+                    self.publish(tid, false);
+                    return false;
+                }
+                if let Some(w) = self.waiting.get(&owner) {
+                    stack.push(*w);
+                }
+            }
+        }
+        self.waiting.insert(tid, (lock, readers));
+        true
+    }
+}
+
+static GRAPH: OnceLock<Mutex<WaitGraph>> = OnceLock::new();
+
+fn with_graph<T, F: FnOnce(&mut WaitGraph) -> T>(f: F) -> T {
+    let mut graph = match GRAPH.get_or_init(Default::default).lock() {
+        Ok(g) => g,
+        Err(p) => p.into_inner()
+    };
+    f(&mut graph)
+}
+
+/// Records that the current thread is going to block on `lock`. It returns
+/// false without recording anything if waiting would close a cycle.
+pub(crate) fn wait(lock: u64) -> bool {
+    with_graph(|g| g.wait(lock, false))
+}
+
+/// Records that the current thread, which owns `lock`, is going to wait for
+/// the readers of `lock` to leave. It returns false without recording
+/// anything if waiting would close a cycle, e.g. if the current thread is one
+/// of the readers.
+pub(crate) fn wait_readers(lock: u64) -> bool {
+    with_graph(|g| g.wait(lock, true))
+}
+
+/// Records that the current thread is not blocked anymore
+pub(crate) fn stop_waiting() {
+    let tid = current();
+    with_graph(|g| {
+        g.waiting.remove(&tid);
+        g.publish(tid, false);
+    })
+}
+
+/// Records that the current thread has entered `lock` for reading
+///
+/// It does not touch the graph.
+pub(crate) fn read_acquired(lock: u64) {
+    READS.with(|reads| *reads.borrow_mut().entry(lock).or_insert(0) += 1)
+}
+
+/// Records that the current thread has left `lock` once for reading
+///
+/// It does not touch the graph.
+pub(crate) fn read_released(lock: u64) {
+    READS.with(|reads| {
+        let mut reads = reads.borrow_mut();
+        if let Some(n) = reads.get_mut(&lock) {
+            *n -= 1;
+            if *n == 0 {
+                reads.remove(&lock);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sync::mutex::{acquire_lock, lock_owner, release_lock, tid_of, MutexInner};
+    use std::thread;
+
+    #[test]
+    fn wait_for_cycle() {
+        use std::sync::mpsc::channel;
+
+        let locks = (Box::new(MutexInner::default()), Box::new(MutexInner::default()));
+        let (a, b) = (locks.0.id(), locks.1.id());
+        let (tx, rx) = channel::<()>();
+        unsafe { assert!(acquire_lock(a)); }
+        let t = thread::spawn(move || unsafe {
+            assert!(acquire_lock(b));
+            assert!(acquire_lock(a));
+            rx.recv().unwrap();
+            release_lock(a);
+            release_lock(b);
+        });
+        let other = tid_of(t.thread().id());
+        while with_graph(|g| !g.waiting.contains_key(&other)) {
+            thread::yield_now();
+        }
+
+        // The other thread holds `b` and waits for `a`
+        assert!(!wait(b));
+        unsafe { release_lock(a); }
+        tx.send(()).unwrap();
+        t.join().unwrap();
+        unsafe {
+            assert_eq!(lock_owner(a), 0);
+            assert_eq!(lock_owner(b), 0);
+        }
+    }
+
+    #[test]
+    fn reader_cycle() {
+        use std::sync::mpsc::channel;
+
+        let locks = (Box::new(MutexInner::default()), Box::new(MutexInner::default()));
+        let (a, b) = (locks.0.id(), locks.1.id());
+
+        // A writer cannot wait for its own read access
+        read_acquired(a);
+        assert!(!wait_readers(a));
+
+        let (tx, rx) = channel::<()>();
+        let t = thread::spawn(move || unsafe {
+            assert!(acquire_lock(b));
+            assert!(acquire_lock(a));
+            assert!(wait_readers(a));
+            rx.recv().unwrap();
+            stop_waiting();
+            release_lock(a);
+            release_lock(b);
+        });
+        let other = tid_of(t.thread().id());
+        while with_graph(|g| !g.waiting.contains_key(&other)) {
+            thread::yield_now();
+        }
+
+        // The other thread holds `b` and waits for the current thread to
+        // stop reading `a`
+        assert!(!wait(b));
+        read_released(a);
+        tx.send(()).unwrap();
+        t.join().unwrap();
+        assert!(with_graph(|g| !g.readers.contains_key(&a)));
+    }
+}
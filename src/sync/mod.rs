@@ -1,7 +1,16 @@
 //! Useful synchronization primitives
 
+mod atomic;
+mod condvar;
+pub(crate) mod deadlock;
 mod mutex;
+mod mwcas;
 mod parc;
+mod rwlock;
 
+pub use atomic::*;
+pub use condvar::*;
 pub use mutex::*;
+pub use mwcas::*;
 pub use parc::*;
+pub use rwlock::*;
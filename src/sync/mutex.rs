@@ -1,13 +1,16 @@
 use crate::alloc::MemPool;
 use crate::cell::VCell;
 use crate::ptr::Ptr;
-use crate::stm::{Journal, Log, Notifier, Logger};
+use super::deadlock;
+use crate::stm::{Journal, Log, Notifier, Logger, ReadTx};
 use crate::*;
 use std::cell::UnsafeCell;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::panic::{RefUnwindSafe, UnwindSafe};
-use std::sync::{TryLockError, TryLockResult};
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, TryLockError, TryLockResult};
+use std::time::Duration;
 
 #[allow(unused_imports)]
 use std::{fmt, intrinsics};
@@ -90,12 +93,17 @@ pub struct PMutex<T, A: MemPool> {
     data: UnsafeCell<(u8, T)>,
 }
 
-struct MutexInner {
+pub(crate) struct MutexInner {
     borrowed: bool,
 
+    /// The number of readers which have entered the lock
+    readers: AtomicUsize,
+
+    /// The lock and the id of its owner thread, or 0 if it is free
     #[cfg(not(any(feature = "no_pthread", windows)))]
-    lock: (bool, libc::pthread_mutex_t, libc::pthread_mutexattr_t),
+    lock: (bool, libc::pthread_mutex_t, libc::pthread_mutexattr_t, u64),
 
+    /// The id of the owner thread, or 0 if the lock is free
     #[cfg(any(feature = "no_pthread", windows))]
     lock: (bool, u64)
 }
@@ -108,17 +116,26 @@ impl Default for MutexInner {
         let mut attr = MaybeUninit::<libc::pthread_mutexattr_t>::uninit();
         let mut lock = libc::PTHREAD_MUTEX_INITIALIZER;
         unsafe { init_lock(&mut lock, attr.as_mut_ptr()); }
-        MutexInner { borrowed: false, lock: (false, lock, unsafe { attr.assume_init() }) }
+        MutexInner {
+            borrowed: false,
+            readers: AtomicUsize::new(0),
+            lock: (false, lock, unsafe { attr.assume_init() }, 0)
+        }
     }
 
     #[cfg(any(feature = "no_pthread", windows))]
     fn default() -> Self {
-        MutexInner { borrowed: false, lock: (false, 0) }
+        MutexInner { borrowed: false, readers: AtomicUsize::new(0), lock: (false, 0) }
     }
 }
 
 impl MutexInner {
-    fn acquire(&self) -> bool {
+    /// Returns the address of the lock which identifies it in the logs
+    pub(crate) fn id(&self) -> u64 {
+        &self.lock as *const _ as u64
+    }
+
+    pub(crate) fn acquire(&self) -> bool {
         if self.borrowed {
             false
         } else {
@@ -127,11 +144,113 @@ impl MutexInner {
         }
     }
 
-    fn release(&self) {
+    pub(crate) fn borrowed(&self) -> bool {
+        self.borrowed
+    }
+
+    pub(crate) fn release(&self) {
         unsafe { utils::as_mut(self).borrowed = false; }
     }
+
+    /// Registers a reader without taking the lock if no thread holds it. It
+    /// returns false if the lock is held, and then the reader should enter
+    /// via [`enter_read`] while holding the lock.
+    ///
+    /// A writer publishes itself as the owner of the lock before it checks
+    /// for the readers, and a reader counts itself before it checks for the
+    /// owner, so at least one of them sees the other.
+    ///
+    /// [`enter_read`]: #method.enter_read
+    pub(crate) fn try_enter_read(&self) -> bool {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        if unsafe { lock_owner(self.id()) } == 0 {
+            deadlock::read_acquired(self.id());
+            true
+        } else {
+            self.unregister_reader();
+            false
+        }
+    }
+
+    /// Registers a reader. The lock should be acquired by the current thread,
+    /// so that no writer is in progress.
+    pub(crate) fn enter_read(&self) {
+        self.readers.fetch_add(1, Ordering::AcqRel);
+        deadlock::read_acquired(self.id());
+    }
+
+    /// Unregisters a reader which is registered by [`try_enter_read`] or
+    /// [`enter_read`]
+    ///
+    /// [`try_enter_read`]: #method.try_enter_read
+    /// [`enter_read`]: #method.enter_read
+    pub(crate) fn leave_read(&self) {
+        deadlock::read_released(self.id());
+        self.unregister_reader();
+    }
+
+    /// Decrements the number of readers, and wakes up the writers waiting
+    /// for the readers to leave if it is the last one
+    fn unregister_reader(&self) {
+        if self.readers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            fence(Ordering::SeqCst);
+            if unsafe { lock_owner(self.id()) } != 0 {
+                let _guard = DRAINED.0.lock();
+                DRAINED.1.notify_all();
+            }
+        }
+    }
+
+    /// Returns true if there is a reader inside the lock
+    pub(crate) fn has_readers(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.readers.load(Ordering::SeqCst) != 0
+    }
+
+    /// Waits until the readers leave. The lock should be acquired by the
+    /// current thread, so that no new reader can enter.
+    ///
+    /// If waiting would cause a deadlock (e.g. the current thread is one of
+    /// the readers), it releases the lock if it is `entered`, and panics with
+    /// [`Error::Deadlock`].
+    ///
+    /// [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+    pub(crate) fn wait_readers(&self, entered: bool) {
+        if self.has_readers() {
+            if !deadlock::wait_readers(self.id()) {
+                if entered {
+                    unsafe { release_lock(self.id()); }
+                }
+                std::panic::panic_any(Error::Deadlock);
+            }
+            let mut guard = match DRAINED.0.lock() {
+                Ok(g) => g,
+                Err(p) => p.into_inner()
+            };
+            while self.has_readers() {
+                // The readers in other processes cannot notify this one
+                guard = if process_shared() {
+                    match DRAINED.1.wait_timeout(guard, Duration::from_millis(1)) {
+                        Ok((g, _)) => g,
+                        Err(p) => p.into_inner().0
+                    }
+                } else {
+                    match DRAINED.1.wait(guard) {
+                        Ok(g) => g,
+                        Err(p) => p.into_inner()
+                    }
+                };
+            }
+            drop(guard);
+            deadlock::stop_waiting();
+        }
+    }
 }
 
+/// Wakes up the writers which wait for the readers of a lock to leave
+static DRAINED: (Mutex<()>, Condvar) = (Mutex::new(()), Condvar::new());
+
 impl<T: ?Sized, A: MemPool> !TxOutSafe for PMutex<T, A> {}
 impl<T, A: MemPool> UnwindSafe for PMutex<T, A> {}
 impl<T, A: MemPool> RefUnwindSafe for PMutex<T, A> {}
@@ -194,23 +313,17 @@ impl<T, A: MemPool> PMutex<T, A> {
     fn raw_lock(&self, journal: &Journal<A>) {
         unsafe {
             // Log::unlock_on_failure(self.inner.get(), journal);
-            let lock = &self.inner.lock.1 as *const _ as *mut _;
-            #[cfg(not(any(feature = "no_pthread", windows)))] {
-                libc::pthread_mutex_lock(lock);
-            }
-            #[cfg(any(feature = "no_pthread", windows))] {
-                let tid = std::thread::current().id().as_u64().get();
-                while intrinsics::atomic_cxchg_acqrel(lock, 0, tid).0 != tid {}
-            }
+            let id = self.inner.id();
+            let entered = acquire_lock(id);
+            self.inner.wait_readers(entered);
             if self.inner.acquire() {
-                Log::unlock_on_commit(&self.inner.lock as *const _ as u64, journal);
+                if entered {
+                    Log::unlock_on_commit(id, journal);
+                }
             } else {
-                #[cfg(not(any(feature = "no_pthread", windows)))]
-                libc::pthread_mutex_unlock(lock);
-
-                #[cfg(any(feature = "no_pthread", windows))] 
-                intrinsics::atomic_store_rel(lock, 0);
-
+                if entered {
+                    release_lock(id);
+                }
                 panic!("Cannot have multiple instances of MutexGuard");
             }
         }
@@ -225,8 +338,18 @@ impl<T, A: MemPool> PMutex<T, A> {
     /// transaction is done.
     /// 
     /// If the local thread already holds the lock, `lock()` does not block it.
+    /// Otherwise, it also waits for the read-only transactions which have
+    /// entered the mutex via [`read`] to end.
     /// The mutex remains locked until the transaction is committed. 
     /// Alternatively, [`PMutex`] can be used as a compact form of `Mutex`.
+    ///
+    /// # Panics
+    ///
+    /// If waiting for the lock would cause a deadlock with other
+    /// transactions, it panics with [`Error::Deadlock`] to abort the current
+    /// transaction and release its locks. The transaction then returns
+    /// `Err(Error::Deadlock)`, and it can be retried, e.g. using
+    /// [`transaction_with_retry`].
     /// 
     /// # Examples
     /// 
@@ -253,7 +376,10 @@ impl<T, A: MemPool> PMutex<T, A> {
     /// ```
     /// 
     /// [`PMutex`]: ../default/type.PMutex.html
+    /// [`read`]: #method.read
     /// [`UnlockOnCommit`]: ../stm/enum.LogEnum.html#variant.UnlockOnCommit
+    /// [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+    /// [`transaction_with_retry`]: ../alloc/trait.MemPool.html#method.transaction_with_retry
     /// 
     pub fn lock<'a>(&'a self, journal: &'a Journal<A>) -> MutexGuard<'a, T, A> {
         self.raw_lock(journal);
@@ -263,28 +389,23 @@ impl<T, A: MemPool> PMutex<T, A> {
     #[inline]
     fn raw_trylock(&self, journal: &Journal<A>) -> bool {
         unsafe {
-            let lock = &self.inner.lock.1 as *const _ as *mut _;
-
-            #[cfg(not(any(feature = "no_pthread", windows)))]
-            let result = libc::pthread_mutex_trylock(lock) == 0;
-
-            #[cfg(any(feature = "no_pthread", windows))]
-            let result = {
-                let tid = std::thread::current().id().as_u64().get();
-                intrinsics::atomic_cxchg_acqrel(lock, 0, tid).0 == tid
-            };
-
-            if result {
+            let id = self.inner.id();
+            if let Some(entered) = try_acquire_lock(id) {
+                if self.inner.has_readers() {
+                    if entered {
+                        release_lock(id);
+                    }
+                    return false;
+                }
                 if self.inner.acquire() {
-                    Log::unlock_on_commit(&self.inner.lock as *const _ as u64, journal);
+                    if entered {
+                        Log::unlock_on_commit(id, journal);
+                    }
                     true
                 } else {
-                    #[cfg(not(any(feature = "no_pthread", windows)))] 
-                    libc::pthread_mutex_unlock(lock);
-
-                    #[cfg(any(feature = "no_pthread", windows))] 
-                    intrinsics::atomic_store_rel(lock, 0);
-
+                    if entered {
+                        release_lock(id);
+                    }
                     panic!("Cannot have multiple instances of MutexGuard");
                 }
             } else {
@@ -344,6 +465,54 @@ impl<T, A: MemPool> PMutex<T, A> {
             Err(TryLockError::WouldBlock)
         }
     }
+
+    /// Acquires the mutex for shared reading in a read-only transaction,
+    /// blocking the current thread until it is able to do so.
+    ///
+    /// Unlike [`lock`], it creates no log, and it returns a shared reference
+    /// to the data. It waits only for a transaction which holds the mutex via
+    /// [`lock`], and other readers may read the data at the same time. The
+    /// read access is held until the read-only transaction ends, so that the
+    /// data cannot be modified by other transactions in the meantime; a
+    /// transaction calling [`lock`] waits for the readers to leave. Further
+    /// reads in the same read-only transaction do not block.
+    ///
+    /// # Panics
+    ///
+    /// If waiting for the lock would cause a deadlock, it panics with
+    /// [`Error::Deadlock`] which aborts the read-only transaction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use corundum::default::*;
+    ///
+    /// type P = Allocator;
+    ///
+    /// let obj = P::open::<Parc<PMutex<i32>>>("foo.pool", O_CF).unwrap();
+    ///
+    /// transaction(|j| *obj.lock(j) = 10).unwrap();
+    ///
+    /// let v = P::read_transaction(|r| *obj.read(r)).unwrap();
+    /// assert_eq!(v, 10);
+    /// ```
+    ///
+    /// [`lock`]: #method.lock
+    /// [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+    pub fn read<'a>(&'a self, tx: &'a ReadTx<A>) -> &'a T {
+        let inner = &*self.inner;
+        if !tx.holds(inner) {
+            if !inner.try_enter_read() {
+                let entered = unsafe { acquire_lock(inner.id()) };
+                inner.enter_read();
+                if entered {
+                    unsafe { release_lock(inner.id()); }
+                }
+            }
+            tx.hold(inner);
+        }
+        unsafe { &(*self.data.get()).1 }
+    }
 }
 
 impl<T: RootObj<A>, A: MemPool> RootObj<A> for PMutex<T, A> {
@@ -352,6 +521,14 @@ impl<T: RootObj<A>, A: MemPool> RootObj<A> for PMutex<T, A> {
     }
 }
 
+unsafe impl<T: Relocate<A>, A: MemPool> Relocate<A> for PMutex<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        // The lock may have waiters, so it cannot be moved
+        r.pin(self);
+        unsafe { (*self.data.get()).1.visit(r) }
+    }
+}
+
 impl<T: fmt::Debug, A: MemPool> fmt::Debug for PMutex<T, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.data.fmt(f)
@@ -409,6 +586,13 @@ impl<T, A: MemPool> Drop for MutexGuard<'_, T, A> {
     }
 }
 
+/// Initializes a recursive pthread mutex
+///
+/// The mutex is process-shared while a pool is open in the shared mode (see
+/// [`O_SHARED`]), so that the locks in the pool image work across the
+/// processes which map it.
+///
+/// [`O_SHARED`]: ../alloc/open_flags/constant.O_SHARED.html
 #[cfg(not(any(feature = "no_pthread", windows)))]
 pub unsafe fn init_lock(mutex: *mut libc::pthread_mutex_t, attr: *mut libc::pthread_mutexattr_t) {
     *mutex = libc::PTHREAD_MUTEX_INITIALIZER;
@@ -417,8 +601,153 @@ pub unsafe fn init_lock(mutex: *mut libc::pthread_mutex_t, attr: *mut libc::pthr
     let result =
         libc::pthread_mutexattr_settype(attr, libc::PTHREAD_MUTEX_RECURSIVE);
     debug_assert_eq!(result, 0);
+    if process_shared() {
+        let result =
+            libc::pthread_mutexattr_setpshared(attr, libc::PTHREAD_PROCESS_SHARED);
+        debug_assert_eq!(result, 0);
+    }
     let result = libc::pthread_mutex_init(mutex, attr);
     debug_assert_eq!(result, 0);
     let result = libc::pthread_mutexattr_destroy(attr);
     debug_assert_eq!(result, 0);
 }
+
+/// The number of pools which are open in the shared mode in this process
+static SHARED_POOLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a pool which is opened (`true`) or closed (`false`) in the shared
+/// mode. The locks initialized while there is such a pool are
+/// process-shared.
+pub fn share_locks(open: bool) {
+    if open {
+        SHARED_POOLS.fetch_add(1, Ordering::AcqRel);
+    } else {
+        SHARED_POOLS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Returns true if the locks are shared with other processes
+pub(crate) fn process_shared() -> bool {
+    SHARED_POOLS.load(Ordering::Acquire) != 0
+}
+
+/// Returns the id of a thread as it is kept in the locks
+///
+/// The id of the process is kept in the upper bits, so that the ids are
+/// unique among the processes which share a pool.
+pub(crate) fn tid_of(id: std::thread::ThreadId) -> u64 {
+    (std::process::id() as u64) << 40 | id.as_u64().get()
+}
+
+/// Returns the id of the current thread as it is kept in the locks
+pub(crate) fn current_tid() -> u64 {
+    tid_of(std::thread::current().id())
+}
+
+/// Returns the id of the thread which holds the lock of a [`PMutex`] given the
+/// address of its inner lock, or 0 if it is free
+///
+/// [`PMutex`]: ./struct.PMutex.html
+pub(crate) unsafe fn lock_owner(id: u64) -> u64 {
+    #[cfg(not(any(feature = "no_pthread", windows)))] {
+        let b = &*(id as *const (bool, libc::pthread_mutex_t, libc::pthread_mutexattr_t, u64));
+        intrinsics::atomic_load_acq(&b.3)
+    }
+    #[cfg(any(feature = "no_pthread", windows))] {
+        let b = &*(id as *const (bool, u64));
+        intrinsics::atomic_load_acq(&b.1)
+    }
+}
+
+/// Acquires the lock of a [`PMutex`] given the address of its inner lock,
+/// blocking the current thread until it is able to do so
+///
+/// It returns true if the lock is entered, and it should be released once
+/// using [`release_lock`]. If the current thread already holds the lock, it
+/// returns false.
+///
+/// If waiting for the lock would cause a deadlock, it panics with
+/// [`Error::Deadlock`]. Only the threads which block take part in the
+/// deadlock detection; the owner of each lock is read from the lock itself.
+///
+/// [`PMutex`]: ./struct.PMutex.html
+/// [`release_lock`]: ./fn.release_lock.html
+/// [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+pub(crate) unsafe fn acquire_lock(id: u64) -> bool {
+    match try_acquire_lock(id) {
+        Some(entered) => entered,
+        None => {
+            if !deadlock::wait(id) {
+                std::panic::panic_any(Error::Deadlock);
+            }
+            let tid = current_tid();
+            #[cfg(not(any(feature = "no_pthread", windows)))] {
+                let b = &mut *(id as *mut (bool, libc::pthread_mutex_t, libc::pthread_mutexattr_t, u64));
+                libc::pthread_mutex_lock(&mut b.1);
+                intrinsics::atomic_store_rel(&mut b.3, tid);
+            }
+            #[cfg(any(feature = "no_pthread", windows))] {
+                let b = &mut *(id as *mut (bool, u64));
+                while intrinsics::atomic_cxchg_acqrel(&mut b.1, 0, tid).0 != 0 {}
+            }
+            deadlock::stop_waiting();
+            true
+        }
+    }
+}
+
+/// Attempts to acquire the lock of a [`PMutex`] given the address of its
+/// inner lock without blocking
+///
+/// It returns `None` if the lock is held by another thread. Otherwise, it
+/// returns whether the lock is entered, similar to [`acquire_lock`].
+///
+/// [`PMutex`]: ./struct.PMutex.html
+/// [`acquire_lock`]: ./fn.acquire_lock.html
+pub(crate) unsafe fn try_acquire_lock(id: u64) -> Option<bool> {
+    let tid = current_tid();
+
+    #[cfg(not(any(feature = "no_pthread", windows)))] {
+        let b = &mut *(id as *mut (bool, libc::pthread_mutex_t, libc::pthread_mutexattr_t, u64));
+        if intrinsics::atomic_load_acq(&b.3) == tid {
+            Some(false)
+        } else if libc::pthread_mutex_trylock(&mut b.1) == 0 {
+            intrinsics::atomic_store_rel(&mut b.3, tid);
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(any(feature = "no_pthread", windows))] {
+        let b = &mut *(id as *mut (bool, u64));
+        match intrinsics::atomic_cxchg_acqrel(&mut b.1, 0, tid).0 {
+            0 => Some(true),
+            owner if owner == tid => Some(false),
+            _ => None
+        }
+    }
+}
+
+/// Releases the lock of a [`PMutex`] given the address of its inner lock
+///
+/// [`PMutex`]: ./struct.PMutex.html
+pub(crate) unsafe fn release_lock(id: u64) {
+    #[cfg(not(any(feature = "no_pthread", windows)))] {
+        let b = &mut *(id as *mut (bool, libc::pthread_mutex_t, libc::pthread_mutexattr_t, u64));
+        b.0 = false;
+        intrinsics::atomic_store_rel(&mut b.3, 0);
+        let lock = &mut b.1;
+        let attr = &mut b.2;
+        let result = libc::pthread_mutex_unlock(lock);
+        if result != 0 {
+            init_lock(lock, attr);
+        }
+    }
+    #[cfg(any(feature = "no_pthread", windows))] {
+        let b = &mut *(id as *mut (bool, u64));
+        b.0 = false;
+        let lock = &mut b.1;
+        intrinsics::atomic_store_rel(lock, 0);
+    }
+}
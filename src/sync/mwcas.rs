@@ -0,0 +1,747 @@
+use crate::alloc::MemPool;
+use crate::ll::{persist_obj, sfence};
+use crate::ptr::Ptr;
+use crate::stm::{Journal, Log, JOURNAL_MWCAS_COMMITTED};
+use crate::*;
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The bit which marks a word as owned by an in-flight [`PMwCas`]
+///
+/// [`PMwCas`]: ./struct.PMwCas.html
+const DESC_FLAG: u64 = 1 << 63;
+
+/// The bit which marks a word as being installed (see `install()`)
+const PENDING_FLAG: u64 = 1 << 62;
+
+/// The position of the index of the word in a pending mark
+const INDEX_SHIFT: u32 = 60;
+
+/// The position of the tag of a pending mark, which tells apart the attempts
+/// to install the same word
+const TAG_SHIFT: u32 = 47;
+const TAG_MASK: u64 = (1 << 13) - 1;
+
+/// The bits of a mark which hold the offset of the descriptor
+const OFF_MASK: u64 = (1 << TAG_SHIFT) - 1;
+
+/// The maximum number of words that a [`PMwCas`] can update at once
+///
+/// [`PMwCas`]: ./struct.PMwCas.html
+pub const MWCAS_MAX_WORDS: usize = 4;
+
+const UNDECIDED: u64 = 0;
+const SUCCEEDED: u64 = 1;
+const FAILED: u64 = 2;
+
+/// A persistent 64-bit word which can be updated by [`PMwCas`]
+///
+/// The most significant bit of the word is reserved for marking it as owned
+/// by an in-flight operation; the values are limited to [`PWord::MAX`].
+///
+/// [`PMwCas`]: ./struct.PMwCas.html
+/// [`PWord::MAX`]: #associatedconstant.MAX
+pub struct PWord<A: MemPool> {
+    value: AtomicU64,
+    heap: PhantomData<A>,
+}
+
+impl<A: MemPool> !TxOutSafe for PWord<A> {}
+impl<A: MemPool> UnwindSafe for PWord<A> {}
+impl<A: MemPool> RefUnwindSafe for PWord<A> {}
+
+unsafe impl<A: MemPool> TxInSafe for PWord<A> {}
+unsafe impl<A: MemPool> PSafe for PWord<A> {}
+unsafe impl<A: MemPool> Send for PWord<A> {}
+unsafe impl<A: MemPool> Sync for PWord<A> {}
+unsafe impl<A: MemPool> PSend for PWord<A> {}
+
+impl<A: MemPool> PWord<A> {
+    /// The largest value that a `PWord` can hold
+    pub const MAX: u64 = DESC_FLAG - 1;
+
+    /// Creates a new word
+    ///
+    /// # Panics
+    ///
+    /// It panics if `v` is greater than [`PWord::MAX`](#associatedconstant.MAX).
+    pub fn new(v: u64) -> Self {
+        assert!(v <= Self::MAX, "The value of a PWord cannot exceed PWord::MAX");
+        Self {
+            value: AtomicU64::new(v),
+            heap: PhantomData,
+        }
+    }
+
+    /// Loads the value
+    ///
+    /// If the word is owned by an in-flight [`PMwCas`], it helps the operation
+    /// to finish instead of waiting for its owner.
+    ///
+    /// [`PMwCas`]: ./struct.PMwCas.html
+    pub fn load(&self) -> u64 {
+        let _guard = mwcas_pin();
+        loop {
+            let v = self.value.load(Ordering::Acquire);
+            if v & DESC_FLAG == 0 {
+                return v;
+            }
+            unsafe { help::<A>(v); }
+        }
+    }
+
+    /// Loads the value, or returns `None` if the word is owned by an
+    /// in-flight [`PMwCas`]
+    ///
+    /// [`PMwCas`]: ./struct.PMwCas.html
+    pub fn try_load(&self) -> Option<u64> {
+        let v = self.value.load(Ordering::Acquire);
+        if v & DESC_FLAG == 0 {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a reference to the raw value for visiting the persistent
+    /// pointers stored in the word
+    pub(crate) fn as_u64(&self) -> &u64 {
+        unsafe { &*(&self.value as *const AtomicU64 as *const u64) }
+    }
+}
+
+impl<A: MemPool> Default for PWord<A> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+unsafe impl<A: MemPool> Relocate<A> for PWord<A> {
+    fn visit(&self, _r: &mut Relocator<A>) {}
+}
+
+impl<A: MemPool> fmt::Debug for PWord<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.load(), f)
+    }
+}
+
+/// The persistent descriptor of a [`PMwCas`]
+///
+/// [`PMwCas`]: ./struct.PMwCas.html
+#[repr(C)]
+struct Descriptor {
+    status: AtomicU64,
+    len: u64,
+    /// `(off, len)` of an allocation to be reclaimed on success
+    reclaim: (u64, u64),
+    /// `(word, current, new)` for every target word, sorted by `word`
+    words: [(u64, u64, u64); MWCAS_MAX_WORDS],
+    /// The next tag of a pending mark; it is only used while the operation
+    /// is in flight
+    tag: AtomicU64,
+}
+
+/// The epoch in which the threads start reading the descriptors
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of threads which read the descriptors in each epoch
+static PINNED: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// Serializes the threads which wait for the readers to leave an epoch
+static RECLAIM: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// The number of live guards of the current thread, and their epoch
+    static PIN: Cell<(usize, usize)> = Cell::new((0, 0));
+}
+
+/// Keeps the descriptors of [`PMwCas`] and the objects that it reclaims from
+/// being deallocated
+///
+/// A thread which reads a word updated by [`PMwCas`] (e.g. the head of a
+/// list) and then dereferences it should hold a guard meanwhile, so that the
+/// object is not reclaimed by another operation before it is done. It is
+/// created by [`mwcas_pin`] and it can be nested.
+///
+/// [`PWord::load`] and [`PMwCas::execute`] take a guard themselves. A failed
+/// [`execute`] deallocates its descriptor, so it lets go of the guards of the
+/// current thread until the other threads are done with it; the objects which
+/// are read before it should be read again.
+///
+/// [`PMwCas`]: ./struct.PMwCas.html
+/// [`mwcas_pin`]: ./fn.mwcas_pin.html
+/// [`PWord::load`]: ./struct.PWord.html#method.load
+/// [`PMwCas::execute`]: ./struct.PMwCas.html#method.execute
+/// [`execute`]: ./struct.PMwCas.html#method.execute
+pub struct MwCasGuard {
+    phantom: PhantomData<*const ()>,
+}
+
+/// Protects the current thread against the reclamation of the objects
+/// updated by [`PMwCas`] until the returned guard is dropped
+///
+/// [`PMwCas`]: ./struct.PMwCas.html
+pub fn mwcas_pin() -> MwCasGuard {
+    PIN.with(|pin| {
+        let (depth, epoch) = pin.get();
+        if depth > 0 {
+            pin.set((depth + 1, epoch));
+        } else {
+            pin.set((1, enter_epoch()));
+        }
+    });
+    MwCasGuard { phantom: PhantomData }
+}
+
+impl Drop for MwCasGuard {
+    fn drop(&mut self) {
+        PIN.with(|pin| {
+            let (depth, epoch) = pin.get();
+            if depth == 1 {
+                PINNED[epoch].fetch_sub(1, Ordering::SeqCst);
+            }
+            pin.set((depth - 1, epoch));
+        })
+    }
+}
+
+/// Counts the current thread in the current epoch, and returns the epoch
+fn enter_epoch() -> usize {
+    loop {
+        let epoch = EPOCH.load(Ordering::SeqCst) & 1;
+        PINNED[epoch].fetch_add(1, Ordering::SeqCst);
+        // A reclaiming thread may have moved to the next epoch before the
+        // current thread is counted
+        if EPOCH.load(Ordering::SeqCst) & 1 == epoch {
+            return epoch;
+        }
+        PINNED[epoch].fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits until the threads which may have read a descriptor or an object
+/// that is not reachable anymore let go of their guards, so that it can be
+/// deallocated
+///
+/// The guards of the current thread are let go meanwhile, so that two
+/// reclaiming threads do not wait for each other.
+fn synchronize() {
+    let (depth, epoch) = PIN.with(|pin| pin.get());
+    if depth > 0 {
+        PINNED[epoch].fetch_sub(1, Ordering::SeqCst);
+    }
+    {
+        let _lock = match RECLAIM.lock() {
+            Ok(g) => g,
+            Err(p) => p.into_inner()
+        };
+        let old = EPOCH.fetch_add(1, Ordering::SeqCst) & 1;
+        while PINNED[old].load(Ordering::SeqCst) != 0 {
+            std::thread::yield_now();
+        }
+    }
+    if depth > 0 {
+        PIN.with(|pin| pin.set((depth, enter_epoch())));
+    }
+}
+
+/// A persistent multi-word compare-and-swap
+///
+/// `PMwCas` atomically updates up to [`MWCAS_MAX_WORDS`] [`PWord`]s if all of
+/// them hold their expected values, without taking any lock. The operation
+/// is described in a persistent descriptor which is logged in the journal as
+/// [`MwCas`]. Each target word is then marked with the address of the
+/// descriptor in the order of their addresses. Once all words are marked,
+/// the descriptor is marked as succeeded, and the new values are written. If
+/// a word does not hold its expected value, the descriptor is marked as
+/// failed, and the marked words are restored. If a crash happens in between,
+/// the recovery procedure uses the descriptor to complete or revert the
+/// operation.
+///
+/// The operation is lock-free: a thread which finds a word marked by another
+/// operation, either in [`execute`] or in [`PWord::load`], helps that
+/// operation to finish using its descriptor, instead of waiting for its
+/// owner. A word is marked in two steps, so that a helper which is delayed
+/// cannot mark it again after the operation is finished. Since the marked
+/// words are taken in the order of their addresses, helping never goes
+/// around in a circle. The descriptors, and the objects given to
+/// [`reclaim_on_success`], are deallocated only after the threads which
+/// might be helping with them are done (see [`MwCasGuard`]).
+///
+/// The descriptor of a failed operation is reclaimed right away, and its log
+/// is reused by the next operation of the transaction, so that retrying in a
+/// loop does not grow the journal. The threads are tracked per process, so
+/// a pool which is shared by several processes (see [`O_SHARED`]) should not
+/// be updated by `PMwCas` in more than one of them.
+///
+/// A successful operation is durable when [`execute`] returns, and it is the
+/// commit point of the enclosing transaction: since other threads may already
+/// observe the new values, the transaction cannot be rolled back afterwards.
+/// Therefore, nothing else can be logged in the transaction: a change which
+/// needs a log (e.g. updating a [`PCell`], allocating an object, or another
+/// `PMwCas`) panics, and the transaction is committed as it was when
+/// `execute` returned. The error is returned as usual. Non-logged work, such
+/// as reading and returning values, may follow `execute`.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// use corundum::sync::PMwCas;
+///
+/// type P = Allocator;
+///
+/// #[derive(Root)]
+/// struct Account {
+///     balance: PWord,
+///     version: PWord,
+/// }
+///
+/// let acc = P::open::<Account>("foo.pool", O_CF).unwrap();
+///
+/// P::transaction(|j| loop {
+///     let (b, v) = (acc.balance.load(), acc.version.load());
+///     let mut cas = PMwCas::new();
+///     cas.compare_exchange(&acc.version, v, v + 1);
+///     cas.compare_exchange(&acc.balance, b, b + 10);
+///     if cas.execute(j) {
+///         break;
+///     }
+/// }).unwrap();
+///
+/// assert_eq!(acc.balance.load(), 10);
+/// ```
+///
+/// [`MWCAS_MAX_WORDS`]: ./constant.MWCAS_MAX_WORDS.html
+/// [`PWord`]: ./struct.PWord.html
+/// [`MwCas`]: ../stm/enum.LogEnum.html#variant.MwCas
+/// [`execute`]: #method.execute
+/// [`reclaim_on_success`]: #method.reclaim_on_success
+/// [`PWord::load`]: ./struct.PWord.html#method.load
+/// [`MwCasGuard`]: ./struct.MwCasGuard.html
+/// [`O_SHARED`]: ../alloc/open_flags/constant.O_SHARED.html
+/// [`PCell`]: ../cell/struct.PCell.html
+pub struct PMwCas<'a, A: MemPool> {
+    words: Vec<(&'a PWord<A>, u64, u64)>,
+    reclaim: (u64, u64),
+}
+
+impl<'a, A: MemPool> PMwCas<'a, A> {
+    /// Creates an empty operation
+    pub fn new() -> Self {
+        Self {
+            words: Vec::with_capacity(MWCAS_MAX_WORDS),
+            reclaim: (u64::MAX, 0),
+        }
+    }
+
+    /// Adds a word to be updated to `new` if it holds `current`
+    ///
+    /// # Panics
+    ///
+    /// It panics if more than [`MWCAS_MAX_WORDS`] words are added, if the
+    /// word is already added, if it is not in the pool, or if any of the
+    /// values exceeds [`PWord::MAX`].
+    ///
+    /// [`MWCAS_MAX_WORDS`]: ./constant.MWCAS_MAX_WORDS.html
+    /// [`PWord::MAX`]: ./struct.PWord.html#associatedconstant.MAX
+    pub fn compare_exchange(&mut self, word: &'a PWord<A>, current: u64, new: u64) -> &mut Self {
+        assert!(self.words.len() < MWCAS_MAX_WORDS, "Too many words in a PMwCas");
+        assert!(current <= PWord::<A>::MAX && new <= PWord::<A>::MAX,
+            "The value of a PWord cannot exceed PWord::MAX");
+        assert!(A::valid(word), "The object is not in the pool's valid range");
+        assert!(!self.words.iter().any(|(w, _, _)| std::ptr::eq(*w, word)),
+            "A word cannot be added to a PMwCas twice");
+        self.words.push((word, current, new));
+        self
+    }
+
+    /// Deallocates `obj` after the transaction commits, if the operation
+    /// succeeds
+    ///
+    /// This is useful for unlinking an object from a data structure which
+    /// is updated by `PMwCas`. Since other threads may still be reading the
+    /// object, it is not reused before the transaction commits and the
+    /// threads holding a [`MwCasGuard`] let go of it.
+    ///
+    /// # Safety
+    ///
+    /// `obj` should be allocated in the pool, it should not be dropped
+    /// otherwise, and it should become unreachable if the operation succeeds.
+    ///
+    /// [`MwCasGuard`]: ./struct.MwCasGuard.html
+    pub unsafe fn reclaim_on_success<T: ?Sized>(&mut self, obj: &T) -> &mut Self {
+        self.reclaim = (A::off_unchecked(obj), mem::size_of_val(obj) as u64);
+        self
+    }
+
+    /// Performs the operation and returns `true` if all words are updated
+    ///
+    /// If it returns `false`, none of the words is changed, and the caller
+    /// may reload the values and retry. If it returns `true`, the transaction
+    /// cannot log any more changes (see [`PMwCas`](#)).
+    pub fn execute(self, journal: &Journal<A>) -> bool {
+        if self.words.is_empty() {
+            return true;
+        }
+        let mut words = [(u64::MAX, 0, 0); MWCAS_MAX_WORDS];
+        for (i, (w, current, new)) in self.words.iter().enumerate() {
+            words[i] = (unsafe { A::off_unchecked(*w) }, *current, *new);
+        }
+        words[..self.words.len()].sort_unstable_by_key(|w| w.0);
+        let (mut log, desc, off) = unsafe {
+            new_descriptor(Descriptor {
+                status: AtomicU64::new(UNDECIDED),
+                len: self.words.len() as u64,
+                reclaim: self.reclaim,
+                words,
+                tag: AtomicU64::new(0),
+            }, journal)
+        };
+        assert!(off <= OFF_MASK, "The descriptor is out of the range of a PWord mark");
+
+        let succeeded = {
+            let _guard = mwcas_pin();
+            unsafe { run::<A>(off) }
+        };
+
+        if succeeded {
+            unsafe { crate::utils::as_mut(journal).set(JOURNAL_MWCAS_COMMITTED); }
+        } else {
+            // The helpers may still be reading the descriptor, but the
+            // recovery has nothing to do for it
+            synchronize();
+            unsafe {
+                let z = A::pre_dealloc(desc as *mut Descriptor as *mut u8, mem::size_of::<Descriptor>());
+                log.reset_mwcas(z);
+                A::perform(z);
+            }
+        }
+        succeeded
+    }
+}
+
+impl<A: MemPool> Default for PMwCas<'_, A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: MemPool> fmt::Debug for PMwCas<'_, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.words.iter().map(|(_, current, new)| (current, new)))
+            .finish()
+    }
+}
+
+/// Allocates a descriptor and logs it in the journal
+unsafe fn new_descriptor<'a, A: MemPool>(
+    desc: Descriptor,
+    journal: &Journal<A>,
+) -> (Ptr<Log<A>, A>, &'a mut Descriptor, u64) {
+    let mut log = Log::mwcas(journal);
+    let (p, off, len, z) = A::atomic_new(desc);
+    log.set(off, len, z);
+    A::perform(z);
+    persist_obj(p, true);
+    (log, p, off)
+}
+
+#[inline]
+fn word<'a, A: MemPool>(off: u64) -> &'a AtomicU64 {
+    unsafe { A::get_unchecked::<AtomicU64>(off) }
+}
+
+/// Returns true if `v` is a pending mark of the descriptor at `off`
+#[inline]
+fn is_pending(v: u64, off: u64) -> bool {
+    v & (DESC_FLAG | PENDING_FLAG) == DESC_FLAG | PENDING_FLAG && v & OFF_MASK == off
+}
+
+/// Helps the operation which has marked a word with `mark` to finish. The
+/// current thread should hold a guard.
+unsafe fn help<A: MemPool>(mark: u64) {
+    let off = mark & OFF_MASK;
+    if mark & PENDING_FLAG != 0 {
+        let k = (mark >> INDEX_SHIFT) & 3;
+        complete_install::<A>(off, k as usize, mark);
+    } else {
+        run::<A>(off);
+    }
+}
+
+/// Runs the operation of the descriptor at `off` to the end, and returns
+/// true if it has succeeded. It may be called by the owner of the operation
+/// or by any helper, which should hold a guard.
+unsafe fn run<A: MemPool>(off: u64) -> bool {
+    let desc = A::get_unchecked::<Descriptor>(off);
+    let marker = DESC_FLAG | off;
+    let len = desc.len as usize;
+
+    if desc.status.load(Ordering::Acquire) == UNDECIDED {
+        let mut status = SUCCEEDED;
+        'words: for k in 0..len {
+            let (w, current, _) = desc.words[k];
+            let w = word::<A>(w);
+            loop {
+                let v = w.load(Ordering::Acquire);
+                if v == marker {
+                    break;
+                } else if is_pending(v, off) {
+                    complete_install::<A>(off, k, v);
+                } else if v & DESC_FLAG != 0 {
+                    // Another operation owns the word. It has taken its words
+                    // in the same order, so it does not wait for this one.
+                    help::<A>(v);
+                } else if v != current {
+                    status = FAILED;
+                    break 'words;
+                } else if desc.status.load(Ordering::Acquire) != UNDECIDED {
+                    break 'words;
+                } else {
+                    install::<A>(desc, off, k, current);
+                }
+            }
+        }
+        if status == SUCCEEDED {
+            // All words should be durably marked before the decision
+            for (w, _, _) in &desc.words[..len] {
+                persist_obj(word::<A>(*w), false);
+            }
+            sfence();
+        }
+        let _ = desc.status.compare_exchange(UNDECIDED, status,
+            Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    // The decision should be durable before any word is released
+    let succeeded = desc.status.load(Ordering::Acquire) == SUCCEEDED;
+    persist_obj(&desc.status, true);
+
+    for (k, (w, current, new)) in desc.words[..len].iter().enumerate() {
+        let w = word::<A>(*w);
+        loop {
+            let v = w.load(Ordering::Acquire);
+            if v == marker {
+                let value = if succeeded { *new } else { *current };
+                if w.compare_exchange(v, value, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                    persist_obj(w, false);
+                    break;
+                }
+            } else if is_pending(v, off) {
+                complete_install::<A>(off, k, v);
+            } else {
+                break;
+            }
+        }
+    }
+    sfence();
+    succeeded
+}
+
+/// Marks the `k`-th word of the descriptor at `off`, if it holds `current`
+///
+/// The word is first replaced with a pending mark, which carries a fresh tag.
+/// Then, it is marked only if the operation is still undecided, or it is
+/// restored otherwise. A helper which is delayed before the first step may
+/// find the word holding `current` again after the operation is finished,
+/// but it only leaves a pending mark, which is then restored.
+unsafe fn install<A: MemPool>(desc: &Descriptor, off: u64, k: usize, current: u64) {
+    let tag = desc.tag.fetch_add(1, Ordering::Relaxed) & TAG_MASK;
+    let pending = DESC_FLAG | PENDING_FLAG | (k as u64) << INDEX_SHIFT | tag << TAG_SHIFT | off;
+    let w = word::<A>(desc.words[k].0);
+    if w.compare_exchange(current, pending, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+        complete_install::<A>(off, k, pending);
+    }
+}
+
+/// Completes the `pending` mark of the `k`-th word of the descriptor at `off`
+unsafe fn complete_install<A: MemPool>(off: u64, k: usize, pending: u64) {
+    let desc = A::get_unchecked::<Descriptor>(off);
+    let (w, current, _) = desc.words[k];
+    let value = if desc.status.load(Ordering::Acquire) == UNDECIDED {
+        DESC_FLAG | off
+    } else {
+        current
+    };
+    let _ = word::<A>(w).compare_exchange(pending, value, Ordering::AcqRel, Ordering::Acquire);
+}
+
+/// Returns true if the operation of the descriptor at `off` has succeeded
+pub(crate) fn mwcas_succeeded<A: MemPool>(off: u64) -> bool {
+    unsafe { A::get_unchecked::<Descriptor>(off).status.load(Ordering::Acquire) == SUCCEEDED }
+}
+
+/// Completes or reverts the operation of the descriptor at `off`
+///
+/// The words which are still marked with the descriptor are updated to their
+/// new values if the operation has succeeded, or restored otherwise. A word
+/// with a pending mark was never marked, so it is restored.
+pub(crate) unsafe fn recover_mwcas<A: MemPool>(off: u64) {
+    let desc = A::get_unchecked::<Descriptor>(off);
+    let marker = DESC_FLAG | off;
+    let succeeded = desc.status.load(Ordering::Acquire) == SUCCEEDED;
+    for (w, current, new) in &desc.words[..desc.len as usize] {
+        let word = word::<A>(*w);
+        let v = word.load(Ordering::Acquire);
+        let value = if v == marker && succeeded {
+            *new
+        } else if v == marker || is_pending(v, off) {
+            *current
+        } else {
+            continue;
+        };
+        if word.compare_exchange(v, value, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            persist_obj(word, false);
+        }
+    }
+    sfence();
+}
+
+/// Reclaims the descriptor at `*off`, as well as the allocation that it
+/// should reclaim on success
+///
+/// It waits until the threads which may be reading them let go of their
+/// guards.
+pub(crate) unsafe fn clear_mwcas<A: MemPool>(off: &mut u64) {
+    synchronize();
+    let desc = A::get_mut_unchecked::<Descriptor>(*off);
+    if desc.status.load(Ordering::Acquire) == SUCCEEDED && desc.reclaim.0 != u64::MAX {
+        let z = A::pre_dealloc(A::get_mut_unchecked(desc.reclaim.0), desc.reclaim.1 as usize);
+        A::log64(A::off_unchecked(&desc.reclaim.0), u64::MAX, z);
+        A::perform(z);
+    }
+    let z = A::pre_dealloc(desc as *mut Descriptor as *mut u8, mem::size_of::<Descriptor>());
+    A::log64(A::off_unchecked(off), u64::MAX, z);
+    A::perform(z);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{install, mwcas_succeeded, new_descriptor, word, Descriptor};
+    use super::{DESC_FLAG, MWCAS_MAX_WORDS, UNDECIDED};
+    use crate::default::*;
+    use crate::sync::PMwCas;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::thread;
+
+    type P = Allocator;
+
+    #[test]
+    fn concurrent_transfers() {
+        let root = P::open::<(PWord, PWord)>("mwcas.pool", O_CF).unwrap();
+        P::transaction(|j| {
+            let mut cas = PMwCas::new();
+            cas.compare_exchange(&root.0, root.0.load(), 1000);
+            cas.compare_exchange(&root.1, root.1.load(), 0);
+            assert!(cas.execute(j));
+        }).unwrap();
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let root = root.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    P::transaction(|j| loop {
+                        let (a, b) = (root.0.load(), root.1.load());
+                        let mut cas = PMwCas::new();
+                        cas.compare_exchange(&root.0, a, a - 1);
+                        cas.compare_exchange(&root.1, b, b + 1);
+                        if cas.execute(j) {
+                            break;
+                        }
+                    }).unwrap();
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(root.0.load(), 600);
+        assert_eq!(root.1.load(), 400);
+
+        // A successful operation is not rolled back
+        assert!(P::transaction(|j| {
+            let mut cas = PMwCas::new();
+            cas.compare_exchange(&root.0, 600, 0);
+            assert!(cas.execute(j));
+            panic!("abort");
+        }).is_err());
+        assert_eq!(root.0.load(), 0);
+    }
+
+    #[test]
+    fn no_logs_after_commit_point() {
+        let root = P::open::<(PWord, PCell<u64>)>("mwcas_commit.pool", O_CF).unwrap();
+        assert!(P::transaction(|j| {
+            root.1.set(1, j);
+            let mut cas = PMwCas::new();
+            cas.compare_exchange(&root.0, 0, 1);
+            assert!(cas.execute(j));
+            root.1.set(2, j);
+        }).is_err());
+
+        // The transaction is committed as it was when `execute` returned
+        assert_eq!(root.0.load(), 1);
+        assert_eq!(root.1.get(), 1);
+
+        // The next transaction is not affected
+        P::transaction(|j| root.1.set(3, j)).unwrap();
+        assert_eq!(root.1.get(), 3);
+    }
+
+    #[test]
+    fn failed_attempts_reuse_log() {
+        let root = P::open::<(PWord, PWord)>("mwcas_retry.pool", O_CF).unwrap();
+        P::transaction(|j| {
+            let pages = j.page_count();
+            for _ in 0..1000 {
+                let mut cas = PMwCas::new();
+                cas.compare_exchange(&root.0, 1, 2);
+                assert!(!cas.execute(j));
+            }
+            assert!(j.page_count() <= pages + 1);
+        }).unwrap();
+        assert_eq!(root.0.load(), 0);
+    }
+
+    #[test]
+    fn readers_help_stalled_operations() {
+        let root = P::open::<(PWord, PWord)>("mwcas_help.pool", O_CF).unwrap();
+        P::transaction(|j| unsafe {
+            let (a, b) = (P::off_unchecked(&root.0), P::off_unchecked(&root.1));
+            let (lo, hi) = (a.min(b), a.max(b));
+            let mut words = [(u64::MAX, 0, 0); MWCAS_MAX_WORDS];
+            words[0] = (lo, 0, 1);
+            words[1] = (hi, 0, 2);
+            let (_log, desc, off) = new_descriptor(Descriptor {
+                status: AtomicU64::new(UNDECIDED),
+                len: 2,
+                reclaim: (u64::MAX, 0),
+                words,
+                tag: AtomicU64::new(0),
+            }, j);
+
+            // The owner stalls after marking the first word
+            install::<P>(desc, off, 0, 0);
+            assert_eq!(word::<P>(lo).load(Ordering::Acquire), DESC_FLAG | off);
+
+            // A reader finishes the operation instead of waiting for it
+            let _ = if lo == a { root.0.load() } else { root.1.load() };
+            assert!(mwcas_succeeded::<P>(off));
+            assert_eq!(word::<P>(lo).load(Ordering::Acquire), 1);
+            assert_eq!(word::<P>(hi).load(Ordering::Acquire), 2);
+
+            // A late attempt to mark a word cannot take it again
+            word::<P>(lo).store(0, Ordering::Release);
+            install::<P>(desc, off, 0, 0);
+            assert_eq!(word::<P>(lo).load(Ordering::Acquire), 0);
+        }).unwrap();
+    }
+}
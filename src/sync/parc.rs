@@ -188,8 +188,35 @@ impl<T: PSafe, A: MemPool> Parc<T, A> {
     /// }).unwrap();
     /// ```
     pub fn new(value: T, journal: &Journal<A>) -> Parc<T, A> {
+        match Self::try_new(value, journal) {
+            Ok(p) => p,
+            Err(_) => panic!("Memory exhausted")
+        }
+    }
+
+    /// Constructs a new `Parc<T>`, or returns an [`AllocError`] if the pool
+    /// is out of space.
+    ///
+    /// Unlike [`new`], it does not panic on failure, and the transaction can
+    /// continue. `value` is dropped if the allocation fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::alloc::heap::*;
+    /// use corundum::sync::Parc;
+    ///
+    /// Heap::transaction(|j| {
+    ///     let five = Parc::try_new(5, j).unwrap();
+    ///     assert_eq!(*five, 5);
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`new`]: #method.new
+    /// [`AllocError`]: std::alloc::AllocError
+    pub fn try_new(value: T, journal: &Journal<A>) -> Result<Parc<T, A>, std::alloc::AllocError> {
         unsafe {
-            let ptr = Ptr::new_unchecked(A::new(
+            let ptr = Ptr::new_unchecked(A::try_new(
                 ParcInner::<T, A> {
                     counter: Counter {
                         strong: 1,
@@ -204,8 +231,8 @@ impl<T: PSafe, A: MemPool> Parc<T, A> {
                     value,
                 },
                 journal,
-            ));
-            Self::from_inner(ptr)
+            )?);
+            Ok(Self::from_inner(ptr))
         }
     }
 
@@ -636,7 +663,7 @@ impl<T: PSafe, A: MemPool> Parc<T, A> {
             "Parc::initialize() cannot be used inside a transaction"
         );
         match arc {
-            Some(_) => Err("already initialized".to_string()),
+            Some(_) => Err(crate::Error::AlreadyInitialized),
             None => if A::valid(arc) {
                 unsafe {
                     let new = A::atomic_new(
@@ -664,7 +691,9 @@ impl<T: PSafe, A: MemPool> Parc<T, A> {
                 }
                 Ok(())
             } else {
-                Err("The object is not in the PM".to_string())
+                Err(crate::Error::InvalidArgument(
+                    "The object is not in the PM".to_string()
+                ))
             }
         }
     }
@@ -751,6 +780,18 @@ impl<T: RootObj<A> + PSafe, A: MemPool> RootObj<A> for Parc<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for Parc<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        if !self.inner().vlist.is_empty() {
+            // The volatile weak references point to the current location
+            r.pin(self.inner());
+        }
+        if r.visit(self.ptr.off_ref(), mem::size_of::<ParcInner<T, A>>()) {
+            self.inner().value.visit(r)
+        }
+    }
+}
+
 // impl<T: Default + PSafe + ?Sized, A: MemPool> RootObj<A> for Parc<T, A> {
 //     #[inline]
 //     default fn init(journal: &Journal<A>) -> Parc<T, A> {
@@ -1062,6 +1103,19 @@ impl<T: PSafe + ?Sized, A: MemPool> Drop for Weak<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for Weak<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        if r.visit(self.ptr.off_ref(), mem::size_of::<ParcInner<T, A>>()) {
+            // The value is already dropped if there is no strong reference
+            if let Some(inner) = self.inner() {
+                if inner.counter.strong != 0 {
+                    inner.value.visit(r)
+                }
+            }
+        }
+    }
+}
+
 impl<T: PSafe + ?Sized, A: MemPool> PClone<A> for Weak<T, A> {
     #[inline]
     fn pclone(&self, j: &Journal<A>) -> Weak<T, A> {
@@ -1408,6 +1462,13 @@ struct VWeakList {
 }
 
 impl VWeakList {
+    fn is_empty(&self) -> bool {
+        match self.head.lock() {
+            Ok(g) => g.is_null(),
+            Err(p) => p.into_inner().is_null(),
+        }
+    }
+
     fn append(&mut self) -> *mut VWeakValid {
         let list = self as *mut Self;
         let mut head = match self.head.lock() {
@@ -0,0 +1,435 @@
+use crate::alloc::MemPool;
+use crate::cell::VCell;
+use crate::ptr::Ptr;
+use super::mutex::*;
+use crate::stm::{Journal, Log, Notifier, Logger};
+use crate::*;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::{TryLockError, TryLockResult};
+use std::fmt;
+
+/// A transaction-wide reader-writer lock
+///
+/// This type of lock allows a number of readers or at most one writer at any
+/// point in time. Similar to [`PMutex`], the write lock is held until the
+/// transaction commits, so that no other thread can observe the uncommitted
+/// changes. The read lock, on the other hand, is held only as long as the read
+/// guard lives, and it does not need a transaction. Readers wait for the
+/// writer transaction to commit, and a writer waits for the current readers
+/// to drop their guards.
+///
+/// Same as [`PMutex`], the lock state is kept in a [`VCell`], so the lock is
+/// reset to the unlocked state after a restart or a crash. The threads which
+/// wait for the readers to leave take part in the deadlock detection of the
+/// transaction-wide locks.
+///
+/// # Examples
+///
+/// ```
+/// use corundum::default::*;
+/// use std::thread;
+///
+/// type P = Allocator;
+///
+/// let obj = P::open::<Parc<PRwLock<i32>>>("foo.pool", O_CF).unwrap();
+///
+/// P::transaction(|j| {
+///     *obj.write(j) += 10;
+/// }).unwrap();
+///
+/// let mut threads = vec![];
+/// for _ in 0..4 {
+///     let obj = Parc::demote(&obj);
+///     threads.push(thread::spawn(move || {
+///         P::transaction(move |j| {
+///             if let Some(obj) = obj.promote(j) {
+///                 assert_eq!(*obj.read(), 10);
+///             }
+///         }).unwrap();
+///     }));
+/// }
+/// for t in threads {
+///     t.join().unwrap();
+/// }
+/// ```
+///
+/// [`PMutex`]: ./struct.PMutex.html
+/// [`VCell`]: ../cell/struct.VCell.html
+pub struct PRwLock<T, A: MemPool> {
+    heap: PhantomData<A>,
+    /// The writer lock which is held until the writer transaction commits,
+    /// and the number of live read guards
+    inner: VCell<MutexInner, A>,
+    data: UnsafeCell<(u8, T)>,
+}
+
+impl<T: ?Sized, A: MemPool> !TxOutSafe for PRwLock<T, A> {}
+impl<T, A: MemPool> UnwindSafe for PRwLock<T, A> {}
+impl<T, A: MemPool> RefUnwindSafe for PRwLock<T, A> {}
+
+unsafe impl<T, A: MemPool> TxInSafe for PRwLock<T, A> {}
+unsafe impl<T, A: MemPool> PSafe for PRwLock<T, A> {}
+unsafe impl<T: Send, A: MemPool> Send for PRwLock<T, A> {}
+unsafe impl<T: Send + Sync, A: MemPool> Sync for PRwLock<T, A> {}
+unsafe impl<T, A: MemPool> PSend for PRwLock<T, A> {}
+
+impl<T, A: MemPool> PRwLock<T, A> {
+    /// Creates a new `PRwLock`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::alloc::heap::*;
+    ///
+    /// Heap::transaction(|j| {
+    ///     let p = Parc::new(PRwLock::new(10), j);
+    /// }).unwrap();
+    /// ```
+    pub fn new(data: T) -> PRwLock<T, A> {
+        PRwLock {
+            heap: PhantomData,
+            inner: VCell::new(MutexInner::default()),
+            data: UnsafeCell::new((0, data)),
+        }
+    }
+}
+
+impl<T: PSafe, A: MemPool> PRwLock<T, A> {
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    #[track_caller]
+    /// Takes a log and returns a `&mut T` for interior mutability
+    fn get_mut(&self, journal: &Journal<A>) -> &mut T {
+        unsafe {
+            let inner = &mut *self.data.get();
+            if inner.0 == 0 {
+                assert!(A::valid(inner), "The object is not in the pool's valid range");
+                inner.1.create_log(journal, Notifier::NonAtomic(Ptr::from_ref(&inner.0)));
+            }
+            &mut inner.1
+        }
+    }
+}
+
+impl<T, A: MemPool> PRwLock<T, A> {
+    /// Registers a reader while holding the writer lock, so that no writer
+    /// can be in progress
+    #[inline]
+    fn enter_read(&self, entered: bool) {
+        let inner = &*self.inner;
+
+        // Only the current thread can have a write guard while the writer
+        // lock is acquired
+        let borrowed = inner.borrowed();
+        if !borrowed {
+            inner.enter_read();
+        }
+        if entered {
+            unsafe { release_lock(inner.id()); }
+        }
+        assert!(!borrowed, "Value was already mutably borrowed");
+    }
+
+    /// Locks this lock with shared read access, blocking the current thread
+    /// until it can be acquired.
+    ///
+    /// The calling thread is blocked until there is no writer transaction
+    /// which holds the lock. There may be other readers inside the lock when
+    /// this method returns. It does not need a transaction, and it creates no
+    /// logs. The shared access is released when the returned guard is dropped.
+    ///
+    /// If no thread holds the writer lock, the reader enters by counting
+    /// itself in an atomic word, without taking the writer lock.
+    ///
+    /// # Panics
+    ///
+    /// It panics if the current thread holds a write guard of this lock. If
+    /// waiting for the lock would cause a deadlock with other transactions, it
+    /// panics with [`Error::Deadlock`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use corundum::default::*;
+    ///
+    /// type P = Allocator;
+    ///
+    /// let obj = P::open::<PRwLock<i32>>("foo.pool", O_CF).unwrap();
+    ///
+    /// let r1 = obj.read();
+    /// let r2 = obj.read();
+    /// assert_eq!(*r1, *r2);
+    /// ```
+    ///
+    /// [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+    pub fn read(&self) -> RwLockReadGuard<'_, T, A> {
+        if self.inner.try_enter_read() {
+            return RwLockReadGuard { lock: self };
+        }
+        let entered = unsafe { acquire_lock(self.inner.id()) };
+        self.enter_read(entered);
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Attempts to acquire this lock with shared read access.
+    ///
+    /// If the access could not be granted at this time, then [`Err`] is
+    /// returned. Otherwise, an RAII guard is returned which will release the
+    /// shared access when it is dropped. This function does not block.
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T, A>> {
+        if self.inner.try_enter_read() {
+            Ok(RwLockReadGuard { lock: self })
+        } else if let Some(entered) = unsafe { try_acquire_lock(self.inner.id()) } {
+            self.enter_read(entered);
+            Ok(RwLockReadGuard { lock: self })
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+
+    /// Takes the write guard after acquiring the writer lock, and creates an
+    /// [`UnlockOnCommit`] log to release it when the transaction is done, if
+    /// the lock is `entered`
+    ///
+    /// [`UnlockOnCommit`]: ../stm/enum.LogEnum.html#variant.UnlockOnCommit
+    #[inline]
+    fn enter_write(&self, entered: bool, journal: &Journal<A>) {
+        let inner = &*self.inner;
+        if inner.acquire() {
+            if entered {
+                unsafe { Log::unlock_on_commit(inner.id(), journal); }
+            }
+        } else {
+            if entered {
+                unsafe { release_lock(inner.id()); }
+            }
+            panic!("Cannot have multiple instances of RwLockWriteGuard");
+        }
+    }
+
+    /// Locks this lock with exclusive write access, blocking the current
+    /// thread until it can be acquired.
+    ///
+    /// This function does not return while other writers or readers hold the
+    /// lock. The write access is held until the transaction commits, even
+    /// after the guard is dropped. Further write locking in the same
+    /// transaction does not block.
+    ///
+    /// # Panics
+    ///
+    /// If waiting for the lock or its readers would cause a deadlock with
+    /// other threads, it panics with [`Error::Deadlock`] to abort the current
+    /// transaction. It is also the case if the current thread has a read
+    /// guard of the same lock.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use corundum::default::*;
+    ///
+    /// type P = Allocator;
+    ///
+    /// let obj = P::open::<PRwLock<i32>>("foo.pool", O_CF).unwrap();
+    ///
+    /// P::transaction(|j| {
+    ///     let mut w = obj.write(j);
+    ///     *w = 5;
+    /// }).unwrap();
+    ///
+    /// assert_eq!(*obj.read(), 5);
+    /// ```
+    ///
+    /// [`Error::Deadlock`]: ../enum.Error.html#variant.Deadlock
+    pub fn write<'a>(&'a self, journal: &'a Journal<A>) -> RwLockWriteGuard<'a, T, A> {
+        let entered = unsafe { acquire_lock(self.inner.id()) };
+
+        // New readers cannot enter while the writer lock is held
+        self.inner.wait_readers(entered);
+        self.enter_write(entered, journal);
+        RwLockWriteGuard { lock: self, journal }
+    }
+
+    /// Attempts to lock this lock with exclusive write access.
+    ///
+    /// If the lock could not be acquired at this time, then [`Err`] is
+    /// returned. Otherwise, an RAII guard is returned. The lock will be
+    /// unlocked when the owner transaction ends. This function does not block.
+    pub fn try_write<'a>(&'a self, journal: &'a Journal<A>) -> TryLockResult<RwLockWriteGuard<'a, T, A>> {
+        let id = self.inner.id();
+        if let Some(entered) = unsafe { try_acquire_lock(id) } {
+            if self.inner.has_readers() {
+                if entered {
+                    unsafe { release_lock(id); }
+                }
+                return Err(TryLockError::WouldBlock);
+            }
+            self.enter_write(entered, journal);
+            Ok(RwLockWriteGuard { lock: self, journal })
+        } else {
+            Err(TryLockError::WouldBlock)
+        }
+    }
+}
+
+impl<T: RootObj<A>, A: MemPool> RootObj<A> for PRwLock<T, A> {
+    fn init(journal: &Journal<A>) -> Self {
+        PRwLock::new(T::init(journal))
+    }
+}
+
+unsafe impl<T: Relocate<A>, A: MemPool> Relocate<A> for PRwLock<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        // The lock may have waiters, so it cannot be moved
+        r.pin(self);
+        unsafe { (*self.data.get()).1.visit(r) }
+    }
+}
+
+impl<T: fmt::Debug, A: MemPool> fmt::Debug for PRwLock<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.data.fmt(f)
+    }
+}
+
+/// RAII structure used to release the shared read access of a [`PRwLock`]
+/// when dropped
+///
+/// [`PRwLock`]: ./struct.PRwLock.html
+pub struct RwLockReadGuard<'a, T: 'a, A: MemPool> {
+    lock: &'a PRwLock<T, A>,
+}
+
+impl<T: ?Sized, A: MemPool> !TxOutSafe for RwLockReadGuard<'_, T, A> {}
+impl<T: ?Sized, A: MemPool> !Send for RwLockReadGuard<'_, T, A> {}
+unsafe impl<T: Sync, A: MemPool> Sync for RwLockReadGuard<'_, T, A> {}
+
+impl<T: fmt::Debug, A: MemPool> fmt::Debug for RwLockReadGuard<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display, A: MemPool> fmt::Display for RwLockReadGuard<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T, A: MemPool> Deref for RwLockReadGuard<'_, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &(*self.lock.data.get()).1 }
+    }
+}
+
+impl<T, A: MemPool> Drop for RwLockReadGuard<'_, T, A> {
+    fn drop(&mut self) {
+        self.lock.inner.leave_read();
+    }
+}
+
+/// RAII structure used to keep track of mutable borrowing of the data
+/// protected by a [`PRwLock`]
+///
+/// The write access is released when the transaction commits, not when the
+/// guard is dropped.
+///
+/// [`PRwLock`]: ./struct.PRwLock.html
+pub struct RwLockWriteGuard<'a, T: 'a, A: MemPool> {
+    lock: &'a PRwLock<T, A>,
+    journal: *const Journal<A>,
+}
+
+impl<T: ?Sized, A: MemPool> !TxOutSafe for RwLockWriteGuard<'_, T, A> {}
+impl<T: ?Sized, A: MemPool> !Send for RwLockWriteGuard<'_, T, A> {}
+unsafe impl<T: Sync, A: MemPool> Sync for RwLockWriteGuard<'_, T, A> {}
+
+impl<T: fmt::Debug, A: MemPool> fmt::Debug for RwLockWriteGuard<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display, A: MemPool> fmt::Display for RwLockWriteGuard<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T, A: MemPool> Deref for RwLockWriteGuard<'_, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &(*self.lock.data.get()).1 }
+    }
+}
+
+impl<T: PSafe, A: MemPool> DerefMut for RwLockWriteGuard<'_, T, A> {
+    #[track_caller]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.lock.get_mut(&*self.journal) }
+    }
+}
+
+impl<T, A: MemPool> Drop for RwLockWriteGuard<'_, T, A> {
+    fn drop(&mut self) {
+        self.lock.inner.release()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::default::*;
+    use std::thread;
+
+    type P = Allocator;
+
+    #[test]
+    fn concurrent_readers() {
+        let obj = P::open::<Parc<PRwLock<u64>>>("rwlock.pool", O_CF).unwrap();
+        P::transaction(|j| *obj.write(j) = 1).unwrap();
+
+        let r = obj.read();
+        let other = Parc::demote(&obj);
+        thread::spawn(move || {
+            P::transaction(move |j| {
+                let obj = other.promote(j).unwrap();
+
+                // Another reader can enter, but the writer has to wait
+                assert_eq!(*obj.read(), 1);
+                assert!(obj.try_write(j).is_err());
+            }).unwrap();
+        }).join().unwrap();
+        drop(r);
+
+        P::transaction(|j| {
+            *obj.write(j) += 1;
+            assert_eq!(*obj.write(j), 2);
+        }).unwrap();
+        assert_eq!(*obj.try_read().unwrap(), 2);
+    }
+
+    #[test]
+    fn write_after_read() {
+        let obj = P::open::<PRwLock<u64>>("rwlock_deadlock.pool", O_CF).unwrap();
+
+        // Writing while the same thread reads is a deadlock
+        let r = obj.read();
+        let res = P::transaction(|j| *obj.write(j) = 1);
+        assert!(matches!(res, Err(crate::Error::Deadlock)));
+        drop(r);
+
+        // Writing again after dropping the guard does not unlock it twice
+        P::transaction(|j| {
+            *obj.write(j) = 1;
+            *obj.write(j) += 1;
+        }).unwrap();
+        assert_eq!(*obj.read(), 2);
+        P::transaction(|j| *obj.write(j) += 1).unwrap();
+        assert_eq!(*obj.try_read().unwrap(), 3);
+    }
+}
@@ -7,7 +7,7 @@ use crate::clone::PClone;
 use crate::ptr::*;
 use crate::stm::*;
 use crate::*;
-use std::alloc::Layout;
+use std::alloc::{AllocError, Layout};
 use std::cmp::Ordering;
 use std::fmt::{Debug, Display, Formatter};
 use std::marker::PhantomData;
@@ -525,25 +525,56 @@ impl<T: PSafe, A: MemPool> Vec<T, A> {
     /// ```
     #[inline]
     pub fn reserve(&mut self, additional: usize, j: &Journal<A>) {
+        if self.try_reserve(additional, j).is_err() {
+            panic!("Memory exhausted");
+        }
+    }
+
+    /// Tries to reserve capacity for at least `additional` more elements, and
+    /// returns an [`AllocError`] if the pool is out of space.
+    ///
+    /// On failure, the vector remains unchanged, and the transaction can
+    /// continue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::default::*;
+    /// # type P = Allocator;
+    /// # let _p = P::open_no_root("foo.pool", O_CF).unwrap();
+    /// P::transaction(|j| {
+    ///     let mut vec = PVec::from_slice(&[1, 2, 3], j);
+    ///     assert!(vec.try_reserve(10, j).is_ok());
+    ///     assert!(vec.capacity() >= 13);
+    ///
+    ///     // The pool is not large enough for this request
+    ///     assert!(vec.try_reserve(1 << 40, j).is_err());
+    ///     assert_eq!(vec, [1, 2, 3]);
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`AllocError`]: std::alloc::AllocError
+    pub fn try_reserve(&mut self, additional: usize, j: &Journal<A>) -> Result<(), AllocError> {
         if additional == 0 {
-            return;
+            return Ok(());
         }
 
         let cap = self.buf.capacity();
         let len = self.len;
-        let new_cap = cap.max(len + additional);
-        if get_idx(new_cap * mem::size_of::<T>()) == get_idx(len * mem::size_of::<T>()) {
+        let new_cap = cap.max(len.checked_add(additional).ok_or(AllocError)?);
+        let layout = Layout::array::<T>(new_cap).map_err(|_| AllocError)?;
+        if get_idx(layout.size()) == get_idx(len * mem::size_of::<T>()) {
             self.buf.set_cap(new_cap);
         } else {
             unsafe {
+                let new = A::try_new_uninit_for_layout(layout.size(), j)?.cast();
                 let old = self.to_slice_mut();
-                let layout = Layout::array::<T>(new_cap).unwrap();
-                let new = A::new_uninit_for_layout(layout.size(), j).cast();
                 ptr::copy(old.as_ptr(), new, len);
                 A::free_slice(Self::__to_slice_mut(self.off(), self.capacity()));
                 self.buf = Slice::new(slice::from_raw_parts(new, new_cap));
             }
         }
+        Ok(())
     }
 
     /// Shortens the vector, keeping the first `len` elements and dropping
@@ -892,6 +923,38 @@ impl<T: PSafe, A: MemPool> Vec<T, A> {
         }
     }
 
+    /// Appends an element to the back of a collection, or returns an
+    /// [`AllocError`] if the pool is out of space.
+    ///
+    /// On failure, `value` is dropped, the vector remains unchanged, and the
+    /// transaction can continue.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use corundum::vec::Vec;
+    /// # use corundum::alloc::heap::*;
+    /// Heap::transaction(|j| {
+    ///     let mut vec = Vec::from_slice(&[1, 2], j);
+    ///     vec.try_push(3, j).unwrap();
+    ///     assert_eq!(vec, [1, 2, 3]);
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`AllocError`]: std::alloc::AllocError
+    #[inline]
+    pub fn try_push(&mut self, value: T, j: &Journal<A>) -> Result<(), AllocError> {
+        if self.len == self.buf.capacity() {
+            self.try_reserve(1, j)?;
+        }
+        unsafe {
+            let end = self.buf.as_mut_ptr().add(self.len);
+            ptr::write(end, value);
+            self.len += 1;
+        }
+        Ok(())
+    }
+
     /// Removes the last element from a vector and returns it, or [`None`] if it
     /// is empty.
     ///
@@ -1135,6 +1198,16 @@ impl<T: PSafe, A: MemPool> RootObj<A> for Vec<T, A> {
     }
 }
 
+unsafe impl<T: PSafe + Relocate<A>, A: MemPool> Relocate<A> for Vec<T, A> {
+    fn visit(&self, r: &mut Relocator<A>) {
+        if r.visit(&self.buf.off, self.buf.cap * mem::size_of::<T>()) {
+            for x in self.as_slice() {
+                x.visit(r)
+            }
+        }
+    }
+}
+
 // Consuming iterator
 
 /// structure helper for consuming iterator.